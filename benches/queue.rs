@@ -0,0 +1,63 @@
+//! pushes and pops a million values through both queues, run with `cargo bench --bench queue`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rget::structures::{Queue, VecQueue};
+
+const VALUES: usize = 1_000_000;
+const RUNS: u32 = 5;
+
+/// the mean time of `RUNS` runs of `f`
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    let mean = start.elapsed() / RUNS;
+    println!("{name:<24} {mean:>12.2?}");
+    mean
+}
+
+fn main() {
+    let linked = bench("Queue push/pop 1M", || {
+        let mut q = Queue::default();
+        for n in 0..VALUES {
+            q.push(black_box(n));
+        }
+        while let Some(n) = q.pop() {
+            black_box(n);
+        }
+    });
+    let ring = bench("VecQueue push/pop 1M", || {
+        let mut q = VecQueue::default();
+        for n in 0..VALUES {
+            q.push(black_box(n));
+        }
+        while let Some(n) = q.pop() {
+            black_box(n);
+        }
+    });
+    // a crawl level pops the frontier while it pushes the next one
+    bench("Queue interleaved 1M", || {
+        let mut q = Queue::default();
+        q.push(0);
+        for n in 1..VALUES {
+            q.push(black_box(n));
+            black_box(q.pop());
+        }
+    });
+    bench("VecQueue interleaved 1M", || {
+        let mut q = VecQueue::default();
+        q.push(0);
+        for n in 1..VALUES {
+            q.push(black_box(n));
+            black_box(q.pop());
+        }
+    });
+    println!(
+        "VecQueue is {:.1}x faster",
+        linked.as_secs_f64() / ring.as_secs_f64()
+    );
+}
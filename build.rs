@@ -0,0 +1,32 @@
+use std::fs;
+use std::process::Command;
+
+/// records build information that `rget version` prints
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(head) = fs::read_to_string(".git/HEAD").ok()
+        && let Some(branch) = head.trim().strip_prefix("ref: ")
+    {
+        println!("cargo:rerun-if-changed=.git/{branch}");
+    }
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=RGET_GIT_COMMIT={}", commit.trim());
+    }
+
+    // the lock file knows which reqwest version was resolved
+    let reqwest = fs::read_to_string("Cargo.lock").ok().and_then(|lock| {
+        let (_, rest) = lock.split_once("name = \"reqwest\"\nversion = \"")?;
+        rest.split_once('"').map(|(version, _)| version.to_string())
+    });
+    if let Some(reqwest) = reqwest {
+        println!("cargo:rustc-env=RGET_REQWEST_VERSION={reqwest}");
+    }
+}
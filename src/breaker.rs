@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use indicatif::MultiProgress;
+use rand::Rng;
+use rand::rngs::OsRng;
+use reqwest::{StatusCode, Url};
+
+/// how many server errors in a row pause a host
+const TRIP_AFTER: u32 = 3;
+/// the pause after the first trip, it doubles with every trip in a row
+const COOLDOWN: Duration = Duration::from_secs(5);
+/// the longest pause of a host
+const MAX_COOLDOWN: Duration = Duration::from_secs(120);
+/// the time between two requests to a host right after its pause, it halves with every success
+const RECOVERY_SPACING: Duration = Duration::from_secs(1);
+/// a host is back to full speed once the spacing gets shorter than this
+const MIN_SPACING: Duration = Duration::from_millis(50);
+
+/// how the requests to one host are let through
+#[derive(Debug, Default)]
+struct Host {
+    /// server errors in a row
+    failures: u32,
+    /// pauses since the host was last at full speed
+    trips: u32,
+    /// when the next request may start
+    next: Option<Instant>,
+    /// the time between the starts of two requests while the host recovers
+    spacing: Duration,
+}
+
+/// circuit breakers for the hosts of a run, shared by all its requests
+///
+/// a host that answers a few server errors in a row gets no requests for a jittered
+/// cooldown, so the requests waiting for it do not all retry at once. After that they start one
+/// after the other, closer together with every success until the host is at full speed again
+#[derive(Debug, Default)]
+pub struct HostBreakers {
+    hosts: Mutex<HashMap<String, Host>>,
+    /// the progress bars of the run, hidden while a paused host is reported
+    progress: MultiProgress,
+}
+
+impl HostBreakers {
+    /// breakers that report a paused host above the bars of `progress`
+    pub fn new(progress: MultiProgress) -> Self {
+        HostBreakers {
+            hosts: Mutex::default(),
+            progress,
+        }
+    }
+
+    /// waits until a request to the host of `url` may start
+    pub(crate) async fn wait(&self, url: &Url) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let start = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let Some(state) = hosts.get_mut(host) else {
+                return;
+            };
+            let Some(next) = state.next else {
+                return;
+            };
+            let start = next.max(Instant::now());
+            state.next = (!state.spacing.is_zero()).then(|| start + state.spacing);
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+
+    /// records the `status` a request to the host of `url` was answered with
+    pub(crate) fn record(&self, url: &Url, status: StatusCode) {
+        self.record_at(url, status, Instant::now());
+    }
+
+    fn record_at(&self, url: &Url, status: StatusCode, now: Instant) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let mut hosts = self.hosts.lock().unwrap();
+        if !status.is_server_error() {
+            // a host that never failed is not tracked at all
+            if let Some(state) = hosts.get_mut(host) {
+                state.failures = 0;
+                state.spacing /= 2;
+                if state.spacing < MIN_SPACING {
+                    hosts.remove(host);
+                }
+            }
+            return;
+        }
+        let state = hosts.entry(host.to_string()).or_default();
+        state.failures += 1;
+        if state.failures < TRIP_AFTER {
+            return;
+        }
+        let cooldown = COOLDOWN
+            .saturating_mul(1 << state.trips.min(16))
+            .min(MAX_COOLDOWN)
+            .mul_f64(OsRng.gen_range(0.5..1.5));
+        state.failures = 0;
+        state.trips += 1;
+        state.next = Some(now + cooldown);
+        state.spacing = RECOVERY_SPACING;
+        drop(hosts);
+        self.progress.suspend(|| {
+            eprintln!(
+                "{host} answered {TRIP_AFTER} server errors in a row, pausing requests to it for {}s",
+                cooldown.as_secs()
+            )
+        });
+    }
+
+    /// when the next request to `host` may start, none if it may start right away
+    #[cfg(test)]
+    fn next(&self, host: &str) -> Option<Instant> {
+        self.hosts.lock().unwrap().get(host)?.next
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use reqwest::{StatusCode, Url};
+
+    use super::{COOLDOWN, HostBreakers};
+
+    #[tokio::test]
+    async fn test_host_breakers() {
+        let breakers = HostBreakers::default();
+        let url = Url::parse("http://a/page").unwrap();
+        let other = Url::parse("http://b/page").unwrap();
+        let now = Instant::now();
+        breakers.record_at(&url, StatusCode::SERVICE_UNAVAILABLE, now);
+        breakers.record_at(&url, StatusCode::BAD_GATEWAY, now);
+        assert_eq!(breakers.next("a"), None);
+        // a success in between starts the count again
+        breakers.record_at(&url, StatusCode::OK, now);
+        breakers.record_at(&url, StatusCode::SERVICE_UNAVAILABLE, now);
+        breakers.record_at(&url, StatusCode::SERVICE_UNAVAILABLE, now);
+        assert_eq!(breakers.next("a"), None);
+        breakers.record_at(&url, StatusCode::SERVICE_UNAVAILABLE, now);
+        let until = breakers.next("a").unwrap() - now;
+        assert!(
+            until >= COOLDOWN / 2 && until <= COOLDOWN * 3 / 2,
+            "{until:?}"
+        );
+
+        // the second trip in a row pauses longer
+        for _ in 0..3 {
+            breakers.record_at(&url, StatusCode::INTERNAL_SERVER_ERROR, now);
+        }
+        let until = breakers.next("a").unwrap() - now;
+        assert!(until >= COOLDOWN, "{until:?}");
+        assert_eq!(breakers.next("b"), None);
+        tokio::time::timeout(Duration::from_millis(100), breakers.wait(&other))
+            .await
+            .unwrap();
+
+        // every success halves the spacing until the host is forgotten
+        let mut successes = 0;
+        while breakers.next("a").is_some() {
+            breakers.record_at(&url, StatusCode::OK, now);
+            successes += 1;
+        }
+        assert_eq!(successes, 5);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::download::FileMeta;
+use crate::error::RgetError;
+use crate::urls::url_hash;
+
+/// a crawled page as it is kept in the cache, the body is stored next to it
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CachedPage {
+    /// the url and the validators the server sent with the page
+    pub(crate) meta: FileMeta,
+    /// where redirects ended, the links of the page are relative to it
+    pub(crate) base: String,
+    /// the Content-Type header of the page
+    pub(crate) content_type: Option<String>,
+}
+
+/// the pages of earlier crawls, a crawl asks the server whether they changed instead of
+/// downloading them again
+///
+/// every page is kept as `<hash>.json` with its validators and `<hash>.body` with its content,
+/// pages without an ETag or Last-Modified cannot be revalidated and are not kept
+#[derive(Debug)]
+pub(crate) struct PageCache {
+    dir: PathBuf,
+}
+
+impl PageCache {
+    pub(crate) fn new(dir: &Path) -> Result<Self, RgetError> {
+        fs::create_dir_all(dir)?;
+        Ok(PageCache {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let hash = url_hash(url);
+        (
+            self.dir.join(format!("{hash}.json")),
+            self.dir.join(format!("{hash}.body")),
+        )
+    }
+
+    /// the cached page of `url` with its body, none when it was not cached
+    pub(crate) fn load(&self, url: &str) -> Option<(CachedPage, Vec<u8>)> {
+        let (meta, body) = self.paths(url);
+        let file = File::open(meta).ok()?;
+        let page: CachedPage = serde_json::from_reader(BufReader::new(file)).ok()?;
+        if page.meta.url != url {
+            return None;
+        }
+        Some((page, fs::read(body).ok()?))
+    }
+
+    /// keeps `page` with its `body`, the body is written first so a page is never loaded with
+    /// half of it
+    pub(crate) fn store(&self, page: &CachedPage, body: &[u8]) -> Result<(), RgetError> {
+        let (meta, body_path) = self.paths(&page.meta.url);
+        fs::write(&body_path, body)?;
+        let mut file = BufWriter::new(File::create(meta)?);
+        serde_json::to_writer(&mut file, page)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use http::HeaderMap;
+    use http::header::ETAG;
+
+    use super::{CachedPage, PageCache};
+    use crate::download::FileMeta;
+
+    #[test]
+    fn test_page_cache() {
+        let dir = std::env::temp_dir().join(format!("rget-cache-{}", std::process::id()));
+        let cache = PageCache::new(&dir).unwrap();
+        let url = "http://localhost/docs/";
+        assert!(cache.load(url).is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, "\"v1\"".parse().unwrap());
+        let page = CachedPage {
+            meta: FileMeta::from_headers(url, &headers).unwrap(),
+            base: "http://localhost/docs/index.html".to_string(),
+            content_type: Some("text/html".to_string()),
+        };
+        cache.store(&page, b"<a href=\"a\">a</a>").unwrap();
+        let (loaded, body) = cache.load(url).unwrap();
+        assert_eq!(loaded, page);
+        assert_eq!(body, b"<a href=\"a\">a</a>");
+        assert!(cache.load("http://localhost/other").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::CommandFactory;
+use clap::parser::ValueSource;
+use rget::error::RgetError;
+use toml::{Table, Value};
+
+/// the config file, `$RGET_CONFIG` or `rget/config.toml` in `$XDG_CONFIG_HOME` or `~/.config`
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("RGET_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("rget").join("config.toml"))
+}
+
+/// reads the config file, a missing file is an empty config
+pub fn load(path: &Path) -> Result<Table, RgetError> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(source) => {
+            return Err(RgetError::ReadFile {
+                path: path.to_path_buf(),
+                source,
+            });
+        }
+    };
+    content
+        .parse()
+        .map_err(|e| RgetError::Config(format!("invalid config file {}: {e}", path.display())))
+}
+
+/// adds the values of `config` to the command line `argv` for every flag of the chosen subcommand
+/// that is not given on the command line, so the command line always wins over the config and the
+/// config over the defaults
+///
+/// the keys of the config are the long flag names, e.g. `user-agent = "..."`, `no-clobber = true`
+/// or `cookie = ["a=b", "c=d"]`. Keys of flags the subcommand does not have are ignored
+pub fn with_config<P: CommandFactory>(
+    argv: Vec<OsString>,
+    config: &Table,
+) -> Result<Vec<OsString>, RgetError> {
+    let mut command = P::command();
+    // errors, --help and --version are reported by the real parse
+    let Ok(matches) = command.try_get_matches_from_mut(&argv) else {
+        return Ok(argv);
+    };
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(argv);
+    };
+    // building propagates the global flags into the subcommands
+    command.build();
+    let sub = command
+        .find_subcommand(name)
+        .expect("the subcommand was just parsed");
+
+    let given = |id: &str| sub_matches.value_source(id) == Some(ValueSource::CommandLine);
+    let mut extra = Vec::new();
+    for (key, value) in config {
+        let Some(arg) = sub.get_arguments().find(|a| a.get_long() == Some(key)) else {
+            let known = command
+                .get_subcommands()
+                .any(|c| c.get_arguments().any(|a| a.get_long() == Some(key)));
+            if !known {
+                eprintln!("warning: unknown key {key} in the config file");
+            }
+            continue;
+        };
+        let conflicts = sub.get_arg_conflicts_with(arg);
+        if given(arg.get_id().as_str()) || conflicts.iter().any(|a| given(a.get_id().as_str())) {
+            continue;
+        }
+
+        match value {
+            Value::Boolean(true) => extra.push(format!("--{key}")),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    extra.push(format!("--{key}={}", scalar(key, value)?));
+                }
+            }
+            value => extra.push(format!("--{key}={}", scalar(key, value)?)),
+        }
+    }
+
+    Ok(argv
+        .into_iter()
+        .chain(extra.into_iter().map(OsString::from))
+        .collect())
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, RgetError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(RgetError::Config(format!(
+            "the config value of {key} has to be a string, number or boolean"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsString;
+
+    use clap::Parser;
+    use toml::Table;
+
+    use super::with_config;
+    use crate::{Args, SubCom};
+
+    fn parse(argv: &[&str], config: &str) -> Args {
+        let argv = argv.iter().map(OsString::from).collect();
+        let config: Table = config.parse().unwrap();
+        Args::parse_from(with_config::<Args>(argv, &config).unwrap())
+    }
+
+    #[test]
+    fn test_with_config() {
+        let config = r#"
+            user-agent = "from-config"
+            no-clobber = true
+            cookie = ["a=b", "c=d"]
+            depth = 3
+            parallel = 2
+        "#;
+
+        let args = parse(&["rget", "get", "http://localhost/"], config);
+        assert_eq!(args.client.user_agent, "from-config");
+        assert_eq!(args.client.cookie, ["a=b", "c=d"]);
+        let SubCom::Get {
+            parallel, download, ..
+        } = args.subs
+        else {
+            panic!("not a get")
+        };
+        assert_eq!(parallel.get(), 2);
+        assert!(download.no_clobber);
+
+        // the command line wins, also over a config flag it conflicts with
+        let args = parse(
+            &[
+                "rget",
+                "get",
+                "--user-agent",
+                "cli",
+                "--force",
+                "http://localhost/",
+            ],
+            config,
+        );
+        assert_eq!(args.client.user_agent, "cli");
+        let SubCom::Get { download, .. } = args.subs else {
+            panic!("not a get")
+        };
+        assert!(download.force);
+        assert!(!download.no_clobber);
+
+        let args = parse(&["rget", "get-depth", "http://localhost/"], config);
+        let SubCom::GetDepth { depth, .. } = args.subs else {
+            panic!("not a get-depth")
+        };
+        assert_eq!(depth, 3);
+    }
+}
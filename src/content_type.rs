@@ -0,0 +1,279 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TextType {
+    Plain,
+    Html,
+    Css,
+    Javascript,
+    Xml,
+    Markdown,
+    Csv,
+    Richtext,
+    TabSeparatedValues,
+}
+
+impl TextType {
+    /// the mime type of the text format, e.g. `text/html`
+    pub fn to_mime_str(&self) -> &'static str {
+        match self {
+            TextType::Plain => "text/plain",
+            TextType::Html => "text/html",
+            TextType::Css => "text/css",
+            TextType::Javascript => "text/javascript",
+            TextType::Xml => "text/xml",
+            TextType::Markdown => "text/markdown",
+            TextType::Csv => "text/csv",
+            TextType::Richtext => "text/richtext",
+            TextType::TabSeparatedValues => "text/tab-separated-values",
+        }
+    }
+
+    /// the usual file extension of the text format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TextType::Plain => "txt",
+            TextType::Html => "html",
+            TextType::Css => "css",
+            TextType::Javascript => "js",
+            TextType::Xml => "xml",
+            TextType::Markdown => "md",
+            TextType::Csv => "csv",
+            TextType::Richtext => "rtx",
+            TextType::TabSeparatedValues => "tsv",
+        }
+    }
+}
+
+impl fmt::Display for TextType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_mime_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum ContentType {
+    Text(TextType), // For specific text formats
+    Other(String),  // For any other content type, storing the string value
+    Unknown,        // For cases where the header is missing or invalid
+}
+
+impl ContentType {
+    /// the mime type as it can be sent in a Content-Type header, a missing type is sent as
+    /// `application/octet-stream` like the http spec suggests
+    pub fn to_mime_str(&self) -> &str {
+        match self {
+            ContentType::Text(text) => text.to_mime_str(),
+            ContentType::Other(other) => other,
+            ContentType::Unknown => "application/octet-stream",
+        }
+    }
+
+    /// the usual file extension of the content type without the dot, none for unknown and
+    /// generic binary types like `application/octet-stream`
+    pub fn extension(&self) -> Option<&'static str> {
+        let other = match self {
+            ContentType::Text(text) => return Some(text.extension()),
+            ContentType::Other(other) => other,
+            ContentType::Unknown => return None,
+        };
+        let essence = other.split(';').next().unwrap_or_default().trim();
+        let ext = match essence.to_ascii_lowercase().as_str() {
+            "application/json" => "json",
+            "application/xml" => "xml",
+            "application/xhtml+xml" => "xhtml",
+            "application/rss+xml" => "rss",
+            "application/atom+xml" => "atom",
+            "application/javascript" => "js",
+            "application/pdf" => "pdf",
+            "application/zip" => "zip",
+            "application/gzip" | "application/x-gzip" => "gz",
+            "application/x-tar" => "tar",
+            "application/wasm" => "wasm",
+            "image/png" => "png",
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/avif" => "avif",
+            "image/svg+xml" => "svg",
+            "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+            "audio/mpeg" => "mp3",
+            "audio/ogg" => "ogg",
+            "video/mp4" => "mp4",
+            "video/webm" => "webm",
+            "font/woff" => "woff",
+            "font/woff2" => "woff2",
+            _ => return None,
+        };
+        Some(ext)
+    }
+
+    /// one of the text formats whose pages get crawled for links
+    pub fn is_text(&self) -> bool {
+        matches!(self, ContentType::Text(_))
+    }
+
+    /// any known content type that is not text
+    pub fn is_binary(&self) -> bool {
+        matches!(self, ContentType::Other(other) if !other.starts_with("text/"))
+    }
+
+    /// the `charset` parameter of a Content-Type header value, e.g. `utf-8` for
+    /// `text/html; charset="UTF-8"`
+    pub fn charset(ct_value: Option<&http::HeaderValue>) -> Option<String> {
+        let value = ct_value?.to_str().ok()?;
+        value.split(';').skip(1).find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+        })
+    }
+
+    /// the content type of a Content-Type header value, missing headers and values that are not
+    /// valid utf-8 are unknown
+    pub fn from_header_value(ct_value: Option<&http::HeaderValue>) -> Self {
+        match ct_value.map(|value| value.to_str()) {
+            Some(Ok(mime)) => ContentType::from_mime_str(mime),
+            Some(Err(_)) | None => ContentType::Unknown,
+        }
+    }
+
+    /// the content type of a mime type like `text/html; charset=utf-8`, parameters are ignored
+    /// and the type is matched case insensitively
+    pub fn from_mime_str(mime: &str) -> Self {
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+        let text = match essence.to_ascii_lowercase().as_str() {
+            "" => return ContentType::Unknown,
+            "text/plain" => TextType::Plain,
+            "text/html" => TextType::Html,
+            "text/css" => TextType::Css,
+            "text/javascript" => TextType::Javascript,
+            "text/xml" => TextType::Xml,
+            "text/markdown" => TextType::Markdown,
+            "text/csv" => TextType::Csv,
+            "text/richtext" => TextType::Richtext,
+            "text/tab-separated-values" => TextType::TabSeparatedValues,
+            _ => return ContentType::Other(mime.to_string()),
+        };
+        ContentType::Text(text)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_mime_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::HeaderValue;
+
+    use super::{ContentType, TextType};
+
+    #[test]
+    fn test_charset() {
+        let charset = |value| ContentType::charset(Some(&HeaderValue::from_static(value)));
+        assert_eq!(charset("text/html"), None);
+        assert_eq!(
+            charset("text/html; charset=utf-8"),
+            Some("utf-8".to_string())
+        );
+        assert_eq!(
+            charset("text/html;Charset=\"ISO-8859-1\""),
+            Some("iso-8859-1".to_string())
+        );
+        assert_eq!(ContentType::charset(None), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(ContentType::Text(TextType::Html).to_string(), "text/html");
+        assert_eq!(
+            TextType::TabSeparatedValues.to_string(),
+            "text/tab-separated-values"
+        );
+        assert_eq!(
+            ContentType::Other("application/json".to_string()).to_string(),
+            "application/json"
+        );
+        assert_eq!(
+            ContentType::Unknown.to_mime_str(),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn test_from_mime_str() {
+        let texts = [
+            TextType::Plain,
+            TextType::Html,
+            TextType::Css,
+            TextType::Javascript,
+            TextType::Xml,
+            TextType::Markdown,
+            TextType::Csv,
+            TextType::Richtext,
+            TextType::TabSeparatedValues,
+        ];
+        for text in texts {
+            let mime = text.to_mime_str();
+            assert_eq!(
+                ContentType::from_mime_str(mime).to_mime_str(),
+                mime,
+                "{mime} does not round-trip"
+            );
+        }
+
+        let html = ContentType::from_mime_str("Text/HTML; charset=utf-8");
+        assert!(matches!(html, ContentType::Text(TextType::Html)));
+        assert!(matches!(
+            ContentType::from_mime_str(" text/csv ;header=present"),
+            ContentType::Text(TextType::Csv)
+        ));
+        // only whole mime types match
+        assert!(matches!(
+            ContentType::from_mime_str("text/htmlx"),
+            ContentType::Other(other) if other == "text/htmlx"
+        ));
+        assert!(matches!(
+            ContentType::from_mime_str("application/pdf; name=a.pdf"),
+            ContentType::Other(other) if other == "application/pdf; name=a.pdf"
+        ));
+        assert!(matches!(
+            ContentType::from_mime_str(""),
+            ContentType::Unknown
+        ));
+    }
+
+    #[test]
+    fn test_extension() {
+        let extension = |mime| ContentType::from_mime_str(mime).extension();
+        assert_eq!(extension("text/html; charset=utf-8"), Some("html"));
+        assert_eq!(extension("application/json"), Some("json"));
+        assert_eq!(extension("Image/PNG"), Some("png"));
+        assert_eq!(extension("image/jpeg"), Some("jpg"));
+        assert_eq!(extension("application/octet-stream"), None);
+        assert_eq!(extension("text/x-unknown"), None);
+        assert_eq!(ContentType::Unknown.extension(), None);
+    }
+
+    #[test]
+    fn test_from_header_value() {
+        let header = HeaderValue::from_static("text/plain");
+        assert!(matches!(
+            ContentType::from_header_value(Some(&header)),
+            ContentType::Text(TextType::Plain)
+        ));
+        let invalid = HeaderValue::from_bytes(b"text/\xff").unwrap();
+        assert!(matches!(
+            ContentType::from_header_value(Some(&invalid)),
+            ContentType::Unknown
+        ));
+        assert!(matches!(
+            ContentType::from_header_value(None),
+            ContentType::Unknown
+        ));
+    }
+}
@@ -0,0 +1,1722 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use encoding_rs::{Encoding, UTF_8};
+use futures::{StreamExt, stream};
+use http::header::CONTENT_TYPE;
+use http::{HeaderValue, StatusCode};
+use indicatif::HumanBytes;
+use rand::Rng;
+use rand::rngs::OsRng;
+use reqwest::Response;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+use crate::cache::{CachedPage, PageCache};
+use crate::client::HttpClient;
+use crate::content_type::ContentType;
+use crate::data_url::{self, is_data_url};
+use crate::download::{
+    DownloadOptions, FileMeta, Quota, create_parent_dir, download, head_name,
+    header_content_length, name_head, report_downloads, unique_name,
+};
+use crate::error::RgetError;
+use crate::filter::UrlFilter;
+use crate::structures::{Tree, TreeNode, TreeNodeRef, VecQueue};
+use crate::urls::{derived_file_name, hash_file_name, is_below, normalize_url, tree_path_from_url};
+
+/// how many pages of one crawl level are fetched at the same time
+pub(crate) const CRAWL_CONCURRENCY: usize = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct CrawlOptions {
+    /// where the crawl state is checkpointed
+    pub state_file: Option<PathBuf>,
+    /// load the crawl state from `state_file` instead of starting from scratch
+    pub resume: bool,
+    /// how the downloaded files are named
+    pub layout: Layout,
+    /// the time between the starts of two page fetches
+    pub wait: Option<Duration>,
+    /// wait between 0.5 and 1.5 times `wait` instead
+    pub random_wait: bool,
+    /// how deep the crawl goes into other hosts than the one of the root, the first page of a
+    /// host is depth 1
+    pub max_depth_per_host: Option<usize>,
+    /// look for links in every page whatever its Content-Type, for servers that mislabel html
+    pub force_html: bool,
+    /// the content type of the root page instead of the one the server sends
+    pub content_type_override: Option<String>,
+    /// when the crawl and its downloads stop, whatever is still running is cancelled
+    pub deadline: Option<Instant>,
+    /// only download this many of the largest files found by the crawl
+    pub largest_only: Option<usize>,
+    /// how many pages or files of the same host are fetched at the same time at most
+    pub max_connections_per_host: Option<usize>,
+    /// the links that are followed, the root is always fetched
+    pub url_filter: UrlFilter,
+    /// only follow links into the directory of the root and below it
+    pub no_parent: bool,
+    /// keep the crawled pages here and only revalidate them on the next crawl
+    pub cache_dir: Option<PathBuf>,
+    /// how many bytes the downloads of the crawl may write together
+    pub quota: Option<u64>,
+}
+
+/// spaces out the page fetches of a crawl, fetches that run concurrently still start one after
+/// the other
+#[derive(Debug)]
+struct Pacer {
+    wait: Duration,
+    random: bool,
+    /// when the next fetch may start
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    fn new(wait: Duration, random: bool) -> Self {
+        Pacer {
+            wait,
+            random,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// waits for the turn of the next fetch
+    async fn wait(&self) {
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = next.map_or_else(Instant::now, |next| next.max(Instant::now()));
+            let wait = if self.random {
+                self.wait.mul_f64(OsRng.gen_range(0.5..1.5))
+            } else {
+                self.wait
+            };
+            *next = Some(start + wait);
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+}
+
+/// limits how many requests of a crawl go to the same host at once, however many run in total
+#[derive(Debug)]
+struct HostLimits {
+    max: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl HostLimits {
+    fn new(max: usize) -> Self {
+        HostLimits {
+            max,
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// waits until another request to the host of `url` is allowed, the request counts until the
+    /// permit is dropped. Urls without a host are not limited
+    async fn acquire(&self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let host = host_of(url)?;
+        let semaphore = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max)))
+            .clone();
+        // the semaphore is never closed
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+/// how [`download_depth`] maps the urls of a crawl to files in the current directory
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// a hash of the url and its extension, see [`hash_file_name`]
+    #[default]
+    Hashed,
+    /// the last segment of the url path, `index.html` for a path ending in `/`
+    ///
+    /// the query string is dropped, so `page?a=1` and `page?a=2` are saved as `page` and
+    /// `page.1`. Every other repeated name gets a `.<n>` suffix as well
+    Flat,
+    /// the directories of the site below a directory per host, see [`tree_path_from_url`]
+    ///
+    /// a path ending in `/` is saved as `index.html` in that directory and the query string
+    /// stays part of the file name as `page?a=1`
+    Tree,
+}
+
+/// the name of the `index`th url of a crawl in the `layout`, the output template of the options
+/// takes the place of the layout
+fn crawl_name(url: &str, index: usize, layout: Layout, options: &DownloadOptions) -> String {
+    match (&options.output_template, layout) {
+        (Some(template), _) => template.file_name(url, index),
+        (None, Layout::Hashed) => hash_file_name(url),
+        (None, Layout::Flat) => derived_file_name(url, options.decode_query),
+        (None, Layout::Tree) => tree_path_from_url(url),
+    }
+}
+
+/// the HEAD responses the names of `urls` depend on, see [`name_head`]
+async fn crawl_heads(
+    client: &HttpClient,
+    urls: &[String],
+    layout: Layout,
+    options: &DownloadOptions,
+) -> Vec<Option<Response>> {
+    let heads = urls.iter().enumerate().map(|(i, url)| async move {
+        let name = crawl_name(url, i + 1, layout, options);
+        name_head(client, url, &name, options).await
+    });
+    stream::iter(heads)
+        .buffered(CRAWL_CONCURRENCY)
+        .collect()
+        .await
+}
+
+/// the outfile of every url of a crawl, they get their extension from the `heads` of the urls
+/// first, see [`head_name`]. Names that would repeat get a `.<n>` suffix, so do files named like
+/// the directory of another file, e.g. `/docs` next to `/docs/page`. Compressed files get a `.gz`
+fn crawl_outfiles(
+    urls: &[String],
+    layout: Layout,
+    options: &DownloadOptions,
+    heads: &[Option<Response>],
+) -> Vec<String> {
+    let names: Vec<String> = urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let name = crawl_name(url, i + 1, layout, options);
+            head_name(&name, url, heads.get(i).and_then(Option::as_ref), options)
+        })
+        .collect();
+    let mut used: HashSet<String> = names
+        .iter()
+        .flat_map(|name| Path::new(name).ancestors().skip(1))
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let name = unique_name(name, &mut used);
+            match options.compress {
+                true => format!("{name}.gz"),
+                false => name,
+            }
+        })
+        .collect()
+}
+
+/// everything needed to continue an interrupted crawl
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CrawlState {
+    /// the url the crawl started at
+    root: String,
+    /// depth of the tree crawled so far
+    depth: usize,
+    visited: HashSet<String>,
+    /// the new links found on every crawled page, this is enough to rebuild the tree because every
+    /// url is only added once
+    children: HashMap<String, Vec<String>>,
+    /// urls of the level that is crawled next
+    pending: VecQueue<String>,
+    /// the pages crawled so far, a new crawl with the same state file only parses the ones that
+    /// changed
+    #[serde(default)]
+    pages: HashMap<String, SeenPage>,
+}
+
+/// a crawled page, a later crawl takes its links from here as long as the page does not change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SeenPage {
+    /// a hash of the page and the url its links are relative to
+    hash: String,
+    /// every link on the page, the ones that were not followed as well
+    links: Vec<String>,
+}
+
+impl SeenPage {
+    /// the links of the page at `url` with the `body` and Content-Type `header`, they are only
+    /// parsed if the page changed since it was `previous`ly crawled
+    fn new(
+        url: &str,
+        base: &Url,
+        header: Option<&HeaderValue>,
+        body: &[u8],
+        previous: Option<&SeenPage>,
+    ) -> Self {
+        let digest = Sha256::new()
+            .chain_update(base.as_str())
+            .chain_update([0])
+            .chain_update(body)
+            .finalize();
+        let hash: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+        let links = match previous {
+            Some(page) if page.hash == hash => page.links.clone(),
+            _ => page_links(url, base, header, body),
+        };
+        SeenPage { hash, links }
+    }
+}
+
+impl CrawlState {
+    fn new(root: String) -> Self {
+        let mut pending = VecQueue::default();
+        pending.push(root.clone());
+        CrawlState {
+            visited: HashSet::from([root.clone()]),
+            root,
+            depth: 1,
+            pending,
+            ..CrawlState::default()
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self, RgetError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// writes the state next to `path` first so an interrupt never leaves a broken state file
+    fn save(&self, path: &Path) -> Result<(), RgetError> {
+        let tmp = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// rebuilds the crawled tree and returns it with the queue of pending nodes, the pending urls
+    /// are moved out of the state
+    fn tree(&mut self) -> (Tree<String>, VecQueue<TreeNodeRef<String>>) {
+        let mut url_tree = Tree::new(TreeNode::new(self.root.clone()));
+        url_tree.depth = self.depth;
+
+        let mut nodes = HashMap::from([(self.root.clone(), url_tree.root.clone())]);
+        let mut q = VecQueue::default();
+        q.push(url_tree.root.clone());
+        while let Some(parent) = q.pop() {
+            let url = parent.borrow().value.clone();
+            for child in self.children.get(&url).into_iter().flatten() {
+                let node = Rc::new(RefCell::new(TreeNode::new(child.clone())));
+                nodes.insert(child.clone(), node.clone());
+                q.push(node.clone());
+                Tree::push_node(parent.clone(), node);
+            }
+        }
+
+        let mut pending = std::mem::take(&mut self.pending);
+        while let Some(url) = pending.pop() {
+            if let Some(node) = nodes.get(&url) {
+                q.push(node.clone());
+            }
+        }
+        (url_tree, q)
+    }
+}
+
+/// what fetching a page of the crawl found
+#[derive(Debug, Default)]
+struct Fetched {
+    /// the links on the page
+    links: Vec<String>,
+    /// where the redirects of the page ended, none when it was not redirected
+    location: Option<String>,
+    /// the page the links were found on, none if it is no page
+    page: Option<SeenPage>,
+}
+
+/// fetches a page of the crawl and returns the links found on it, the page is taken to be of the
+/// `mime` type instead of its Content-Type if one is given
+///
+/// with a `cache` a page crawled before is only revalidated and its cached copy used when the
+/// server answers 304. The links of a page that did not change since the `previous` crawl are
+/// taken from there
+async fn fetch_links(
+    client: &HttpClient,
+    url: String,
+    mime: Option<&str>,
+    cache: Option<&PageCache>,
+    previous: Option<&SeenPage>,
+) -> Result<Fetched, reqwest::Error> {
+    let cached = cache.and_then(|cache| cache.load(&url));
+    // a HEAD is enough to tell whether the page can contain links at all, this saves downloading
+    // large binaries just to throw them away. When the server rejects HEAD or does not tell the
+    // content type the GET below decides. A cached page was a page the last time
+    if mime.is_none()
+        && cached.is_none()
+        && let Ok(head) = client
+            .send(client.head(&url))
+            .await
+            .and_then(|r| r.error_for_status())
+    {
+        let content_type = ContentType::from_header_value(head.headers().get(CONTENT_TYPE));
+        if let ContentType::Other(string) = content_type {
+            let size = header_content_length(head.headers())
+                .map(|l| HumanBytes(l).to_string())
+                .unwrap_or_else(|| "unknown size".to_string());
+            client.suspend(|| {
+                println!("other content type: {string} ({size}) not following links of {url}")
+            });
+            return Ok(Fetched::default());
+        }
+    }
+
+    let mut request = client.get(&url);
+    if let Some((page, _)) = &cached {
+        request = page.meta.conditional(request);
+    }
+    let res = client.send(request).await?.error_for_status()?;
+    if let Some((page, body)) = cached
+        && res.status() == StatusCode::NOT_MODIFIED
+    {
+        let Ok(base) = Url::parse(&page.base) else {
+            return Ok(Fetched::default());
+        };
+        let header = page
+            .content_type
+            .and_then(|ct| HeaderValue::from_str(&ct).ok());
+        let page = SeenPage::new(&url, &base, header.as_ref(), &body, previous);
+        return Ok(Fetched {
+            links: page.links.clone(),
+            location: location(&url, &base),
+            page: Some(page),
+        });
+    }
+    let location = location(&url, res.url());
+    let content_type = match mime {
+        Some(mime) => ContentType::from_mime_str(mime),
+        None => ContentType::from_header_value(res.headers().get(CONTENT_TYPE)),
+    };
+    match content_type {
+        ContentType::Text(_) => {
+            // relative links are relative to where a redirect ended up
+            let base = res.url().clone();
+            let header = res.headers().get(CONTENT_TYPE).cloned();
+            let meta = FileMeta::from_headers(&url, res.headers());
+            let body = res.bytes().await?;
+            if let (Some(cache), Some(meta)) = (cache, meta) {
+                let page = CachedPage {
+                    meta,
+                    base: base.to_string(),
+                    content_type: header
+                        .as_ref()
+                        .and_then(|ct| ct.to_str().ok())
+                        .map(String::from),
+                };
+                if let Err(e) = cache.store(&page, &body) {
+                    client.suspend(|| eprintln!("failed to cache {url}: {e}"));
+                }
+            }
+            let page = SeenPage::new(&url, &base, header.as_ref(), &body, previous);
+            Ok(Fetched {
+                links: page.links.clone(),
+                location,
+                page: Some(page),
+            })
+        }
+        ContentType::Other(string) => {
+            client
+                .suspend(|| println!("other content type: {string} not following links of {url}"));
+            Ok(Fetched {
+                location,
+                ..Fetched::default()
+            })
+        }
+        ContentType::Unknown => Ok(Fetched {
+            location,
+            ..Fetched::default()
+        }),
+    }
+}
+
+/// where the redirects of `url` ended, none when it was not redirected
+fn location(url: &str, resolved: &Url) -> Option<String> {
+    (resolved.as_str() != url).then(|| resolved.to_string())
+}
+
+/// the links of the page at `url` with the `body` and Content-Type `header`, relative links are
+/// resolved against `base`
+fn page_links(url: &str, base: &Url, header: Option<&HeaderValue>, body: &[u8]) -> Vec<String> {
+    if body.is_empty() {
+        return Vec::new();
+    }
+    let charset = ContentType::charset(header);
+    match decode_page(url, body, charset.as_deref()) {
+        Some(site) => find_links(base, &site),
+        None => Vec::new(),
+    }
+}
+
+/// crawls the site at `root_url` breadth first and returns the tree of the urls found up to
+/// `max_depth`, the root alone is depth 1
+pub async fn crawl(
+    client: &HttpClient,
+    root_url: &str,
+    max_depth: usize,
+    options: &CrawlOptions,
+) -> Result<Tree<String>, RgetError> {
+    let root_url = normalize_url(root_url)?;
+    if root_url.starts_with("ftp://") {
+        return Err(RgetError::InvalidUrl {
+            url: root_url,
+            reason: "ftp sites have no links to crawl".to_string(),
+        });
+    }
+    let (state, previous) = match &options.state_file {
+        Some(path) if options.resume => {
+            let state = CrawlState::load(path)?;
+            if state.root != root_url {
+                return Err(RgetError::CrawlRootMismatch {
+                    path: path.clone(),
+                    root: state.root,
+                    url: root_url,
+                });
+            }
+            (state, HashMap::new())
+        }
+        // the pages of the last crawl of the same site, a state file that cannot be read is
+        // simply overwritten
+        Some(path) => {
+            let previous = CrawlState::load(path)
+                .ok()
+                .filter(|state| state.root == root_url)
+                .map(|state| state.pages)
+                .unwrap_or_default();
+            (CrawlState::new(root_url), previous)
+        }
+        None => (CrawlState::new(root_url), HashMap::new()),
+    };
+    let previous = &previous;
+    let checkpoint = options.state_file.as_deref();
+    let pacer = options
+        .wait
+        .map(|wait| Pacer::new(wait, options.random_wait));
+    let pacer = pacer.as_ref();
+    let cache = options
+        .cache_dir
+        .as_deref()
+        .map(PageCache::new)
+        .transpose()?;
+    let cache = cache.as_ref();
+    let host_limits = options.max_connections_per_host.map(HostLimits::new);
+    let host_limits = host_limits.as_ref();
+    let root = &state.root.clone();
+    let follow = |link: &String| {
+        options.url_filter.allows(link)
+            && (!options.no_parent || is_data_url(link) || is_below(root, link))
+    };
+    let fetch = |url: String| {
+        let mime = if options.force_html {
+            Some("text/html")
+        } else if url == *root {
+            options.content_type_override.as_deref()
+        } else {
+            None
+        };
+        let previous = previous.get(&url);
+        async move {
+            // inline content is saved but never crawled
+            if is_data_url(&url) {
+                return Ok(Fetched::default());
+            }
+            let _permit = match host_limits {
+                Some(limits) => limits.acquire(&url).await,
+                None => None,
+            };
+            if let Some(pacer) = pacer {
+                pacer.wait().await;
+            }
+            let fetched = fetch_links(client, url, mime, cache, previous).await;
+            if let (Err(_), Some(stats)) = (&fetched, client.stats()) {
+                stats.failed();
+            }
+            let mut fetched = fetched?;
+            fetched.links.retain(|link| follow(link));
+            Ok(fetched)
+        }
+    };
+    let limits = CrawlLimits {
+        max_depth,
+        max_depth_per_host: options.max_depth_per_host,
+        deadline: options.deadline,
+    };
+    Ok(crawl_with(state, limits, checkpoint, fetch).await)
+}
+
+/// how deep a crawl goes
+#[derive(Debug, Clone, Copy)]
+struct CrawlLimits {
+    max_depth: usize,
+    /// the depth of the crawl within every host but the one of the root, counted from the first
+    /// page of the host
+    max_depth_per_host: Option<usize>,
+    /// no page is fetched after this
+    deadline: Option<Instant>,
+}
+
+/// runs `future` until the `deadline`, `None` when it did not finish in time
+pub(crate) async fn before<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Some(Url::parse(url).ok()?.host_str()?.to_string())
+}
+
+/// how many pages deep `url` is into its host when it was found on `parent` at its own host
+/// depth, a link to another host starts at 1 again
+fn host_depth(url: &str, parent: Option<(&str, usize)>) -> usize {
+    match parent {
+        Some((parent, depth)) if host_of(parent) == host_of(url) => depth + 1,
+        _ => 1,
+    }
+}
+
+/// breadth first crawl continuing from `state` where `fetch` returns the links of a page
+///
+/// a depth of 1 only contains the root, every further level adds the links of the level above.
+/// The links of pages on other hosts than the root are only followed up to the depth per host.
+/// After every level the state is saved to `checkpoint`, a level cut short by the deadline is not
+async fn crawl_with<F, Fut>(
+    mut state: CrawlState,
+    limits: CrawlLimits,
+    checkpoint: Option<&Path>,
+    mut fetch: F,
+) -> Tree<String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<Fetched, reqwest::Error>>,
+{
+    let CrawlLimits {
+        max_depth,
+        max_depth_per_host,
+        deadline,
+    } = limits;
+    let (mut url_tree, mut q) = state.tree();
+
+    let root_host = host_of(&state.root);
+    let mut host_depths: HashMap<String, usize> = HashMap::new();
+    for (url, parent) in url_tree.with_parents() {
+        let parent = parent
+            .as_deref()
+            .map(|parent| (parent, host_depths[parent]));
+        let depth = host_depth(&url, parent);
+        host_depths.insert(url, depth);
+    }
+    // pages of other hosts that are as deep as allowed are kept without following their links
+    let follow = |url: &str, host_depths: &HashMap<String, usize>| match max_depth_per_host {
+        Some(max) if host_of(url) != root_host => host_depths[url] < max,
+        _ => true,
+    };
+    let mut fetch = |url: String, follow: bool| {
+        let page = follow.then(|| fetch(url));
+        async move {
+            match page {
+                Some(page) => page.await,
+                None => Ok(Fetched::default()),
+            }
+        }
+    };
+
+    while !q.is_empty() && max_depth > url_tree.depth {
+        // the queue length at the start of a level is exactly the number of nodes on that level,
+        // everything pushed while processing it belongs to the next one
+        let width = q.len();
+        let mut level = Vec::with_capacity(width);
+        for _ in 0..width {
+            level.push(q.pop().expect("the queue holds the whole level"));
+        }
+
+        // the pages of a level are fetched concurrently, the tree and the visited set are only
+        // touched here on the crawl task once the results are in so they need no locking
+        let urls: Vec<(String, bool)> = level
+            .iter()
+            .map(|n| {
+                let url = n.borrow().value.clone();
+                let follow = follow(&url, &host_depths);
+                (url, follow)
+            })
+            .collect();
+        let pages = urls.into_iter().map(|(url, follow)| fetch(url, follow));
+        let mut pages = stream::iter(pages).buffered(CRAWL_CONCURRENCY);
+
+        let mut pending = VecQueue::default();
+        let mut i = 0;
+        let mut timed_out = false;
+        loop {
+            let Some(links) = before(deadline, pages.next()).await else {
+                timed_out = true;
+                break;
+            };
+            let Some(links) = links else {
+                break;
+            };
+            let parent = &level[i];
+            let parent_url = parent.borrow().value.clone();
+            i += 1;
+            let links = match links {
+                Ok(fetched) => {
+                    parent.borrow_mut().resolved = fetched.location;
+                    if let Some(page) = fetched.page {
+                        state.pages.insert(parent_url.clone(), page);
+                    }
+                    fetched.links
+                }
+                Err(e) => {
+                    eprintln!("failed to crawl {parent_url}: {}", RgetError::from(e));
+                    continue;
+                }
+            };
+
+            for link in links {
+                if !state.visited.insert(link.clone()) {
+                    continue;
+                }
+                let depth = host_depth(&link, Some((&parent_url, host_depths[&parent_url])));
+                host_depths.insert(link.clone(), depth);
+                let children = state.children.entry(parent_url.clone()).or_default();
+                children.push(link.clone());
+                pending.push(link.clone());
+                let tree_node_ref = Rc::new(RefCell::new(TreeNode::new(link)));
+                q.push(tree_node_ref.clone());
+                Tree::push_node(parent.clone(), tree_node_ref);
+            }
+        }
+
+        if !q.is_empty() {
+            url_tree.depth += 1;
+        }
+        if timed_out {
+            // a resumed crawl fetches the whole level again from the last checkpoint
+            println!(
+                "reached the time limit, stopped crawling at depth {}",
+                url_tree.depth
+            );
+            break;
+        }
+
+        state.depth = url_tree.depth;
+        state.pending = pending;
+        if let Some(path) = checkpoint
+            && let Err(e) = state.save(path)
+        {
+            eprintln!("failed to save the crawl state to {}: {e}", path.display());
+        }
+    }
+    url_tree
+}
+
+/// decodes the page at `url` in the `charset` of its Content-Type, utf-8 if it has none
+///
+/// bytes that are invalid in the charset are replaced, the links around them are still found.
+/// Pages in an unknown charset are skipped
+pub(crate) fn decode_page<'a>(
+    url: &str,
+    body: &'a [u8],
+    charset: Option<&str>,
+) -> Option<Cow<'a, str>> {
+    let encoding = match charset {
+        Some(label) => match Encoding::for_label(label.as_bytes()) {
+            Some(encoding) => encoding,
+            None => {
+                println!("unknown charset {label}, not following links of {url}");
+                return None;
+            }
+        },
+        None => UTF_8,
+    };
+    // a byte order mark takes precedence over the header
+    let (text, encoding, had_errors) = encoding.decode(body);
+    if had_errors {
+        eprintln!(
+            "warning: {url} is not valid {}, invalid bytes are replaced",
+            encoding.name()
+        );
+    }
+    Some(text)
+}
+
+/// the base url of `document` fetched from `url`, the href of its first `<base>` element
+///
+/// like in browsers a relative href is resolved against `url`, and an href that is not a valid url
+/// or a `data:` or `javascript:` url is ignored
+pub(crate) fn document_base(url: &Url, document: &Html) -> Url {
+    let selector = Selector::parse("base[href]").expect("the selector is valid");
+    document
+        .select(&selector)
+        .next()
+        .and_then(|base| url.join(base.attr("href")?.trim()).ok())
+        .filter(|base| !matches!(base.scheme(), "data" | "javascript"))
+        .unwrap_or_else(|| url.clone())
+}
+
+/// the links of a html page, relative links are resolved against `base` or the `<base>` of the
+/// page
+///
+/// only http(s) and `data:` links are kept and their fragment is dropped, `page#top` and
+/// `page#end` are the same download
+pub fn find_links(base: &Url, html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let base = &document_base(base, &document);
+    let selector = Selector::parse("body a[href], body img[src]").expect("the selector is valid");
+
+    document
+        .select(&selector)
+        .filter_map(|element| element.attr("href").or_else(|| element.attr("src")))
+        .filter_map(|link| base.join(link.trim()).ok())
+        .filter(|url| matches!(url.scheme(), "http" | "https" | "data"))
+        .map(|mut url| {
+            url.set_fragment(None);
+            url.to_string()
+        })
+        .collect()
+}
+
+/// crawls `url` and downloads every file of the tree, the files are named according to the
+/// [`Layout`] of the crawl options
+///
+/// a failed download does not stop the others, they are all reported at the end
+pub async fn download_depth(
+    client: &HttpClient,
+    url: &str,
+    depth: usize,
+    crawl_options: &CrawlOptions,
+    options: DownloadOptions,
+) -> Result<(), RgetError> {
+    let start = Instant::now();
+    let quota = match crawl_options.quota {
+        Some(limit) => Some(Arc::new(Quota::new(limit))),
+        None => options.quota.clone(),
+    };
+    let options = DownloadOptions {
+        quota: quota.clone(),
+        ..options
+    };
+    let t: Tree<String> = crawl(client, url, depth, crawl_options).await?;
+    let (urls, pages): (Vec<String>, Vec<Option<String>>) = t.with_parents().into_iter().unzip();
+    println!("found {} urls", urls.len());
+    // the page a file was linked from is sent as its referer
+    let referers: Arc<HashMap<String, String>> = Arc::new(
+        urls.iter()
+            .zip(pages)
+            .filter_map(|(url, page)| Some((url.clone(), page?)))
+            .collect(),
+    );
+    let urls = match crawl_options.largest_only {
+        Some(count) => {
+            let plan = plan_downloads(client, urls, crawl_options.layout, &options).await;
+            largest(plan, count).into_iter().map(|d| d.url).collect()
+        }
+        None => urls,
+    };
+    // only the urls with an outfile are downloaded, their names are decided before the downloads
+    // start so two files never end up with the same one
+    let heads = crawl_heads(client, &urls, crawl_options.layout, &options).await;
+    let outfiles = crawl_outfiles(&urls, crawl_options.layout, &options, &heads);
+    let options = DownloadOptions {
+        add_extension: false,
+        trust_server_names: false,
+        ..options
+    };
+    let outfiles: Arc<HashMap<String, String>> = Arc::new(urls.into_iter().zip(outfiles).collect());
+
+    let total = outfiles.len();
+
+    // every download is its own task, so each one gets its own handle to the client
+    let client = client.clone();
+    let deadline = crawl_options.deadline;
+    let host_limits = crawl_options
+        .max_connections_per_host
+        .map(|max| Arc::new(HostLimits::new(max)));
+    let written = Arc::new(AtomicU64::new(0));
+    let total_written = written.clone();
+    let results = t
+        .traverse_with(move |url: String| {
+            let client = client.clone();
+            let options = DownloadOptions {
+                referer: referers.get(&url).cloned().or(options.referer.clone()),
+                ..options.clone()
+            };
+            let outfile = outfiles.get(&url).cloned();
+            let written = written.clone();
+            let host_limits = host_limits.clone();
+            async move {
+                let Some(outfile) = outfile else {
+                    return Ok(());
+                };
+                let save = async {
+                    create_parent_dir(&outfile)?;
+                    let _permit = match &host_limits {
+                        Some(limits) => limits.acquire(&url).await,
+                        None => None,
+                    };
+                    download(&client, &url, &outfile, &options).await
+                };
+                let result = before(deadline, save)
+                    .await
+                    .unwrap_or(Err(RgetError::TimeLimit));
+                match result {
+                    Ok(bytes) => {
+                        written.fetch_add(bytes, Ordering::Relaxed);
+                        Ok(())
+                    }
+                    Err(e) => Err((url, e)),
+                }
+            }
+        })
+        .await;
+
+    // files left out for the quota are not failures, the crawl was asked to stop there
+    let (over_quota, failures): (Vec<_>, Vec<_>) = results
+        .into_iter()
+        .filter_map(Result::err)
+        .partition(|(_, e)| matches!(e, RgetError::QuotaExceeded { .. }));
+    if let (Some(quota), false) = (&quota, over_quota.is_empty()) {
+        println!(
+            "the quota of {} is used up, {} left out",
+            HumanBytes(quota.limit()),
+            over_quota.len()
+        );
+    }
+    let written = total_written.load(Ordering::Relaxed);
+    report_downloads(
+        total - over_quota.len(),
+        &failures,
+        written,
+        start.elapsed(),
+    )
+}
+
+/// a file a dry run would download
+#[derive(Debug, PartialEq, Eq)]
+pub struct PlannedDownload {
+    pub url: String,
+    pub outfile: String,
+    /// the Content-Length of a HEAD request, if the server sent one
+    pub size: Option<u64>,
+}
+
+/// crawls `url` like [`download_depth`] but only prints what would be downloaded, nothing is
+/// written to disk
+pub async fn dry_run(
+    client: &HttpClient,
+    url: &str,
+    depth: usize,
+    crawl_options: &CrawlOptions,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let tree = crawl(client, url, depth, crawl_options).await?;
+    let urls = tree.iter().map(|(url, _)| url).collect();
+
+    let mut plan = plan_downloads(client, urls, crawl_options.layout, options).await;
+    if let Some(count) = crawl_options.largest_only {
+        plan = largest(plan, count);
+    }
+    for download in &plan {
+        let size = download
+            .size
+            .map(|size| HumanBytes(size).to_string())
+            .unwrap_or_else(|| "unknown size".to_string());
+        println!("{} -> {} ({size})", download.url, download.outfile);
+    }
+    let total: u64 = plan.iter().filter_map(|d| d.size).sum();
+    let unknown = plan.iter().filter(|d| d.size.is_none()).count();
+    println!(
+        "{} files, {} ({unknown} of unknown size)",
+        plan.len(),
+        HumanBytes(total)
+    );
+    Ok(())
+}
+
+/// the `count` largest downloads of the plan, largest first. Files of unknown size come last
+fn largest(mut plan: Vec<PlannedDownload>, count: usize) -> Vec<PlannedDownload> {
+    // the sort is stable, so files of the same size stay in crawl order
+    plan.sort_by_key(|download| Reverse(download.size));
+    plan.truncate(count);
+    plan
+}
+
+/// asks the server for the size and type of every url with a HEAD and leaves out the ones the
+/// filters or the maximum file size would skip
+async fn plan_downloads(
+    client: &HttpClient,
+    urls: Vec<String>,
+    layout: Layout,
+    options: &DownloadOptions,
+) -> Vec<PlannedDownload> {
+    let heads = urls.iter().map(|url| async move {
+        if is_data_url(url) {
+            return None;
+        }
+        client
+            .send(client.head(url))
+            .await
+            .and_then(|r| r.error_for_status())
+            .ok()
+    });
+    let heads: Vec<_> = stream::iter(heads)
+        .buffered(CRAWL_CONCURRENCY)
+        .collect()
+        .await;
+    let outfiles = crawl_outfiles(&urls, layout, options, &heads);
+
+    urls.into_iter()
+        .zip(outfiles)
+        .zip(heads)
+        .filter_map(|((url, outfile), head)| {
+            let headers = head.as_ref().map(|head| head.headers());
+            let header = headers.and_then(|h| h.get(CONTENT_TYPE));
+            let allowed = match header.and_then(|v| v.to_str().ok()) {
+                Some(mime) => {
+                    let content_type = ContentType::from_header_value(header);
+                    options.filter.allows(&url, Some((&content_type, mime)))
+                }
+                None => options.filter.allows(&url, None),
+            };
+            let size = headers.and_then(header_content_length).or_else(|| {
+                is_data_url(&url)
+                    .then(|| data_url::decode(&url).ok())
+                    .flatten()
+                    .map(|(_, content)| content.len() as u64)
+            });
+            let too_large =
+                matches!((size, options.max_filesize), (Some(size), Some(max)) if size > max);
+            (allowed != Some(false) && !too_large).then_some(PlannedDownload { outfile, url, size })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use regex::Regex;
+    use tokio::time::timeout;
+    use url::Url;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{
+        CrawlLimits, CrawlOptions, CrawlState, Fetched, HostLimits, Layout, Pacer, PlannedDownload,
+        crawl, crawl_outfiles, crawl_with, find_links, largest, plan_downloads,
+    };
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::download::DownloadOptions;
+    use crate::filter::{FileFilter, UrlFilter};
+    use crate::urls::hash_file_name;
+
+    /// a page with `links` that was not redirected
+    fn links_of(links: Vec<&str>) -> Fetched {
+        Fetched {
+            links: links.into_iter().map(String::from).collect(),
+            ..Fetched::default()
+        }
+    }
+
+    /// crawls a small fake site and returns the urls in the tree and the urls that were fetched
+    async fn crawl_site(depth: usize) -> (Vec<String>, Vec<String>) {
+        crawl_site_from(CrawlState::new("a".to_string()), depth, None).await
+    }
+
+    async fn crawl_site_from(
+        state: CrawlState,
+        depth: usize,
+        checkpoint: Option<&Path>,
+    ) -> (Vec<String>, Vec<String>) {
+        let site: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("a", vec!["b", "c"]),
+            ("b", vec!["d"]),
+            ("c", vec!["d", "e", "a"]),
+            ("d", vec!["f"]),
+        ]);
+        let fetched = RefCell::new(Vec::new());
+        let limits = CrawlLimits {
+            max_depth: depth,
+            max_depth_per_host: None,
+            deadline: None,
+        };
+        let tree = crawl_with(state, limits, checkpoint, |url| {
+            let links = site.get(url.as_str()).cloned().unwrap_or_default();
+            fetched.borrow_mut().push(url);
+            async move { Ok(links_of(links)) }
+        })
+        .await;
+
+        let mut nodes = Vec::new();
+        tree.traverse(|n| nodes.push(n.clone()));
+        (nodes, fetched.take())
+    }
+
+    #[tokio::test]
+    async fn test_crawl_depth() {
+        assert_eq!(crawl_site(1).await, (vec!["a".to_string()], vec![]));
+
+        let (nodes, fetched) = crawl_site(2).await;
+        assert_eq!(nodes, ["a", "b", "c"]);
+        assert_eq!(fetched, ["a"]);
+
+        let (nodes, fetched) = crawl_site(3).await;
+        assert_eq!(nodes, ["a", "b", "c", "d", "e"]);
+        assert_eq!(fetched, ["a", "b", "c"]);
+
+        let (nodes, fetched) = crawl_site(10).await;
+        assert_eq!(nodes, ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(fetched, ["a", "b", "c", "d", "e", "f"]);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_depth_per_host() {
+        let site: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("http://a/", vec!["http://a/1", "http://b/"]),
+            ("http://a/1", vec!["http://a/2"]),
+            ("http://a/2", vec!["http://a/3"]),
+            ("http://b/", vec!["http://b/1", "http://c/"]),
+            ("http://b/1", vec!["http://b/2"]),
+        ]);
+        let fetched = RefCell::new(Vec::new());
+        let limits = CrawlLimits {
+            max_depth: 5,
+            max_depth_per_host: Some(2),
+            deadline: None,
+        };
+        let state = CrawlState::new("http://a/".to_string());
+        let tree = crawl_with(state, limits, None, |url| {
+            let links = site.get(url.as_str()).cloned().unwrap_or_default();
+            fetched.borrow_mut().push(url);
+            async move { Ok(links_of(links)) }
+        })
+        .await;
+
+        let mut nodes = Vec::new();
+        tree.traverse(|n| nodes.push(n.clone()));
+        // the root host goes as deep as the crawl, b/1 is the second page of b so its links are
+        // not followed, c starts over
+        assert_eq!(
+            nodes,
+            [
+                "http://a/",
+                "http://a/1",
+                "http://b/",
+                "http://a/2",
+                "http://b/1",
+                "http://c/",
+                "http://a/3"
+            ]
+        );
+        assert!(!fetched.borrow().contains(&"http://b/1".to_string()));
+        assert!(fetched.borrow().contains(&"http://c/".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_crawl_deadline() {
+        let limits = CrawlLimits {
+            max_depth: 10,
+            max_depth_per_host: None,
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+        };
+        let state = CrawlState::new("a".to_string());
+        let start = Instant::now();
+        let tree = crawl_with(state, limits, None, |url| async move {
+            match url.as_str() {
+                "a" => Ok(links_of(vec!["b", "c"])),
+                // b never answers, c is done but its links come after the ones of b
+                "b" => std::future::pending().await,
+                _ => Ok(links_of(vec!["d"])),
+            }
+        })
+        .await;
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let mut nodes = Vec::new();
+        tree.traverse(|n| nodes.push(n.clone()));
+        assert_eq!(nodes, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_resume_crawl() {
+        let state_file = std::env::temp_dir().join(format!("rget-state-{}", std::process::id()));
+
+        crawl_site_from(CrawlState::new("a".to_string()), 2, Some(&state_file)).await;
+        let state = CrawlState::load(&state_file).unwrap();
+        assert_eq!(state.depth, 2);
+        assert_eq!(state.pending.len(), 2);
+
+        // resuming only fetches the pages that were not crawled yet
+        let (nodes, fetched) = crawl_site_from(state, 3, Some(&state_file)).await;
+        assert_eq!(nodes, ["a", "b", "c", "d", "e"]);
+        assert_eq!(fetched, ["b", "c"]);
+
+        let state = CrawlState::load(&state_file).unwrap();
+        let (nodes, fetched) = crawl_site_from(state, 10, None).await;
+        assert_eq!(nodes, ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(fetched, ["d", "e", "f"]);
+
+        fs::remove_file(&state_file).unwrap();
+    }
+
+    /// crawls the mock server and returns the paths of the urls in the tree
+    async fn crawl_paths(client: &HttpClient, uri: &str, depth: usize) -> Vec<String> {
+        crawl_paths_with(client, uri, depth, &CrawlOptions::default()).await
+    }
+
+    async fn crawl_paths_with(
+        client: &HttpClient,
+        uri: &str,
+        depth: usize,
+        options: &CrawlOptions,
+    ) -> Vec<String> {
+        let root = format!("{uri}/");
+        let tree = crawl(client, &root, depth, options).await.unwrap();
+        let mut paths = Vec::new();
+        tree.traverse(|url| paths.push(url.strip_prefix(uri).unwrap().to_string()));
+        paths
+    }
+
+    #[tokio::test]
+    async fn test_crawl_mislabeled_html() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let page = |links: &str| {
+            ResponseTemplate::new(200).set_body_raw(links.to_string(), "application/octet-stream")
+        };
+        Mock::given(path("/"))
+            .respond_with(page(r#"<a href="/a">a</a>"#))
+            .mount(&server)
+            .await;
+        Mock::given(path("/a"))
+            .respond_with(page(r#"<a href="/b">b</a>"#))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+
+        assert_eq!(crawl_paths(&client, &uri, 3).await, ["/"]);
+        let options = CrawlOptions {
+            content_type_override: Some("text/html".to_string()),
+            ..CrawlOptions::default()
+        };
+        assert_eq!(
+            crawl_paths_with(&client, &uri, 3, &options).await,
+            ["/", "/a"]
+        );
+        let options = CrawlOptions {
+            force_html: true,
+            ..CrawlOptions::default()
+        };
+        assert_eq!(
+            crawl_paths_with(&client, &uri, 3, &options).await,
+            ["/", "/a", "/b"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crawl() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let pages = [
+            ("/", vec!["/a", "/b"]),
+            ("/a", vec!["/c", "/"]),
+            ("/b", vec!["/image.png"]),
+            ("/c", vec![]),
+        ];
+        for (page, links) in pages {
+            let body: String = links
+                .iter()
+                .map(|link| format!("<a href=\"{link}\">{link}</a>"))
+                .collect();
+            Mock::given(path(page))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_raw(format!("<html><body>{body}</body></html>"), "text/html"),
+                )
+                .mount(&server)
+                .await;
+        }
+        // the HEAD shows that the image has no links, it is never downloaded by the crawl
+        Mock::given(method("HEAD"))
+            .and(path("/image.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0; 100], "image/png"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/image.png"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        assert_eq!(crawl_paths(&client, &uri, 1).await, ["/"]);
+        assert_eq!(crawl_paths(&client, &uri, 2).await, ["/", "/a", "/b"]);
+        assert_eq!(
+            crawl_paths(&client, &uri, 3).await,
+            ["/", "/a", "/b", "/c", "/image.png"]
+        );
+        assert_eq!(
+            crawl_paths(&client, &uri, 10).await,
+            ["/", "/a", "/b", "/c", "/image.png"]
+        );
+
+        let options = CrawlOptions {
+            url_filter: UrlFilter {
+                accept: Some(Regex::new("/[ab]$").unwrap()),
+                reject: Some(Regex::new("/b$").unwrap()),
+            },
+            ..CrawlOptions::default()
+        };
+        assert_eq!(
+            crawl_paths_with(&client, &uri, 10, &options).await,
+            ["/", "/a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crawl_no_parent() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let page =
+            |links: &str| ResponseTemplate::new(200).set_body_raw(links.to_string(), "text/html");
+        Mock::given(path("/docs/"))
+            .respond_with(page(
+                r#"<a href="/">home</a><a href="guide/">guide</a><a href="/blog/">blog</a>"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/docs/guide/"))
+            .respond_with(page(
+                r#"<a href="../">docs</a><a href="setup.html">setup</a>"#,
+            ))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let docs = format!("{uri}/docs");
+
+        // the paths are relative to /docs
+        let options = CrawlOptions {
+            no_parent: true,
+            ..CrawlOptions::default()
+        };
+        assert_eq!(
+            crawl_paths_with(&client, &docs, 3, &options).await,
+            ["/", "/guide/", "/guide/setup.html"]
+        );
+        let tree = crawl(&client, &format!("{docs}/"), 2, &CrawlOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(tree.leaves().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_crawl_unchanged_pages() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let page =
+            |body: &str| ResponseTemplate::new(200).set_body_raw(body.to_string(), "text/html");
+        Mock::given(path("/"))
+            .respond_with(page(r#"<a href="/a">a</a>"#))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let state_file =
+            std::env::temp_dir().join(format!("rget-unchanged-{}", std::process::id()));
+        let options = CrawlOptions {
+            state_file: Some(state_file.clone()),
+            ..CrawlOptions::default()
+        };
+        let crawl_urls = || async {
+            let tree = crawl(&client, &format!("{uri}/"), 2, &options)
+                .await
+                .unwrap();
+            tree.iter().map(|(url, _)| url).collect::<Vec<_>>()
+        };
+        assert_eq!(crawl_urls().await, [format!("{uri}/"), format!("{uri}/a")]);
+
+        // links that are only in the state file show that the unchanged page was not parsed
+        let mut state = CrawlState::load(&state_file).unwrap();
+        let root = state.pages.get_mut(&format!("{uri}/")).unwrap();
+        root.links = vec![format!("{uri}/saved")];
+        state.save(&state_file).unwrap();
+        assert_eq!(
+            crawl_urls().await,
+            [format!("{uri}/"), format!("{uri}/saved")]
+        );
+
+        // a page that changed is parsed again
+        server.reset().await;
+        Mock::given(path("/"))
+            .respond_with(page(r#"<a href="/b">b</a>"#))
+            .mount(&server)
+            .await;
+        assert_eq!(crawl_urls().await, [format!("{uri}/"), format!("{uri}/b")]);
+        fs::remove_file(&state_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_crawl_redirects() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let redirect = |to: &str| {
+            ResponseTemplate::new(301).insert_header("Location", format!("{uri}{to}").as_str())
+        };
+        Mock::given(path("/old"))
+            .respond_with(redirect("/new/"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/new/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(r#"<a href="a">a</a><a href="/loop">loop</a>"#, "text/html"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/loop"))
+            .respond_with(redirect("/loop"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+
+        let tree = crawl(&client, &format!("{uri}/old"), 3, &CrawlOptions::default())
+            .await
+            .unwrap();
+        let root = tree.root.borrow();
+        assert_eq!(root.resolved, Some(format!("{uri}/new/")));
+        let children: Vec<_> = root
+            .children
+            .iter()
+            .map(|c| (c.borrow().value.clone(), c.borrow().resolved.clone()))
+            .collect();
+        // links are relative to where the redirect ended, the loop is kept without being resolved
+        assert_eq!(
+            children,
+            [
+                (format!("{uri}/new/a"), None),
+                (format!("{uri}/loop"), None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crawl_cache() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        Mock::given(path("/"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_raw(r#"<a href="/a">a</a>"#, "text/html"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/a"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("", "text/plain"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let dir = std::env::temp_dir().join(format!("rget-crawl-cache-{}", std::process::id()));
+        let options = CrawlOptions {
+            cache_dir: Some(dir.clone()),
+            ..CrawlOptions::default()
+        };
+
+        // the second crawl finds the links in the cached page
+        for _ in 0..2 {
+            assert_eq!(
+                crawl_paths_with(&client, &uri, 2, &options).await,
+                ["/", "/a"]
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_crawl_invalid_utf8() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let mut body = b"<html><body><a href=\"/a\">\xff\xfe</a>".to_vec();
+        body.extend_from_slice(b"<a href=\"/latin1\">l</a><a href=\"/unknown\">u</a></body>");
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/html"))
+            .mount(&server)
+            .await;
+        // the é of the link is a single byte in latin-1
+        let latin1 = b"<html><body><a href=\"/caf\xe9\">caf\xe9</a></body></html>".to_vec();
+        Mock::given(path("/latin1"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(latin1, "text/html; charset=iso-8859-1"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/unknown"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("<a href=\"/x\">x</a>", "text/html; charset=klingon"),
+            )
+            .mount(&server)
+            .await;
+
+        // invalid bytes do not hide the links around them, a page in an unknown charset is
+        // skipped
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        assert_eq!(
+            crawl_paths(&client, &uri, 3).await,
+            ["/", "/a", "/latin1", "/unknown", "/caf%C3%A9"]
+        );
+    }
+
+    #[test]
+    fn test_find_links() {
+        let base = Url::parse("http://localhost:3000/docs/index.html").unwrap();
+        let html = r##"<html><body>
+            <a href="https://example.com/">absolute</a>
+            <a href="page.html#intro">relative</a>
+            <a href="/root.html">root relative</a>
+            <a href="../up.html">parent</a>
+            <img src="//cdn.example.com/logo.png">
+            <a href="mailto:someone@example.com">mail</a>
+            <img src="data:image/gif;base64,R0lGODlhAQABAAAAACw=">
+            <a href="#top">top</a>
+        </body></html>"##;
+
+        assert_eq!(
+            find_links(&base, html),
+            [
+                "https://example.com/",
+                "http://localhost:3000/docs/page.html",
+                "http://localhost:3000/root.html",
+                "http://localhost:3000/up.html",
+                "http://cdn.example.com/logo.png",
+                "data:image/gif;base64,R0lGODlhAQABAAAAACw=",
+                "http://localhost:3000/docs/index.html",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_links_base() {
+        let url = Url::parse("http://localhost:3000/docs/index.html").unwrap();
+        let page = |base: &str| {
+            format!(
+                r#"<html><head>{base}<base href="/ignored/"></head>
+                <body><a href="page.html">page</a></body></html>"#
+            )
+        };
+        let links = |base: &str| find_links(&url, &page(base));
+
+        assert_eq!(
+            links(r#"<base href="https://cdn.example.com/v2/">"#),
+            ["https://cdn.example.com/v2/page.html"]
+        );
+        // a relative base is relative to the url of the page
+        assert_eq!(
+            links(r#"<base href="../api/">"#),
+            ["http://localhost:3000/api/page.html"]
+        );
+        // only the first base with an href counts
+        assert_eq!(
+            links(r#"<base target="_blank">"#),
+            ["http://localhost:3000/ignored/page.html"]
+        );
+        for invalid in [r#"<base href="http://[::1">"#, r#"<base href="data:,x">"#] {
+            assert_eq!(links(invalid), ["http://localhost:3000/docs/page.html"]);
+        }
+        assert_eq!(
+            find_links(&url, r#"<a href="page.html">page</a>"#),
+            ["http://localhost:3000/docs/page.html"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_downloads() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let files = [
+            ("/page.html", "text/html", 100),
+            ("/small.png", "image/png", 10),
+            ("/large.png", "image/png", 10_000),
+            ("/archive.zip", "application/zip", 50),
+        ];
+        for (file, mime, size) in files {
+            Mock::given(method("HEAD"))
+                .and(path(file))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0; size], mime))
+                .mount(&server)
+                .await;
+        }
+        let urls: Vec<String> = files
+            .iter()
+            .map(|(file, _, _)| format!("{uri}{file}"))
+            .chain([format!("{uri}/missing.png")])
+            .collect();
+        let options = DownloadOptions {
+            filter: FileFilter {
+                accept: vec!["image/*".to_string(), "png".to_string()],
+                reject: Vec::new(),
+            },
+            max_filesize: Some(1000),
+            ..DownloadOptions::default()
+        };
+
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let plan = plan_downloads(&client, urls, Layout::Hashed, &options).await;
+        assert_eq!(
+            plan,
+            [
+                PlannedDownload {
+                    url: format!("{uri}/small.png"),
+                    outfile: hash_file_name(&format!("{uri}/small.png")),
+                    size: Some(10),
+                },
+                // the HEAD failed, so only the extension can be checked
+                PlannedDownload {
+                    url: format!("{uri}/missing.png"),
+                    outfile: hash_file_name(&format!("{uri}/missing.png")),
+                    size: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_largest() {
+        let planned = |url: &str, size| PlannedDownload {
+            url: url.to_string(),
+            outfile: url.to_string(),
+            size,
+        };
+        let plan = vec![
+            planned("page", Some(10)),
+            planned("unknown", None),
+            planned("video", Some(1000)),
+            planned("image", Some(100)),
+            planned("thumbnail", Some(100)),
+        ];
+        let urls = |plan: Vec<PlannedDownload>| -> Vec<String> {
+            plan.into_iter().map(|d| d.url).collect()
+        };
+        assert_eq!(urls(largest(plan, 3)), ["video", "image", "thumbnail"]);
+        assert_eq!(
+            urls(largest(
+                vec![planned("unknown", None), planned("page", Some(10))],
+                5
+            )),
+            ["page", "unknown"]
+        );
+    }
+
+    #[test]
+    fn test_crawl_outfiles() {
+        let options = DownloadOptions::default();
+        let urls = [
+            "http://localhost:3000/",
+            "http://localhost:3000/docs/page?a=1",
+            "http://localhost:3000/docs/page?a=2",
+            "http://localhost:3000/img/page",
+        ]
+        .map(String::from);
+        assert_eq!(
+            crawl_outfiles(&urls, Layout::Flat, &options, &[]),
+            ["index.html", "page", "page.1", "page.2"]
+        );
+        assert_eq!(
+            crawl_outfiles(&urls, Layout::Tree, &options, &[]),
+            [
+                "localhost:3000/index.html",
+                "localhost:3000/docs/page?a=1",
+                "localhost:3000/docs/page?a=2",
+                "localhost:3000/img/page",
+            ]
+        );
+        assert_eq!(
+            crawl_outfiles(&urls[..1], Layout::Hashed, &options, &[]),
+            [hash_file_name(&urls[0])]
+        );
+        // a page named like a directory of another page does not take its place
+        let nested = [
+            "http://localhost:3000/docs",
+            "http://localhost:3000/docs/index.html",
+            "http://localhost:3000/docs/",
+        ]
+        .map(String::from);
+        assert_eq!(
+            crawl_outfiles(&nested, Layout::Tree, &options, &[]),
+            [
+                "localhost:3000/docs.1",
+                "localhost:3000/docs/index.html",
+                "localhost:3000/docs/index.html.1",
+            ]
+        );
+
+        let compressed = DownloadOptions {
+            compress: true,
+            ..DownloadOptions::default()
+        };
+        assert_eq!(
+            crawl_outfiles(&urls[..2], Layout::Flat, &compressed, &[]),
+            ["index.html.gz", "page.gz"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_host_limits() {
+        let limits = HostLimits::new(1);
+        let busy = Duration::from_millis(50);
+        let first = limits.acquire("http://a/1").await;
+        assert!(first.is_some());
+        // a second request to a has to wait, other hosts do not
+        assert!(timeout(busy, limits.acquire("http://a/2")).await.is_err());
+        assert!(timeout(busy, limits.acquire("http://b/")).await.is_ok());
+        drop(first);
+        assert!(timeout(busy, limits.acquire("http://a/2")).await.is_ok());
+        assert!(limits.acquire("data:,a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pacer() {
+        let pacer = Pacer::new(Duration::from_millis(50), false);
+        let start = Instant::now();
+        // concurrent fetches still start one after the other
+        futures::future::join_all((0..3).map(|_| pacer.wait())).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let pacer = Pacer::new(Duration::from_millis(50), true);
+        let start = Instant::now();
+        pacer.wait().await;
+        pacer.wait().await;
+        // a random wait is at least half the wait, how long it takes at most depends on the load
+        // of the machine running the test
+        assert!(start.elapsed() >= Duration::from_millis(25));
+    }
+}
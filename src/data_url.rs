@@ -0,0 +1,143 @@
+use std::fs::{self, File};
+use std::io::Write;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest as _, Sha256};
+
+use crate::content_type::ContentType;
+use crate::download::{
+    DownloadOptions, FileWriter, check_filesize, finish_file, part_file, resolve_outfile,
+    with_extension,
+};
+use crate::error::RgetError;
+use crate::progress::DownloadProgress;
+
+/// the media type of a data url that does not name one
+const DEFAULT_MIME: &str = "text/plain;charset=US-ASCII";
+
+/// whether `url` carries its content itself instead of pointing to it
+pub(crate) fn is_data_url(url: &str) -> bool {
+    url.get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// the media type and the decoded content of a `data:[<mime>][;base64],<data>` url
+pub(crate) fn decode(url: &str) -> Result<(String, Vec<u8>), RgetError> {
+    let (header, data) = url[5..]
+        .split_once(',')
+        .ok_or_else(|| RgetError::InvalidUrl {
+            url: url.to_string(),
+            reason: "a data url needs a comma before its content".to_string(),
+        })?;
+    let (mime, base64) = match header.trim_end().rsplit_once(';') {
+        Some((mime, encoding)) if encoding.eq_ignore_ascii_case("base64") => (mime, true),
+        _ => (header, false),
+    };
+    let mime = match mime.trim() {
+        "" => DEFAULT_MIME.to_string(),
+        mime => percent_decode_str(mime).decode_utf8_lossy().into_owned(),
+    };
+    let bytes: Vec<u8> = percent_decode_str(data).collect();
+    if !base64 {
+        return Ok((mime, bytes));
+    }
+    // base64 in html is often wrapped over several lines
+    let bytes: Vec<u8> = bytes
+        .into_iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let content = STANDARD.decode(bytes).map_err(|e| RgetError::InvalidUrl {
+        url: format!("data:{header},..."),
+        reason: e.to_string(),
+    })?;
+    Ok((mime, content))
+}
+
+/// the `data:` url carrying `content` as base64, spaces are dropped from the media type
+pub(crate) fn encode(mime: &str, content: &[u8]) -> String {
+    let mime: String = mime.chars().filter(|c| !c.is_whitespace()).collect();
+    format!("data:{mime};base64,{}", STANDARD.encode(content))
+}
+
+/// saves the content of the `data:` url to `outfile`, nothing is sent over the network
+///
+/// returns the number of bytes written, 0 when the file is filtered out
+pub(crate) async fn download(
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    let (mime, content) = decode(url)?;
+    let content_type = ContentType::from_mime_str(&mime);
+    if options.filter.allows(url, Some((&content_type, &mime))) == Some(false) {
+        options.suspend(|| println!("skipping a data url, content type {mime:?} is filtered out"));
+        return Ok(0);
+    }
+    let size = content.len() as u64;
+    check_filesize(url, size, options.max_filesize)?;
+    if let Some(quota) = &options.quota {
+        quota.reserve(url, size)?;
+    }
+    let outfile = &with_extension(outfile, &content_type, options);
+    let outfile = &resolve_outfile(outfile, options);
+
+    let pb = DownloadProgress::new(url, Some(size), options);
+    let part = part_file(outfile);
+    let mut dest = FileWriter::new(File::create(&part)?, options.compression());
+    dest.write_all(&content)?;
+    dest.finish()?;
+    fs::rename(&part, outfile)?;
+    pb.inc(size);
+    pb.finish(outfile);
+
+    let digest = Sha256::digest(&content).into();
+    finish_file(options, url, outfile, Some(digest), None).await?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DEFAULT_MIME, decode, encode, is_data_url};
+
+    #[test]
+    fn test_decode() {
+        let decoded = |url| decode(url).ok();
+        assert_eq!(
+            decoded("data:text/plain;base64,aGVsbG8gd29y\n bGQ="),
+            Some(("text/plain".to_string(), b"hello world".to_vec()))
+        );
+        assert_eq!(
+            decoded("data:,hello%20world"),
+            Some((DEFAULT_MIME.to_string(), b"hello world".to_vec()))
+        );
+        assert_eq!(
+            decoded("data:image/svg+xml;charset=utf-8,%3Csvg%2F%3E"),
+            Some((
+                "image/svg+xml;charset=utf-8".to_string(),
+                b"<svg/>".to_vec()
+            ))
+        );
+        assert_eq!(decoded("data:text/plain;base64,not base64!"), None);
+        assert_eq!(decoded("data:text/plain"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        let url = encode("text/css; charset=utf-8", b"a{}");
+        assert_eq!(url, "data:text/css;charset=utf-8;base64,YXt9");
+        assert_eq!(
+            decode(&url).ok(),
+            Some(("text/css;charset=utf-8".to_string(), b"a{}".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_is_data_url() {
+        assert!(is_data_url("data:,a"));
+        assert!(is_data_url("DATA:,a"));
+        assert!(!is_data_url("https://example.com/data:"));
+        assert!(!is_data_url("dat"));
+    }
+}
@@ -0,0 +1,2916 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use bytes::Bytes;
+use encoding_rs::{Encoding, UTF_8};
+use filetime::FileTime;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures::{StreamExt, future, stream};
+use http::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE, REFERER,
+};
+use http::{HeaderMap, Method, StatusCode, Version};
+use indicatif::{HumanBytes, MultiProgress, ProgressStyle};
+use reqwest::{RequestBuilder, Response, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use unicode_width::UnicodeWidthChar;
+
+use crate::client::HttpClient;
+use crate::content_type::ContentType;
+use crate::data_url::{self, is_data_url};
+use crate::error::RgetError;
+use crate::filter::FileFilter;
+use crate::ftp;
+use crate::progress::DownloadProgress;
+use crate::resume::{CHECKPOINT_INTERVAL, Checkpoint};
+use crate::stats::Stats;
+#[cfg(unix)]
+use crate::unix;
+use crate::urls::{OutputTemplate, derived_file_name, normalize_url};
+
+/// how a file is downloaded, the default downloads over one connection and overwrites existing
+/// files with a warning
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub clobber: ClobberPolicy,
+    pub filter: FileFilter,
+    /// number of byte ranges downloaded in parallel
+    pub connections: usize,
+    /// the progress bars of all downloads of a run are drawn together
+    pub progress: MultiProgress,
+    /// files larger than this many bytes are not saved
+    pub max_filesize: Option<u64>,
+    /// send conditional requests for files that were downloaded before
+    pub etag: bool,
+    pub progress_format: ProgressFormat,
+    /// replaces the indicatif template of the progress bar preset
+    pub progress_template: Option<String>,
+    /// replaces the bar characters of the progress bar preset
+    pub progress_chars: Option<String>,
+    /// the request method, only GET downloads use conditional and range requests
+    pub method: Method,
+    /// sent as the body of the request
+    pub body: Option<Vec<u8>>,
+    /// files with the same content as an earlier download of the run are replaced by a hard link
+    pub dedupe: Option<Arc<ContentIndex>>,
+    /// report the progress here instead of showing it, for callers with their own display
+    pub transfer: Option<Arc<Transfer>>,
+    /// called with the progress instead of showing it, for embedders that draw their own bars
+    pub on_progress: Option<OnProgress>,
+    /// a shell command run for every saved file, `{}` is replaced by its path
+    pub exec: Option<String>,
+    /// skip files whose local copy is not older than the Last-Modified of the server and give
+    /// downloaded files the modification time of the server
+    pub timestamping: bool,
+    /// sent as the Referer header, some servers only hand out files linked from their own pages
+    pub referer: Option<String>,
+    /// only download this slice of the file
+    pub byte_range: Option<ByteRange>,
+    /// names the files of a run with several urls instead of their url
+    pub output_template: Option<OutputTemplate>,
+    /// gzip the files while saving them, their names are left as they are
+    pub compress: bool,
+    /// the gzip level of [`DownloadOptions::compress`] from 0 to 9, 0 only stores the data
+    pub compression_level: u32,
+    /// send the request over this unix socket instead of connecting to the host of the url
+    pub unix_socket: Option<PathBuf>,
+    /// append the extension of the content type to an outfile without one, the runs of several
+    /// urls only do it for names derived from the url
+    pub add_extension: bool,
+    /// name the file after the last segment of the url the redirects ended at, in the directory
+    /// of the outfile, for names derived from the url
+    pub trust_server_names: bool,
+    /// derive the names of files from the `filename`, `file` or `name` parameter of their query
+    /// if they have one, see [`file_name_from_query`](crate::urls::file_name_from_query)
+    pub decode_query: bool,
+    /// create the directory of the outfile when it does not exist, the directories of names
+    /// derived from a template or a crawl layout are always created
+    pub create_dirs: bool,
+    /// count the downloads and their bytes here, for --stats-file
+    pub stats: Option<Arc<Stats>>,
+    /// remove every file once it is saved and --exec ran on it, to only exercise the server
+    pub delete_after: bool,
+    /// save the body of text responses as trimmed utf-8 in place of the bytes the server sent,
+    /// other responses are an error
+    pub text: bool,
+    /// turn the `\r\n` and `\r` line endings of [`DownloadOptions::text`] into `\n`
+    pub convert_newlines: bool,
+    /// the bytes all downloads sharing it may write together
+    pub quota: Option<Arc<Quota>>,
+    /// print the http version and the status of the response a file is downloaded with
+    pub verbose: bool,
+}
+
+impl DownloadOptions {
+    /// runs `print` with the progress bars of the run hidden, lines printed while they are drawn
+    /// would be overwritten or leave pieces of the bars behind
+    pub(crate) fn suspend<R>(&self, print: impl FnOnce() -> R) -> R {
+        self.progress.suspend(print)
+    }
+
+    /// the gzip level the files are saved with, none without --compress
+    pub(crate) fn compression(&self) -> Option<Compression> {
+        self.compress
+            .then(|| Compression::new(self.compression_level))
+    }
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            clobber: ClobberPolicy::default(),
+            filter: FileFilter::default(),
+            connections: 1,
+            progress: MultiProgress::new(),
+            max_filesize: None,
+            etag: false,
+            progress_format: ProgressFormat::default(),
+            progress_template: None,
+            progress_chars: None,
+            method: Method::GET,
+            body: None,
+            dedupe: None,
+            transfer: None,
+            on_progress: None,
+            exec: None,
+            timestamping: false,
+            referer: None,
+            byte_range: None,
+            output_template: None,
+            compress: false,
+            compression_level: Compression::default().level(),
+            unix_socket: None,
+            add_extension: false,
+            trust_server_names: false,
+            decode_query: false,
+            create_dirs: false,
+            stats: None,
+            delete_after: false,
+            text: false,
+            convert_newlines: false,
+            quota: None,
+            verbose: false,
+        }
+    }
+}
+
+/// the progress of a download shown by the caller, see [`DownloadOptions::transfer`]
+#[derive(Debug)]
+pub struct Transfer {
+    downloaded: AtomicU64,
+    /// `u64::MAX` while the size is not known
+    total: AtomicU64,
+    /// the sidecar of the part file once the download can be resumed from it
+    sidecar: Mutex<Option<PathBuf>>,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Transfer {
+            downloaded: AtomicU64::new(0),
+            total: AtomicU64::new(u64::MAX),
+            sidecar: Mutex::new(None),
+        }
+    }
+}
+
+impl Transfer {
+    pub(crate) fn start(&self, total: Option<u64>) {
+        self.downloaded.store(0, Ordering::Relaxed);
+        self.total
+            .store(total.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub(crate) fn inc(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// the bytes received so far
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// the size of the file if the server sent it
+    pub fn total(&self) -> Option<u64> {
+        let total = self.total.load(Ordering::Relaxed);
+        (total != u64::MAX).then_some(total)
+    }
+
+    pub(crate) fn set_sidecar(&self, sidecar: PathBuf) {
+        *self.sidecar.lock().unwrap() = Some(sidecar);
+    }
+
+    /// the sidecar a stopped download can be continued from with [`resume`](crate::resume::resume),
+    /// none if it cannot be continued
+    pub fn sidecar(&self) -> Option<PathBuf> {
+        self.sidecar.lock().unwrap().clone()
+    }
+}
+
+/// a callback for the progress of a download, see [`DownloadOptions::on_progress`]
+///
+/// it is called with the bytes received so far and the size of the file if the server sent it,
+/// once with 0 when the body starts and then after every chunk. It runs on whichever thread
+/// received the chunk, the parts of a download over several connections call it at the same
+/// time, so it has to be `Send + Sync` and should return quickly because the download waits for
+/// it. A download that is retried or resumed starts again with the bytes it already has
+#[derive(Clone)]
+pub struct OnProgress(Arc<dyn Fn(u64, Option<u64>) + Send + Sync>);
+
+impl OnProgress {
+    pub fn new(callback: impl Fn(u64, Option<u64>) + Send + Sync + 'static) -> Self {
+        OnProgress(Arc::new(callback))
+    }
+
+    pub(crate) fn call(&self, downloaded: u64, total: Option<u64>) {
+        (self.0)(downloaded, total);
+    }
+}
+
+impl fmt::Debug for OnProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnProgress")
+    }
+}
+
+/// the files downloaded so far by their content hash
+#[derive(Debug, Default)]
+pub struct ContentIndex {
+    files: Mutex<HashMap<Digest, String>>,
+}
+
+impl ContentIndex {
+    /// the file that was saved first with this content, `outfile` if it is the first
+    fn original(&self, digest: Digest, outfile: &str) -> String {
+        let mut files = self.files.lock().unwrap();
+        files
+            .entry(digest)
+            .or_insert_with(|| outfile.to_string())
+            .clone()
+    }
+}
+
+pub(crate) type Digest = [u8; 32];
+
+/// a budget of bytes shared by the downloads of a run
+///
+/// files of known size reserve their size before they are started, so a file either fits and is
+/// saved completely or is not started at all. Files of unknown size count every chunk and are
+/// removed when they run over
+#[derive(Debug)]
+pub struct Quota {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl Quota {
+    pub fn new(limit: u64) -> Self {
+        Quota {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// the bytes written or reserved so far
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn exceeded(&self, url: &str) -> RgetError {
+        RgetError::QuotaExceeded {
+            url: url.to_string(),
+            quota: self.limit,
+        }
+    }
+
+    /// an error once nothing is left, a file is not even requested then
+    pub(crate) fn check(&self, url: &str) -> Result<(), RgetError> {
+        match self.used() >= self.limit {
+            true => Err(self.exceeded(url)),
+            false => Ok(()),
+        }
+    }
+
+    /// takes `bytes` of the quota for `url`, an error without taking any if they do not fit
+    pub(crate) fn reserve(&self, url: &str, bytes: u64) -> Result<(), RgetError> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&total| total <= self.limit)
+            })
+            .map(drop)
+            .map_err(|_| self.exceeded(url))
+    }
+
+    /// gives back the reservation of a download that failed
+    pub(crate) fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// the inclusive byte offsets of a slice of a file, without an end it goes to the end of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// the value of the Range header
+    pub(crate) fn header(&self) -> String {
+        match self.end {
+            Some(end) => format!("bytes={}-{end}", self.start),
+            None => format!("bytes={}-", self.start),
+        }
+    }
+}
+
+/// what to do when the outfile of a download already exists
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClobberPolicy {
+    /// overwrite the file but print a warning
+    #[default]
+    Warn,
+    /// keep the file and pick a free `<outfile>.<n>` name
+    NoClobber,
+    /// overwrite the file silently
+    Force,
+}
+
+/// how the progress of a download is shown
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// progress bars and spinners on the terminal
+    #[default]
+    Bar,
+    /// a bar of dots with the speed
+    Dots,
+    /// just the percentage and the bytes
+    Minimal,
+    /// newline delimited json events on stderr for programs wrapping rget
+    Json,
+}
+
+/// the validators of a downloaded file, they are saved in `<outfile>.rget-meta` to ask the server
+/// whether the file changed the next time
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileMeta {
+    pub(crate) url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl FileMeta {
+    fn path(outfile: &str) -> String {
+        format!("{outfile}.rget-meta")
+    }
+
+    /// the validators the server sent for `url`, none if it sent neither
+    pub(crate) fn from_headers(url: &str, headers: &HeaderMap) -> Option<Self> {
+        let header = |name| Some(headers.get(name)?.to_str().ok()?.to_string());
+        let meta = FileMeta {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        (meta.etag.is_some() || meta.last_modified.is_some()).then_some(meta)
+    }
+
+    /// the meta of an earlier download of `url` to `outfile`, as long as the file is still there
+    fn load(outfile: &str, url: &str) -> Option<Self> {
+        if !Path::new(outfile).exists() {
+            return None;
+        }
+        let file = File::open(FileMeta::path(outfile)).ok()?;
+        let meta: FileMeta = serde_json::from_reader(BufReader::new(file)).ok()?;
+        (meta.url == url).then_some(meta)
+    }
+
+    fn save(&self, outfile: &str) -> Result<(), RgetError> {
+        let mut file = BufWriter::new(File::create(FileMeta::path(outfile))?);
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// makes `request` conditional, the server answers 304 when the file did not change
+    pub(crate) fn conditional(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// parses a size like `4096`, `500K`, `1.5M` or `2GiB`, the units are powers of 1024
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {size:?}"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let exponent = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(format!("invalid size unit {unit:?}, use K, M, G or T")),
+    };
+    Ok((number * 1024f64.powi(exponent)) as u64)
+}
+
+/// parses a gzip level from 0 to 9, `store` is 0 and keeps the data as it is in the gzip file
+pub fn parse_compression_level(level: &str) -> Result<u32, String> {
+    match level.trim() {
+        "store" => Ok(0),
+        level => level
+            .parse()
+            .ok()
+            .filter(|level| *level <= 9)
+            .ok_or_else(|| format!("invalid compression level {level:?}, use 0 to 9 or store")),
+    }
+}
+
+/// checks that `template` is a valid indicatif progress bar template
+pub fn parse_progress_template(template: &str) -> Result<String, String> {
+    ProgressStyle::with_template(template)
+        .map(|_| template.to_string())
+        .map_err(|e| format!("invalid progress template: {e}"))
+}
+
+/// checks that `chars` can draw a progress bar, the filled, the current and the empty part of the
+/// bar need at least 2 characters of the same width
+pub fn parse_progress_chars(chars: &str) -> Result<String, String> {
+    let mut widths = chars.chars().map(|c| c.width().unwrap_or(0));
+    let first = widths.next();
+    if chars.chars().count() < 2 {
+        return Err("at least 2 progress characters are needed".to_string());
+    }
+    if widths.any(|width| Some(width) != first) {
+        return Err("the progress characters need to have the same width".to_string());
+    }
+    Ok(chars.to_string())
+}
+
+/// fails when a file of `size` bytes is larger than the --max-filesize limit
+pub(crate) fn check_filesize(
+    url: &str,
+    size: u64,
+    max_filesize: Option<u64>,
+) -> Result<(), RgetError> {
+    match max_filesize {
+        Some(max) if size > max => Err(RgetError::TooLarge {
+            url: url.to_string(),
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// returns the path the download should actually be written to according to the clobber policy
+pub(crate) fn resolve_outfile(outfile: &str, options: &DownloadOptions) -> String {
+    if !Path::new(outfile).exists() {
+        return outfile.to_string();
+    }
+    match options.clobber {
+        ClobberPolicy::Force => outfile.to_string(),
+        ClobberPolicy::Warn => {
+            options.suspend(|| eprintln!("warning: overwriting existing file {outfile}"));
+            outfile.to_string()
+        }
+        ClobberPolicy::NoClobber => free_outfile(outfile),
+    }
+}
+
+/// the first of `outfile`, `<outfile>.1`, `<outfile>.2`, ... that does not exist yet
+fn free_outfile(outfile: &str) -> String {
+    [outfile.to_string()]
+        .into_iter()
+        .chain((1..).map(|i| format!("{outfile}.{i}")))
+        .find(|candidate| !Path::new(candidate).exists())
+        .expect("there is always a free file name")
+}
+
+/// `outfile` with the extension of `content_type` appended when extensions are added and it has
+/// none, e.g. `index` of a `text/html` page becomes `index.html`
+pub(crate) fn with_extension(
+    outfile: &str,
+    content_type: &ContentType,
+    options: &DownloadOptions,
+) -> String {
+    match content_type.extension() {
+        Some(ext) if options.add_extension && Path::new(outfile).extension().is_none() => {
+            format!("{outfile}.{ext}")
+        }
+        _ => outfile.to_string(),
+    }
+}
+
+/// whether the name of `outfile` depends on the answer of the server, with extensions added it
+/// needs one when it has none and with --trust-server-names its redirects may rename it
+pub(crate) fn named_by_server(outfile: &str, options: &DownloadOptions) -> bool {
+    options.trust_server_names || options.add_extension && Path::new(outfile).extension().is_none()
+}
+
+/// the answer to a HEAD request for `url` when the name of its file `outfile` depends on it, none
+/// for data urls and urls that cannot be asked
+pub(crate) async fn name_head(
+    client: &HttpClient,
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Option<Response> {
+    if !named_by_server(outfile, options) || is_data_url(url) {
+        return None;
+    }
+    client
+        .send(client.head(url))
+        .await
+        .and_then(|r| r.error_for_status())
+        .ok()
+}
+
+/// `outfile` of `url` named after the `head` response of the server, for names that are decided
+/// before the download starts: after the url its redirects ended at, see [`server_name`], and
+/// with the extension of its content type. The content type of a data url is part of the url
+pub(crate) fn head_name(
+    outfile: &str,
+    url: &str,
+    head: Option<&Response>,
+    options: &DownloadOptions,
+) -> String {
+    if is_data_url(url) {
+        return match data_url::decode(url) {
+            Ok((mime, _)) => with_extension(outfile, &ContentType::from_mime_str(&mime), options),
+            Err(_) => outfile.to_string(),
+        };
+    }
+    match head {
+        Some(head) if named_by_server(outfile, options) => {
+            let outfile = server_name(outfile, url, head.url(), options);
+            let content_type = ContentType::from_header_value(head.headers().get(CONTENT_TYPE));
+            with_extension(&outfile, &content_type, options)
+        }
+        _ => outfile.to_string(),
+    }
+}
+
+/// `outfile` renamed after the `final_url` the redirects of `url` ended at with
+/// --trust-server-names, e.g. `download?id=3` that forwards to `/files/report.pdf` is saved as
+/// `report.pdf`
+pub(crate) fn server_name(
+    outfile: &str,
+    url: &str,
+    final_url: &Url,
+    options: &DownloadOptions,
+) -> String {
+    if !options.trust_server_names || final_url.as_str() == url {
+        return outfile.to_string();
+    }
+    let name = derived_file_name(final_url.as_str(), options.decode_query);
+    Path::new(outfile)
+        .with_file_name(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// name of the file a download is written to until it is complete
+pub(crate) fn part_file(outfile: &str) -> String {
+    format!("{outfile}.part")
+}
+
+/// the Content-Length header, `Response::content_length` is always 0 for responses to HEAD
+pub(crate) fn header_content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// a url to download together with the file name it should be saved as
+#[derive(Debug, PartialEq, Eq)]
+pub struct Target {
+    pub url: String,
+    /// derived from the url when not set
+    pub outfile: Option<String>,
+}
+
+impl Target {
+    pub fn new(url: &str) -> Self {
+        Target {
+            url: url.to_string(),
+            outfile: None,
+        }
+    }
+}
+
+/// parses an input file with one `url` or `url<TAB>outfile` per line
+pub fn parse_input_file(content: &str) -> Vec<Target> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('\t') {
+            Some((url, outfile)) => Target {
+                url: url.trim().to_string(),
+                outfile: Some(outfile.trim().to_string()),
+            },
+            None => Target::new(line),
+        })
+        .collect()
+}
+
+/// the name of the `index`th target derived from its `url`, with the template if there is one
+fn derived_name(url: &str, index: usize, options: &DownloadOptions) -> String {
+    match &options.output_template {
+        Some(template) => template.file_name(url, index),
+        None => derived_file_name(url, options.decode_query),
+    }
+}
+
+/// file names for all targets, derived names get their extension from the `heads` of the targets
+/// first, see [`head_name`]. Derived names that would repeat get a `.<n>` suffix so parallel
+/// downloads never write the same file
+fn outfile_names(
+    targets: &[Target],
+    options: &DownloadOptions,
+    heads: &[Option<Response>],
+) -> Vec<String> {
+    let mut used: HashSet<String> = targets.iter().filter_map(|t| t.outfile.clone()).collect();
+    targets
+        .iter()
+        .enumerate()
+        .map(|(i, target)| {
+            if let Some(outfile) = &target.outfile {
+                return outfile.clone();
+            }
+            let name = derived_name(&target.url, i + 1, options);
+            let head = heads.get(i).and_then(Option::as_ref);
+            unique_name(head_name(&name, &target.url, head, options), &mut used)
+        })
+        .collect()
+}
+
+/// `name` if it is not in `used` yet, otherwise the first free `<name>.<n>`, the result is added
+/// to `used`
+pub fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    let mut candidate = name.clone();
+    let mut i = 1;
+    while !used.insert(candidate.clone()) {
+        candidate = format!("{name}.{i}");
+        i += 1;
+    }
+    candidate
+}
+
+/// creates the directories of `outfile` that do not exist yet
+pub fn create_parent_dir(outfile: &str) -> std::io::Result<()> {
+    match Path::new(outfile).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// the directories a file could be created in, they are not checked again
+static WRITABLE_DIRS: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(Mutex::default);
+
+/// makes sure `outfile` can be created before anything is requested, so a wrong path does not
+/// fail only once the body arrives. The missing directory is created with `create_dirs`
+pub(crate) fn check_outfile_dir(outfile: &str, options: &DownloadOptions) -> Result<(), RgetError> {
+    let dir = match Path::new(outfile).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let error = |reason: String| RgetError::OutputDir {
+        path: outfile.to_string(),
+        reason,
+    };
+    match fs::metadata(dir) {
+        Ok(meta) if !meta.is_dir() => {
+            return Err(error(format!("{} is not a directory", dir.display())));
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && options.create_dirs => {
+            fs::create_dir_all(dir)
+                .map_err(|e| error(format!("failed to create {}: {e}", dir.display())))?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(error(format!(
+                "the directory {} does not exist, create it or pass --create-dirs",
+                dir.display()
+            )));
+        }
+        Err(e) => return Err(error(format!("{}: {e}", dir.display()))),
+    }
+    // permissions alone do not tell, e.g. for read only mounts, so try to create a file once for
+    // every directory of the run
+    let dir = dir.to_path_buf();
+    if WRITABLE_DIRS.lock().unwrap().contains(&dir) {
+        return Ok(());
+    }
+    let probe = dir.join(format!(".rget-write-check-{}", std::process::id()));
+    match OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            WRITABLE_DIRS.lock().unwrap().insert(dir);
+            Ok(())
+        }
+        // another download of the run is checking the same directory right now
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(error(format!(
+            "the directory {} is not writable: {e}",
+            dir.display()
+        ))),
+    }
+}
+
+/// downloads every target with at most `parallel` downloads at a time and prints a summary, a
+/// failing download does not stop the others
+pub async fn download_all(
+    client: &HttpClient,
+    targets: &[Target],
+    parallel: usize,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let start = Instant::now();
+    // the names are decided before the downloads start, so two files never end up with the same
+    // one. Only the names derived from the urls get an extension or the name of the server, the
+    // ones of the input file are kept
+    let heads: Vec<_> = stream::iter(targets.iter().enumerate())
+        .map(|(i, target)| async move {
+            match &target.outfile {
+                Some(_) => None,
+                None => {
+                    let name = derived_name(&target.url, i + 1, options);
+                    name_head(client, &target.url, &name, options).await
+                }
+            }
+        })
+        .buffered(parallel)
+        .collect()
+        .await;
+    let outfiles = outfile_names(targets, options, &heads);
+    let options = &DownloadOptions {
+        add_extension: false,
+        trust_server_names: false,
+        ..options.clone()
+    };
+    let results: Vec<_> = stream::iter(targets.iter().zip(&outfiles))
+        .map(|(target, outfile)| async move {
+            let url = &target.url;
+            // the directories of derived names come from the template, the ones of the input
+            // file are only created with `create_dirs`
+            let created = match target.outfile {
+                Some(_) => Ok(()),
+                None => create_parent_dir(outfile),
+            };
+            let result = match created {
+                Ok(()) => download(client, url, outfile, options).await,
+                Err(e) => Err(e.into()),
+            };
+            (url, result)
+        })
+        .buffer_unordered(parallel)
+        .collect()
+        .await;
+
+    let mut written = 0;
+    let mut failures = Vec::new();
+    for (url, result) in results {
+        match result {
+            Ok(bytes) => written += bytes,
+            Err(e) => failures.push((url.clone(), e)),
+        }
+    }
+    report_downloads(targets.len(), &failures, written, start.elapsed())
+}
+
+/// prints how many of `total` downloads failed and why together with the bytes `written` in
+/// `elapsed`, an error if any download failed
+pub(crate) fn report_downloads(
+    total: usize,
+    failures: &[(String, RgetError)],
+    written: u64,
+    elapsed: Duration,
+) -> Result<(), RgetError> {
+    println!(
+        "{} downloaded, {} failed, {} written in {:.1}s",
+        total - failures.len(),
+        failures.len(),
+        HumanBytes(written),
+        elapsed.as_secs_f64()
+    );
+    for (url, e) in failures {
+        eprintln!("failed to download {url}: {e}");
+    }
+    if !failures.is_empty() {
+        return Err(RgetError::DownloadsFailed {
+            failed: failures.len(),
+            total,
+        });
+    }
+    Ok(())
+}
+
+/// downloads `url` to `outfile`, the file is written to `<outfile>.part` and renamed once it is
+/// complete
+///
+/// returns the number of bytes written, 0 when the file is filtered out or up to date
+pub async fn download(
+    client: &HttpClient,
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    download_mirrors(client, &[url.to_string()], outfile, options).await
+}
+
+/// downloads the file from the first of `urls` that works, the others are mirrors of it that are
+/// tried in order when the ones before fail
+///
+/// the mirror the file came from is printed, the error of the last one is returned when none
+/// works
+pub async fn download_mirrors(
+    client: &HttpClient,
+    urls: &[String],
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    let mut result = Err(RgetError::Config("there is no url to download".to_string()));
+    for (i, url) in urls.iter().enumerate() {
+        if let (Some(previous), Err(e)) = (i.checked_sub(1), &result) {
+            let previous = &urls[previous];
+            options.suspend(|| eprintln!("failed to download {previous}: {e}, trying {url}"));
+        }
+        result = download_file(client, url, outfile, options).await;
+        if result.is_ok() {
+            if urls.len() > 1 {
+                options.suspend(|| println!("downloaded {outfile} from {url}"));
+            }
+            break;
+        }
+    }
+    if let Some(stats) = &options.stats {
+        match result {
+            Ok(_) => stats.downloaded(),
+            Err(_) => stats.failed(),
+        }
+    }
+    result
+}
+
+async fn download_file(
+    client: &HttpClient,
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    check_outfile_dir(outfile, options)?;
+    if let Some(quota) = &options.quota {
+        quota.check(url)?;
+    }
+    if is_data_url(url) {
+        return data_url::download(url, outfile, options).await;
+    }
+    let url = &normalize_url(url)?;
+    if options.filter.allows(url, None) == Some(false) {
+        options.suspend(|| println!("skipping {url}, it is filtered out"));
+        return Ok(0);
+    }
+    if url.starts_with("ftp://") {
+        return ftp::download(url, outfile, options).await;
+    }
+    #[cfg(unix)]
+    if let Some(socket) = &options.unix_socket {
+        return unix::download(client, socket, url, outfile, options).await;
+    }
+    #[cfg(not(unix))]
+    if options.unix_socket.is_some() {
+        return Err(RgetError::Config(
+            "--unix-socket is only supported on unix".to_string(),
+        ));
+    }
+
+    // a slice of the file can neither be compared with nor split like the whole file
+    let get = options.method == Method::GET && options.byte_range.is_none();
+    // -N and --etag compare with the file the download is saved as, so a name that depends on the
+    // server is asked for first
+    let ask = options.timestamping || options.etag && named_by_server(outfile, options);
+    let head = match get && ask {
+        true => client
+            .send(client.head(url))
+            .await
+            .and_then(|r| r.error_for_status())
+            .ok(),
+        false => None,
+    };
+    let outfile = &head_name(outfile, url, head.as_ref(), options);
+    // the name is decided, the answer to the download does not change it again
+    let decided;
+    let options = match head {
+        Some(_) => {
+            decided = DownloadOptions {
+                add_extension: false,
+                trust_server_names: false,
+                ..options.clone()
+            };
+            &decided
+        }
+        None => options,
+    };
+    if options.timestamping && get && is_up_to_date(outfile, head.as_ref()) {
+        options.suspend(|| println!("not retrieving {outfile}, file is up to date"));
+        return Ok(0);
+    }
+    // with --no-clobber the file is saved next to an existing one, the meta is loaded from the
+    // same path it is saved to
+    let meta = if options.etag && get {
+        match options.clobber {
+            ClobberPolicy::NoClobber => FileMeta::load(&free_outfile(outfile), url),
+            _ => FileMeta::load(outfile, url),
+        }
+    } else {
+        None
+    };
+
+    // a file that was downloaded before is checked with a single conditional request, ranges
+    // cannot be written into a compressed file or decoded as text
+    if options.connections > 1 && get && meta.is_none() && !options.compress && !options.text {
+        if let Some((total_size, headers, final_url)) = range_support(client, url).await {
+            check_filesize(url, total_size, options.max_filesize)?;
+            let content_type = ContentType::from_header_value(headers.get(CONTENT_TYPE));
+            let outfile = &server_name(outfile, url, &final_url, options);
+            let outfile = &with_extension(outfile, &content_type, options);
+            let outfile = &resolve_outfile(outfile, options);
+            reserved(options, url, total_size, async {
+                download_ranges(client, url, outfile, total_size, options).await
+            })
+            .await?;
+            finish_file(options, url, outfile, None, Some(&headers)).await?;
+            return Ok(total_size);
+        }
+        options.suspend(|| {
+            println!("{url} does not support range requests, downloading over one connection")
+        });
+    }
+
+    let mut request = client.download(options.method.clone(), url);
+    if let Some(referer) = &options.referer {
+        request = request.header(REFERER, referer);
+    }
+    if let Some(body) = &options.body {
+        request = request.body(body.clone());
+    }
+    if let Some(range) = &options.byte_range {
+        // ranges refer to the encoded body, so ask for the plain one
+        request = request
+            .header(RANGE, range.header())
+            .header(ACCEPT_ENCODING, "identity");
+    }
+    if let Some(meta) = &meta {
+        request = meta.conditional(request);
+    }
+    let mut response = client.send(request).await?.error_for_status()?;
+    print_response(options, url, response.version(), response.status());
+    if response.status() == StatusCode::NOT_MODIFIED {
+        options.suspend(|| println!("{outfile} is up to date"));
+        return Ok(0);
+    }
+    // a server that does not support ranges sends the whole file instead
+    if options.byte_range.is_some() && response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(RgetError::RangeNotSatisfied {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+    let headers = response.headers().clone();
+
+    let mime = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let content_type = ContentType::from_header_value(response.headers().get(CONTENT_TYPE));
+    if options.filter.allows(url, Some((&content_type, mime))) == Some(false) {
+        options.suspend(|| println!("skipping {url}, content type {mime:?} is filtered out"));
+        return Ok(0);
+    }
+    if options.text && !content_type.is_text() {
+        return Err(RgetError::NotText {
+            url: url.to_string(),
+            content_type: content_type.to_mime_str().to_string(),
+        });
+    }
+    let total_size = match response.status() {
+        StatusCode::NO_CONTENT => Some(0),
+        _ => response.content_length(),
+    };
+    if let Some(ts) = total_size {
+        check_filesize(url, ts, options.max_filesize)?;
+    }
+    let outfile = &server_name(outfile, url, response.url(), options);
+    let outfile = &with_extension(outfile, &content_type, options);
+    let outfile = &resolve_outfile(outfile, options);
+
+    let (written, digest) = match total_size {
+        _ if options.text => save_text(outfile, response, total_size, options).await?,
+        Some(0) => (0, save_empty(url, outfile, options)?),
+        Some(ts) => {
+            let download = download_pb(outfile, ts, &mut response, options);
+            (ts, reserved(options, url, ts, download).await?)
+        }
+        None => download_sp(outfile, response, options).await?,
+    };
+    let headers = get.then_some(&headers);
+    finish_file(options, url, outfile, Some(digest), headers).await?;
+    Ok(written)
+}
+
+/// prints the http `version` and the `status` the server answered `url` with for
+/// [`DownloadOptions::verbose`]
+pub(crate) fn print_response(
+    options: &DownloadOptions,
+    url: &str,
+    version: Version,
+    status: StatusCode,
+) {
+    if options.verbose {
+        options.suspend(|| println!("{url}: {version:?} {status}"));
+    }
+}
+
+/// the steps after the file of `url` is saved to `outfile`: a duplicate becomes a link, the
+/// validators in the `headers` of a whole file are kept for the next download and at last
+/// [`after_save`] runs. Without the `digest` of the content the file is read again for it
+pub(crate) async fn finish_file(
+    options: &DownloadOptions,
+    url: &str,
+    outfile: &str,
+    digest: Option<Digest>,
+    headers: Option<&HeaderMap>,
+) -> Result<(), RgetError> {
+    if let Some(index) = &options.dedupe {
+        let digest = match digest {
+            Some(digest) => digest,
+            None => file_digest(outfile)?,
+        };
+        link_duplicate(options, index, outfile, digest)?;
+    }
+    if let Some(headers) = headers {
+        save_meta(options, url, headers, outfile)?;
+        set_modified(options, headers, outfile)?;
+    }
+    after_save(options, outfile).await
+}
+
+/// runs the `download` of a file of `size` bytes with them reserved in the quota, a download that
+/// fails gives them back
+async fn reserved<T>(
+    options: &DownloadOptions,
+    url: &str,
+    size: u64,
+    download: impl Future<Output = Result<T, RgetError>>,
+) -> Result<T, RgetError> {
+    let Some(quota) = &options.quota else {
+        return download.await;
+    };
+    quota.reserve(url, size)?;
+    let result = download.await;
+    if result.is_err() {
+        quota.release(size);
+    }
+    result
+}
+
+/// the Last-Modified header as a time
+fn last_modified(headers: &HeaderMap) -> Option<SystemTime> {
+    httpdate::parse_http_date(headers.get(LAST_MODIFIED)?.to_str().ok()?).ok()
+}
+
+/// whether `outfile` exists and is at least as new as the file of the `head` response, files the
+/// server does not give a Last-Modified for are never up to date
+fn is_up_to_date(outfile: &str, head: Option<&Response>) -> bool {
+    let Ok(local) = fs::metadata(outfile).and_then(|meta| meta.modified()) else {
+        return false;
+    };
+    match head.and_then(|head| last_modified(head.headers())) {
+        Some(remote) => local >= remote,
+        None => false,
+    }
+}
+
+/// gives a downloaded file the Last-Modified time of the server when timestamping
+fn set_modified(
+    options: &DownloadOptions,
+    headers: &HeaderMap,
+    outfile: &str,
+) -> Result<(), RgetError> {
+    match last_modified(headers) {
+        Some(modified) if options.timestamping => {
+            filetime::set_file_mtime(outfile, FileTime::from_system_time(modified))?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// finishes the saved `outfile`: the --exec command runs on it and with --delete-after it is
+/// removed again, only complete files get here so a `.part` file is never removed
+pub(crate) async fn after_save(options: &DownloadOptions, outfile: &str) -> Result<(), RgetError> {
+    run_exec(options, outfile).await;
+    if options.delete_after {
+        fs::remove_file(outfile)?;
+    }
+    Ok(())
+}
+
+/// runs the --exec command for the saved `outfile`, a failing command is reported but the file
+/// stays downloaded
+async fn run_exec(options: &DownloadOptions, outfile: &str) {
+    let Some(command) = &options.exec else {
+        return;
+    };
+    let command = command.replace("{}", &shell_quote(outfile));
+    match shell(&command).status().await {
+        Ok(status) if status.success() => {}
+        Ok(status) => options.suspend(|| eprintln!("warning: `{command}` failed with {status}")),
+        Err(e) => options.suspend(|| eprintln!("warning: failed to run `{command}`: {e}")),
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> tokio::process::Command {
+    let mut shell = tokio::process::Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// quotes `path` so the shell passes it on as a single argument
+#[cfg(unix)]
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(path: &str) -> String {
+    format!("\"{path}\"")
+}
+
+/// replaces `outfile` with a hard link to the first file of the run with the same content, the
+/// copy is kept when the link fails, e.g. across file systems
+pub(crate) fn link_duplicate(
+    options: &DownloadOptions,
+    index: &ContentIndex,
+    outfile: &str,
+    digest: Digest,
+) -> Result<(), RgetError> {
+    let original = index.original(digest, outfile);
+    if original == outfile {
+        return Ok(());
+    }
+    // the link is renamed over the outfile so the outfile never goes missing
+    let link = format!("{outfile}.rget-link");
+    if fs::hard_link(&original, &link).is_err() {
+        return Ok(());
+    }
+    fs::rename(&link, outfile)?;
+    options
+        .suspend(|| println!("{outfile} has the same content as {original}, saved as a hard link"));
+    Ok(())
+}
+
+fn file_digest(path: &str) -> Result<Digest, RgetError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    loop {
+        let buf = file.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(buf);
+        let len = buf.len();
+        file.consume(len);
+    }
+}
+
+/// remembers the validators of a finished download for the next conditional request
+fn save_meta(
+    options: &DownloadOptions,
+    url: &str,
+    headers: &HeaderMap,
+    outfile: &str,
+) -> Result<(), RgetError> {
+    match FileMeta::from_headers(url, headers) {
+        Some(meta) if options.etag => meta.save(outfile),
+        _ => Ok(()),
+    }
+}
+
+/// the size of the file at `url`, the headers of the HEAD response and the url its redirects
+/// ended at if the server supports byte range requests for it
+async fn range_support(client: &HttpClient, url: &str) -> Option<(u64, HeaderMap, Url)> {
+    // ranges refer to the encoded body, so ask for the plain one
+    let head = client
+        .send(client.head(url).header(ACCEPT_ENCODING, "identity"))
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    let ranges = head.headers().get(ACCEPT_RANGES)?.to_str().ok()?;
+    if !ranges.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let size = header_content_length(head.headers()).filter(|&size| size > 0)?;
+    Some((size, head.headers().clone(), head.url().clone()))
+}
+
+/// splits `total_size` bytes into at most `count` inclusive ranges of nearly equal size
+fn byte_ranges(total_size: u64, count: usize) -> Vec<(u64, u64)> {
+    let size = total_size.div_ceil(count as u64).max(1);
+    (0..total_size)
+        .step_by(size as usize)
+        .map(|start| (start, (start + size).min(total_size) - 1))
+        .collect()
+}
+
+/// downloads the file in byte ranges over `connections` parallel requests, every range is written
+/// at its offset of the preallocated part file
+async fn download_ranges(
+    client: &HttpClient,
+    url: &str,
+    outfile: &str,
+    total_size: u64,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let part = part_file(outfile);
+    create_part(&part, total_size)?;
+    // the ranges arrive side by side, the sidecar keeps how far each one got
+    let checkpoint = Mutex::new(Checkpoint::start(
+        &part,
+        url,
+        Some(total_size),
+        false,
+        options,
+    )?);
+    let pb = DownloadProgress::new(url, Some(total_size), options);
+
+    let ranges = byte_ranges(total_size, options.connections);
+    let downloads = ranges.into_iter().map(|(start, end)| {
+        let referer = options.referer.as_deref();
+        download_range(client, url, referer, &part, (start, end), &pb, &checkpoint)
+    });
+    future::try_join_all(downloads).await?;
+    fs::rename(&part, outfile)?;
+    checkpoint.into_inner().unwrap().finish()?;
+
+    pb.finish(outfile);
+    Ok(())
+}
+
+/// downloads the inclusive range from `start` to `end` into the `part` file, how far it got is
+/// written to the `checkpoint` every [`CHECKPOINT_INTERVAL`]
+async fn download_range(
+    client: &HttpClient,
+    url: &str,
+    referer: Option<&str>,
+    part: &str,
+    (start, end): (u64, u64),
+    pb: &DownloadProgress,
+    checkpoint: &Mutex<Checkpoint>,
+) -> Result<(), RgetError> {
+    let mut request = client
+        .get(url)
+        .header(RANGE, format!("bytes={start}-{end}"))
+        .header(ACCEPT_ENCODING, "identity");
+    if let Some(referer) = referer {
+        request = request.header(REFERER, referer);
+    }
+    let mut response = client.send(request).await?.error_for_status()?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(RgetError::RangeNotSatisfied {
+            url: url.to_string(),
+            status: response.status(),
+        });
+    }
+
+    let mut file = OpenOptions::new().write(true).open(part)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut dest = BufWriter::new(file);
+    let mut at = start;
+    let mut saved = Instant::now();
+    while let Some(chunk) = response.chunk().await? {
+        dest.write_all(chunk.as_ref())?;
+        at += chunk.len() as u64;
+        pb.inc(chunk.len() as u64);
+        if saved.elapsed() >= CHECKPOINT_INTERVAL {
+            // the sidecar must never claim more than is in the file
+            dest.flush()?;
+            checkpoint.lock().unwrap().range_written(start, at)?;
+            saved = Instant::now();
+        }
+    }
+    dest.flush()?;
+    checkpoint.lock().unwrap().range_written(start, at)?;
+    Ok(())
+}
+
+async fn download_pb(
+    outfile: &str,
+    total_size: u64,
+    response: &mut Response,
+    options: &DownloadOptions,
+) -> Result<Digest, RgetError> {
+    let url = response.url().to_string();
+    let part = part_file(outfile);
+    // the compressed size is not known up front
+    let file = match options.compress {
+        true => File::create(&part)?,
+        false => create_part(&part, total_size)?,
+    };
+    let encoded = response.headers().contains_key(CONTENT_ENCODING);
+    let checkpoint = Checkpoint::start(&part, &url, Some(total_size), encoded, options)?;
+    let writer = PipelinedWriter::spawn(FileWriter::new(file, options.compression()), checkpoint);
+    let pb = DownloadProgress::new(&url, Some(total_size), options);
+
+    let received = async {
+        while let Some(chunk) = response.chunk().await? {
+            pb.inc(chunk.len() as u64);
+            if !writer.write(chunk).await {
+                break;
+            }
+        }
+        Ok::<_, RgetError>(())
+    }
+    .await;
+    // what arrived before a failing read is still written, the part can be resumed from there
+    let written = writer.finish().await?;
+    received?;
+    written.dest.finish()?;
+    if written.size != total_size {
+        return Err(RgetError::SizeMismatch {
+            url,
+            expected: total_size,
+            actual: written.size,
+        });
+    }
+    fs::rename(&part, outfile)?;
+    written.checkpoint.finish()?;
+
+    pb.finish(outfile);
+    Ok(written.digest)
+}
+
+/// how many chunks the network may read ahead of the disk, a few MiB with the chunks of reqwest
+const WRITE_AHEAD: usize = 64;
+
+/// writes the chunks of a download on a blocking thread, so reading from the network does not
+/// stall while the disk is busy until [`WRITE_AHEAD`] chunks wait to be written
+pub(crate) struct PipelinedWriter<W> {
+    chunks: mpsc::Sender<Bytes>,
+    writer: JoinHandle<Result<Written<W>, RgetError>>,
+}
+
+/// what the [`PipelinedWriter`] hands back once every chunk is written
+pub(crate) struct Written<W> {
+    /// still has to be finished, it is only flushed for the checkpoints
+    pub(crate) dest: W,
+    pub(crate) checkpoint: Checkpoint,
+    pub(crate) digest: Digest,
+    pub(crate) size: u64,
+}
+
+impl<W: Write + Send + 'static> PipelinedWriter<W> {
+    /// starts the thread writing to `dest`, it hashes the chunks and updates the `checkpoint` as
+    /// well
+    pub(crate) fn spawn(mut dest: W, mut checkpoint: Checkpoint) -> Self {
+        let (chunks, mut queue) = mpsc::channel::<Bytes>(WRITE_AHEAD);
+        let writer = tokio::task::spawn_blocking(move || {
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            while let Some(chunk) = queue.blocking_recv() {
+                dest.write_all(&chunk)?;
+                hasher.update(&chunk);
+                size += chunk.len() as u64;
+                checkpoint.update(&mut dest, size)?;
+            }
+            Ok(Written {
+                dest,
+                checkpoint,
+                digest: hasher.finalize().into(),
+                size,
+            })
+        });
+        PipelinedWriter { chunks, writer }
+    }
+
+    /// queues `chunk` and only waits while the queue is full. False once the writer failed,
+    /// [`PipelinedWriter::finish`] returns its error
+    pub(crate) async fn write(&self, chunk: Bytes) -> bool {
+        self.chunks.send(chunk).await.is_ok()
+    }
+
+    /// waits until the queued chunks are written
+    pub(crate) async fn finish(self) -> Result<Written<W>, RgetError> {
+        drop(self.chunks);
+        match self.writer.await {
+            Ok(written) => written,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// the file a download is written to, gzip compressed with [`DownloadOptions::compress`]
+pub(crate) enum FileWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl FileWriter {
+    /// `compression` is the gzip level of the file, see [`DownloadOptions::compression`]
+    pub(crate) fn new(file: File, compression: Option<Compression>) -> Self {
+        let file = BufWriter::new(file);
+        match compression {
+            Some(level) => FileWriter::Gzip(GzEncoder::new(file, level)),
+            None => FileWriter::Plain(file),
+        }
+    }
+
+    /// writes what is still buffered and the gzip trailer, dropping the writer would swallow a
+    /// failing final write
+    pub(crate) fn finish(self) -> std::io::Result<()> {
+        match self {
+            FileWriter::Plain(mut file) => file.flush(),
+            FileWriter::Gzip(gzip) => gzip.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FileWriter::Plain(file) => file.write(buf),
+            FileWriter::Gzip(gzip) => gzip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FileWriter::Plain(file) => file.flush(),
+            FileWriter::Gzip(gzip) => gzip.flush(),
+        }
+    }
+}
+
+/// creates the `part` file with `size` bytes reserved on disk, so a full disk fails the download
+/// before it starts instead of halfway through. The file is removed again when there is no room
+fn create_part(part: &str, size: u64) -> Result<File, RgetError> {
+    let file = File::create(part)?;
+    if let Err(e) = preallocate(&file, part, size) {
+        drop(file);
+        fs::remove_file(part)?;
+        return Err(e);
+    }
+    Ok(file)
+}
+
+/// file systems that cannot reserve space only get the size
+fn preallocate(file: &File, part: &str, size: u64) -> Result<(), RgetError> {
+    #[cfg(target_os = "linux")]
+    match rustix::fs::fallocate(file, rustix::fs::FallocateFlags::empty(), 0, size) {
+        Ok(()) => return Ok(()),
+        Err(rustix::io::Errno::NOSPC) => {
+            return Err(RgetError::NoSpace {
+                path: part.to_string(),
+                size,
+            });
+        }
+        Err(_) => {}
+    }
+    file.set_len(size).map_err(|e| match e.kind() {
+        std::io::ErrorKind::StorageFull => RgetError::NoSpace {
+            path: part.to_string(),
+            size,
+        },
+        _ => e.into(),
+    })
+}
+
+/// creates the empty `outfile` for a response without a body, there is no progress to show
+fn save_empty(url: &str, outfile: &str, options: &DownloadOptions) -> Result<Digest, RgetError> {
+    // an empty gzip file still has a header
+    FileWriter::new(File::create(outfile)?, options.compression()).finish()?;
+    options.suspend(|| println!("{url} sent an empty response, saved an empty {outfile}"));
+    Ok(Sha256::digest(b"").into())
+}
+
+/// the text of `body` in `charset`, utf-8 if there is none, with the whitespace around it removed
+/// and a single newline at its end
+///
+/// a byte order mark takes precedence over the charset, bytes that are invalid in it are replaced
+pub(crate) fn decode_text(
+    url: &str,
+    body: &[u8],
+    charset: Option<&str>,
+    convert_newlines: bool,
+) -> Result<String, RgetError> {
+    let encoding = match charset {
+        Some(label) => {
+            Encoding::for_label(label.as_bytes()).ok_or_else(|| RgetError::UnknownCharset {
+                url: url.to_string(),
+                charset: label.to_string(),
+            })?
+        }
+        None => UTF_8,
+    };
+    let (text, _, _) = encoding.decode(body);
+    let mut text = text.trim().to_string();
+    if convert_newlines {
+        text = text.replace("\r\n", "\n").replace('\r', "\n");
+    }
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// saves the text of a text response as utf-8 for --text, the body is only decoded once it is
+/// complete because a character can be split between two chunks
+async fn save_text(
+    outfile: &str,
+    mut response: Response,
+    total_size: Option<u64>,
+    options: &DownloadOptions,
+) -> Result<(u64, Digest), RgetError> {
+    let url = response.url().to_string();
+    let charset = ContentType::charset(response.headers().get(CONTENT_TYPE));
+    let pb = DownloadProgress::new(&url, total_size, options);
+    // every error abandons the bar
+    let saved = async {
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            check_filesize(&url, body.len() as u64, options.max_filesize)?;
+            pb.inc(chunk.len() as u64);
+        }
+        let text = decode_text(&url, &body, charset.as_deref(), options.convert_newlines)?;
+        let size = text.len() as u64;
+        reserved(options, &url, size, async {
+            write_text(outfile, &text, options)
+        })
+        .await?;
+        Ok((size, Sha256::digest(text.as_bytes()).into()))
+    }
+    .await;
+    match saved {
+        Ok(saved) => {
+            pb.finish(outfile);
+            Ok(saved)
+        }
+        Err(e) => {
+            pb.abandon();
+            Err(e)
+        }
+    }
+}
+
+/// writes `text` to its part file and renames it to `outfile`, the part file is removed again
+/// when that fails
+fn write_text(outfile: &str, text: &str, options: &DownloadOptions) -> Result<(), RgetError> {
+    let part = part_file(outfile);
+    let written = File::create(&part)
+        .and_then(|file| {
+            let mut dest = FileWriter::new(file, options.compression());
+            dest.write_all(text.as_bytes())?;
+            dest.finish()
+        })
+        .and_then(|()| fs::rename(&part, outfile));
+    if written.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    Ok(written?)
+}
+
+/// shows a spinner while downloading a file of unknown size, the download is aborted and the part
+/// file removed once it grows beyond `max_filesize` or the quota
+async fn download_sp(
+    outfile: &str,
+    mut response: Response,
+    options: &DownloadOptions,
+) -> Result<(u64, Digest), RgetError> {
+    let url = response.url().to_string();
+    // a chunked body can still turn out to be empty
+    let Some(first) = response.chunk().await? else {
+        return Ok((0, save_empty(&url, outfile, options)?));
+    };
+    let sp = DownloadProgress::new(&url, None, options);
+    let part = part_file(outfile);
+    let mut dest = FileWriter::new(File::create(&part)?, options.compression());
+    let encoded = response.headers().contains_key(CONTENT_ENCODING);
+    let mut checkpoint = Checkpoint::start(&part, &url, None, encoded, options)?;
+
+    let mut downloaded: u64 = 0;
+    let mut hasher = Sha256::new();
+
+    let mut next = Some(first);
+    while let Some(chunk) = next {
+        dest.write_all(chunk.as_ref())?;
+        hasher.update(&chunk);
+        downloaded += chunk.len() as u64;
+        let fits = match &options.quota {
+            Some(quota) => quota.reserve(&url, chunk.len() as u64),
+            None => Ok(()),
+        };
+        let reserved = match fits {
+            Ok(()) => downloaded,
+            Err(_) => downloaded - chunk.len() as u64,
+        };
+        if let Err(e) = fits.and(check_filesize(&url, downloaded, options.max_filesize)) {
+            drop(dest);
+            fs::remove_file(&part)?;
+            checkpoint.finish()?;
+            // the removed file takes no room, what it reserved is free again
+            if let Some(quota) = &options.quota {
+                quota.release(reserved);
+            }
+            sp.abandon();
+            return Err(e);
+        }
+        sp.inc(chunk.len() as u64);
+        checkpoint.update(&mut dest, downloaded)?;
+        next = response.chunk().await?;
+    }
+    dest.finish()?;
+    fs::rename(&part, outfile)?;
+    checkpoint.finish()?;
+
+    sp.finish(outfile);
+    Ok((downloaded, hasher.finalize().into()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use filetime::FileTime;
+    use flate2::Compression;
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use http::header::CONTENT_TYPE;
+    use http::{Method, StatusCode};
+    use reqwest::Response;
+    use sha2::{Digest, Sha256};
+    use wiremock::matchers::{body_string, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{
+        ByteRange, ClobberPolicy, DownloadOptions, FileMeta, OnProgress, PipelinedWriter, Quota,
+        Target, WRITABLE_DIRS, byte_ranges, create_part, decode_text, download, download_all,
+        download_mirrors, outfile_names, parse_compression_level, parse_input_file,
+        parse_progress_chars, parse_progress_template, parse_size, part_file, resolve_outfile,
+    };
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::error::RgetError;
+    use crate::resume::Checkpoint;
+    use crate::stats::Stats;
+    use crate::urls::OutputTemplate;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pipelined_writer() {
+        // an encoded download has no sidecar to keep up to date
+        let checkpoint = || {
+            Checkpoint::start(
+                "unused.part",
+                "url",
+                None,
+                true,
+                &DownloadOptions::default(),
+            )
+            .unwrap()
+        };
+        let body: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let writer = PipelinedWriter::spawn(Vec::new(), checkpoint());
+        for chunk in body.chunks(1000) {
+            assert!(writer.write(Bytes::copy_from_slice(chunk)).await);
+        }
+        let written = writer.finish().await.unwrap();
+        assert_eq!(written.dest, body);
+        assert_eq!(written.size, body.len() as u64);
+        assert_eq!(written.digest, <[u8; 32]>::from(Sha256::digest(&body)));
+
+        // a file opened for reading cannot be written, the error comes out of finish
+        let writer = PipelinedWriter::spawn(File::open("Cargo.toml").unwrap(), checkpoint());
+        for chunk in body.chunks(1000) {
+            if !writer.write(Bytes::copy_from_slice(chunk)).await {
+                break;
+            }
+        }
+        assert!(matches!(writer.finish().await, Err(RgetError::Io(_))));
+
+        // the network goes on while the disk is busy, every chunk is queued while the disk has
+        // not even written the first one
+        struct BlockedDisk(Vec<u8>, std::sync::mpsc::Receiver<()>);
+        impl Write for BlockedDisk {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                // blocks until the disk is opened by dropping the sender
+                let _ = self.1.recv();
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let (open, gate) = std::sync::mpsc::channel();
+        let writer = PipelinedWriter::spawn(BlockedDisk(Vec::new(), gate), checkpoint());
+        for chunk in body.chunks(12_500) {
+            let queued = writer.write(Bytes::copy_from_slice(chunk));
+            let queued = tokio::time::timeout(Duration::from_secs(10), queued).await;
+            assert!(matches!(queued, Ok(true)));
+        }
+        drop(open);
+        assert_eq!(writer.finish().await.unwrap().dest.0, body);
+    }
+
+    #[test]
+    fn test_byte_ranges() {
+        assert_eq!(byte_ranges(10, 1), [(0, 9)]);
+        assert_eq!(byte_ranges(10, 3), [(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(byte_ranges(10, 5), [(0, 1), (2, 3), (4, 5), (6, 7), (8, 9)]);
+        // never more ranges than bytes
+        assert_eq!(byte_ranges(2, 4), [(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_outfile_names() {
+        let targets = [
+            "http://localhost:3000/a/report.pdf",
+            "http://localhost:3000/",
+            "http://localhost:3000/b/report.pdf?v=2",
+            "http://localhost:3000/dir/",
+            "not a url",
+        ]
+        .map(Target::new);
+        let options = DownloadOptions::default();
+        let names = outfile_names(&targets, &options, &[]);
+        assert_eq!(
+            names[..4],
+            ["report.pdf", "index.html", "report.pdf.1", "index.html.1"]
+        );
+        assert!(!names[4].contains(' '));
+
+        // explicit names are kept and never handed out twice
+        let targets = [
+            Target::new("http://localhost:3000/a.txt"),
+            Target {
+                url: "http://localhost:3000/b".to_string(),
+                outfile: Some("a.txt".to_string()),
+            },
+        ];
+        assert_eq!(outfile_names(&targets, &options, &[]), ["a.txt.1", "a.txt"]);
+
+        // names from the query repeat like the others
+        let targets = [
+            "http://localhost:3000/get.php?file=report.pdf",
+            "http://localhost:3000/get.php?name=report.pdf",
+            "http://localhost:3000/get.php?id=3",
+        ]
+        .map(Target::new);
+        let decoded = DownloadOptions {
+            decode_query: true,
+            ..DownloadOptions::default()
+        };
+        assert_eq!(
+            outfile_names(&targets, &decoded, &[]),
+            ["report.pdf", "report.pdf.1", "get.php"]
+        );
+
+        // names get their extension before they are made unique
+        let targets = [
+            "http://localhost:3000/page?a=1",
+            "http://localhost:3000/page?a=2",
+            "http://localhost:3000/page.html",
+            "data:text/plain,a",
+        ]
+        .map(Target::new);
+        let html = || {
+            let head = http::Response::builder()
+                .header(CONTENT_TYPE, "text/html")
+                .body("")
+                .unwrap();
+            Some(Response::from(head))
+        };
+        let heads = [html(), html(), None, None];
+        let extended = DownloadOptions {
+            add_extension: true,
+            ..DownloadOptions::default()
+        };
+        assert_eq!(
+            outfile_names(&targets, &extended, &heads)[..3],
+            ["page.html", "page.html.1", "page.html.2"]
+        );
+        assert_eq!(
+            outfile_names(&targets, &options, &heads)[..2],
+            ["page", "page.1"]
+        );
+    }
+
+    #[test]
+    fn test_parse_input_file() {
+        let content = "\
+# mirror list
+http://localhost:3000/a
+
+  http://localhost:3000/b\tb.html
+";
+        assert_eq!(
+            parse_input_file(content),
+            [
+                Target::new("http://localhost:3000/a"),
+                Target {
+                    url: "http://localhost:3000/b".to_string(),
+                    outfile: Some("b.html".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_create_part() {
+        let part = temp_file("preallocated.part");
+        let file = create_part(&part, 4096).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 4096);
+        fs::remove_file(&part).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_outfile() {
+        let dir = std::env::temp_dir().join(format!("rget-clobber-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let outfile = dir.join("out").to_string_lossy().to_string();
+
+        let resolve = |clobber| {
+            let options = DownloadOptions {
+                clobber,
+                ..DownloadOptions::default()
+            };
+            resolve_outfile(&outfile, &options)
+        };
+
+        assert_eq!(resolve(ClobberPolicy::NoClobber), outfile);
+        File::create(&outfile).unwrap();
+        assert_eq!(resolve(ClobberPolicy::Force), outfile);
+        assert_eq!(resolve(ClobberPolicy::Warn), outfile);
+        assert_eq!(resolve(ClobberPolicy::NoClobber), format!("{outfile}.1"));
+        File::create(format!("{outfile}.1")).unwrap();
+        assert_eq!(resolve(ClobberPolicy::NoClobber), format!("{outfile}.2"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_progress_style() {
+        assert!(parse_progress_template("{wide_bar} {bytes}/{total_bytes}").is_ok());
+        assert!(parse_progress_template("{wide_bar:x}").is_err());
+        assert!(parse_progress_template("{}").is_err());
+        assert!(parse_progress_chars("=> ").is_ok());
+        assert!(parse_progress_chars("█▉▊▋▌▍▎▏ ").is_ok());
+        assert!(parse_progress_chars("#").is_err());
+        assert!(parse_progress_chars("#界").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500K"), Ok(500 * 1024));
+        assert_eq!(parse_size("100M"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("1.5m"), Ok(3 * 512 * 1024));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1 tb"), Ok(1 << 40));
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+    }
+
+    /// a client with the default flags
+    fn test_client() -> HttpClient {
+        HttpClient::new(&ClientArgs::default(), &[]).unwrap()
+    }
+
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rget-{name}-{}", std::process::id()));
+        path.to_string_lossy().to_string()
+    }
+
+    /// a body that cannot be mistaken for another one
+    fn test_body(len: usize) -> Vec<u8> {
+        (0..=255u8).cycle().take(len).collect()
+    }
+
+    #[tokio::test]
+    async fn test_download_with_length() {
+        let server = MockServer::start().await;
+        let body = test_body(10_000);
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.bin", server.uri());
+        let outfile = temp_file("with-length");
+        let client = test_client();
+
+        let written = download(&client, &url, &outfile, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(written, 10_000);
+        assert_eq!(fs::read(&outfile).unwrap(), body);
+        fs::remove_file(&outfile).unwrap();
+
+        // the Content-Length is known, so the download is refused before anything is written
+        let options = DownloadOptions {
+            max_filesize: Some(9_999),
+            ..DownloadOptions::default()
+        };
+        let result = download(&client, &url, &outfile, &options).await;
+        assert!(matches!(
+            result,
+            Err(RgetError::TooLarge { max: 9_999, .. })
+        ));
+        assert!(!Path::new(&outfile).exists());
+        assert!(!Path::new(&part_file(&outfile)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_stats() {
+        let server = MockServer::start().await;
+        Mock::given(path("/file.bin"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(test_body(1_000)))
+            .mount(&server)
+            .await;
+        let stats = Arc::new(Stats::default());
+        let client = test_client().with_stats(stats.clone());
+        let options = DownloadOptions {
+            stats: Some(stats.clone()),
+            ..DownloadOptions::default()
+        };
+        let outfile = temp_file("stats");
+
+        download(
+            &client,
+            &format!("{}/file.bin", server.uri()),
+            &outfile,
+            &options,
+        )
+        .await
+        .unwrap();
+        let missing = format!("{}/missing", server.uri());
+        assert!(
+            download(&client, &missing, &outfile, &options)
+                .await
+                .is_err()
+        );
+
+        let report = stats.report();
+        assert_eq!(report.urls_fetched, 2);
+        assert_eq!(report.files_downloaded, 1);
+        assert_eq!(report.bytes_downloaded, 1_000);
+        assert_eq!(report.retries, 1);
+        assert_eq!(report.failures, 1);
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_without_length() {
+        let server = MockServer::start().await;
+        let body = test_body(10_000);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+        // reqwest drops the Content-Length of a body it decompresses, so this takes the spinner
+        // path
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_bytes(encoder.finish().unwrap()),
+            )
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.bin", server.uri());
+        let outfile = temp_file("without-length");
+        let client = test_client();
+
+        let written = download(&client, &url, &outfile, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(written, 10_000);
+        assert_eq!(fs::read(&outfile).unwrap(), body);
+        fs::remove_file(&outfile).unwrap();
+
+        // the size is only known while streaming, the partial file is removed
+        let options = DownloadOptions {
+            max_filesize: Some(9_999),
+            ..DownloadOptions::default()
+        };
+        let result = download(&client, &url, &outfile, &options).await;
+        assert!(matches!(
+            result,
+            Err(RgetError::TooLarge { max: 9_999, .. })
+        ));
+        assert!(!Path::new(&outfile).exists());
+        assert!(!Path::new(&part_file(&outfile)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_quota() {
+        let server = MockServer::start().await;
+        for (file, len) in [("/a.bin", 1_000), ("/b.bin", 1_000), ("/c.bin", 400)] {
+            Mock::given(method("GET"))
+                .and(path(file))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(test_body(len)))
+                .mount(&server)
+                .await;
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&test_body(1_000)).unwrap();
+        Mock::given(method("GET"))
+            .and(path("/unknown.bin"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_bytes(encoder.finish().unwrap()),
+            )
+            .mount(&server)
+            .await;
+        let quota = Arc::new(Quota::new(1_400));
+        let options = DownloadOptions {
+            quota: Some(quota.clone()),
+            ..DownloadOptions::default()
+        };
+        let client = test_client();
+        let get = |file: &str| {
+            let url = format!("{}{file}", server.uri());
+            let outfile = temp_file(&format!("quota-{}", &file[1..]));
+            let options = &options;
+            let client = &client;
+            async move {
+                let result = download(client, &url, &outfile, options).await;
+                let saved = Path::new(&outfile).exists();
+                assert!(!Path::new(&part_file(&outfile)).exists());
+                if saved {
+                    fs::remove_file(&outfile).unwrap();
+                }
+                (result, saved)
+            }
+        };
+
+        assert!(matches!(get("/a.bin").await, (Ok(1_000), true)));
+        // a file of known size that does not fit is not started
+        let (result, saved) = get("/b.bin").await;
+        assert!(matches!(
+            result,
+            Err(RgetError::QuotaExceeded { quota: 1_400, .. })
+        ));
+        assert!(!saved);
+        // a file of unknown size is removed once it runs over and gives back what it took
+        let (result, saved) = get("/unknown.bin").await;
+        assert!(matches!(result, Err(RgetError::QuotaExceeded { .. })));
+        assert!(!saved);
+        assert_eq!(quota.used(), 1_000);
+        assert!(matches!(get("/c.bin").await, (Ok(400), true)));
+        assert_eq!(quota.used(), 1_400);
+        // nothing is requested once the quota is used up
+        let (result, _) = get("/missing").await;
+        assert!(matches!(result, Err(RgetError::QuotaExceeded { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_invalid_url() {
+        let result = download(
+            &test_client(),
+            "file",
+            &temp_file("invalid"),
+            &DownloadOptions::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(RgetError::InvalidUrl { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_etag() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_string("remote"),
+            )
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.txt", server.uri());
+        let outfile = temp_file("etag");
+        let options = DownloadOptions {
+            etag: true,
+            ..DownloadOptions::default()
+        };
+        let client = test_client();
+
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "remote");
+        let meta = FileMeta::load(&outfile, &url).unwrap();
+        assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+
+        // the server answers 304, so the local file is left alone
+        fs::write(&outfile, "local").unwrap();
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "local");
+
+        // without the file the meta is useless and the file is downloaded again
+        fs::remove_file(&outfile).unwrap();
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "remote");
+
+        // with --no-clobber the meta is loaded from and saved to the name the file is saved as
+        let options = DownloadOptions {
+            clobber: ClobberPolicy::NoClobber,
+            ..options
+        };
+        let kept = format!("{outfile}.1");
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "remote");
+        assert!(FileMeta::load(&kept, &url).is_some());
+
+        for file in [&outfile, &kept] {
+            fs::remove_file(file).unwrap();
+            fs::remove_file(FileMeta::path(file)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_follows_redirects() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/new"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/new"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("moved"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let outfile = temp_file("redirect");
+
+        let url = format!("{}/old", server.uri());
+        download(&test_client(), &url, &outfile, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "moved");
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_post() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api"))
+            .and(body_string("query=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("result"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let outfile = temp_file("post");
+        let options = DownloadOptions {
+            method: Method::POST,
+            body: Some(b"query=1".to_vec()),
+            ..DownloadOptions::default()
+        };
+
+        let url = format!("{}/api", server.uri());
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "result");
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_download_dedupe() {
+        use std::os::unix::fs::MetadataExt;
+
+        let server = MockServer::start().await;
+        for file in ["/logo.png", "/logo.png?v=2", "/other.png"] {
+            let body = if file == "/other.png" {
+                "other"
+            } else {
+                "logo"
+            };
+            Mock::given(method("GET"))
+                .and(path(file.split('?').next().unwrap()))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+        let options = DownloadOptions {
+            dedupe: Some(Arc::default()),
+            ..DownloadOptions::default()
+        };
+        let client = test_client();
+
+        let outfiles = ["dedupe-a", "dedupe-b", "dedupe-c"].map(temp_file);
+        let urls = ["/logo.png", "/logo.png?v=2", "/other.png"];
+        for (url, outfile) in urls.iter().zip(&outfiles) {
+            let url = format!("{}{url}", server.uri());
+            download(&client, &url, outfile, &options).await.unwrap();
+        }
+        let inode = |file: &str| fs::metadata(file).unwrap().ino();
+        assert_eq!(inode(&outfiles[0]), inode(&outfiles[1]));
+        assert_ne!(inode(&outfiles[0]), inode(&outfiles[2]));
+        assert_eq!(fs::read_to_string(&outfiles[1]).unwrap(), "logo");
+        for outfile in outfiles {
+            fs::remove_file(outfile).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_download_exec() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("content"))
+            .mount(&server)
+            .await;
+        // the quoting keeps a path with spaces and quotes together
+        let outfile = temp_file("exec it's");
+        let options = DownloadOptions {
+            exec: Some("cp {} {}.copy".to_string()),
+            ..DownloadOptions::default()
+        };
+
+        let url = format!("{}/file.txt", server.uri());
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        let copy = format!("{outfile}.copy");
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "content");
+        fs::remove_file(&outfile).unwrap();
+        fs::remove_file(&copy).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_download_delete_after() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("content"))
+            .mount(&server)
+            .await;
+        let outfile = temp_file("delete-after");
+        // the command still sees the complete file before it is removed
+        let options = DownloadOptions {
+            exec: Some("cp {} {}.copy".to_string()),
+            delete_after: true,
+            ..DownloadOptions::default()
+        };
+
+        let url = format!("{}/file.txt", server.uri());
+        let written = download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(written, 7);
+        assert!(!Path::new(&outfile).exists());
+        assert!(!Path::new(&part_file(&outfile)).exists());
+        let copy = format!("{outfile}.copy");
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "content");
+        fs::remove_file(&copy).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_timestamping() {
+        let server = MockServer::start().await;
+        let modified = "Wed, 01 Jan 2020 00:00:00 GMT";
+        Mock::given(method("HEAD"))
+            .and(path("/file.txt"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Last-Modified", modified))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Last-Modified", modified)
+                    .set_body_string("remote"),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.txt", server.uri());
+        let outfile = temp_file("timestamping");
+        let options = DownloadOptions {
+            timestamping: true,
+            ..DownloadOptions::default()
+        };
+        let client = test_client();
+        let mtime = || FileTime::from_last_modification_time(&fs::metadata(&outfile).unwrap());
+        let server_time = FileTime::from_unix_time(1_577_836_800, 0);
+
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(mtime(), server_time);
+
+        // the local copy is as new as the remote one
+        fs::write(&outfile, "local").unwrap();
+        filetime::set_file_mtime(&outfile, server_time).unwrap();
+        assert_eq!(
+            download(&client, &url, &outfile, &options).await.unwrap(),
+            0
+        );
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "local");
+
+        // an older copy is replaced
+        filetime::set_file_mtime(&outfile, FileTime::from_unix_time(0, 0)).unwrap();
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "remote");
+        assert_eq!(mtime(), server_time);
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_empty() {
+        let server = MockServer::start().await;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"").unwrap();
+        let gzip = encoder.finish().unwrap();
+        let responses = [
+            ("/no-content", ResponseTemplate::new(204)),
+            ("/zero-length", ResponseTemplate::new(200)),
+            // decompressed without a Content-Length
+            (
+                "/chunked",
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_bytes(gzip),
+            ),
+        ];
+        let client = test_client();
+        for (file, response) in responses {
+            Mock::given(method("GET"))
+                .and(path(file))
+                .respond_with(response)
+                .mount(&server)
+                .await;
+            let outfile = temp_file(&format!("empty{}", file.replace('/', "-")));
+            let url = format!("{}{file}", server.uri());
+
+            let written = download(&client, &url, &outfile, &DownloadOptions::default())
+                .await
+                .unwrap();
+            assert_eq!(written, 0);
+            assert_eq!(fs::read(&outfile).unwrap(), b"", "{file}");
+            assert!(!Path::new(&part_file(&outfile)).exists());
+            fs::remove_file(&outfile).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_compress() {
+        let server = MockServer::start().await;
+        let body = test_body(10_000);
+        Mock::given(method("GET"))
+            .and(path("/page.html"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+            .mount(&server)
+            .await;
+        let outfile = temp_file("compressed.html.gz");
+        let url = format!("{}/page.html", server.uri());
+
+        let mut sizes = Vec::new();
+        for compression_level in [6, 0] {
+            let options = DownloadOptions {
+                compress: true,
+                compression_level,
+                ..DownloadOptions::default()
+            };
+            let written = download(&test_client(), &url, &outfile, &options)
+                .await
+                .unwrap();
+            assert_eq!(written, 10_000);
+            let mut content = Vec::new();
+            GzDecoder::new(File::open(&outfile).unwrap())
+                .read_to_end(&mut content)
+                .unwrap();
+            assert_eq!(content, body);
+            sizes.push(fs::metadata(&outfile).unwrap().len());
+            fs::remove_file(&outfile).unwrap();
+        }
+        // level 0 only stores the body with the gzip header and block framing around it
+        assert!(sizes[0] < 1_000, "{sizes:?}");
+        assert!(sizes[1] > 10_000, "{sizes:?}");
+    }
+
+    #[test]
+    fn test_parse_compression_level() {
+        assert_eq!(parse_compression_level("0"), Ok(0));
+        assert_eq!(parse_compression_level("9"), Ok(9));
+        assert_eq!(parse_compression_level("store"), Ok(0));
+        assert!(parse_compression_level("10").is_err());
+        assert!(parse_compression_level("-1").is_err());
+        assert!(parse_compression_level("fast").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_data_url() {
+        let outfile = temp_file("data-url.txt");
+        let written = download(
+            &test_client(),
+            "data:text/plain;base64,aGVsbG8=",
+            &outfile,
+            &DownloadOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(fs::read(&outfile).unwrap(), b"hello");
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_add_extension() {
+        let server = MockServer::start().await;
+        Mock::given(path("/about"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .mount(&server)
+            .await;
+        Mock::given(path("/about"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_raw("<p>about</p>", "text/html"),
+            )
+            .mount(&server)
+            .await;
+        let url = format!("{}/about", server.uri());
+        let options = DownloadOptions {
+            add_extension: true,
+            ..DownloadOptions::default()
+        };
+
+        let outfile = temp_file("about");
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert!(!Path::new(&outfile).exists());
+        let html = format!("{outfile}.html");
+        assert_eq!(fs::read_to_string(&html).unwrap(), "<p>about</p>");
+        fs::remove_file(&html).unwrap();
+
+        // names that have an extension are kept
+        let outfile = format!("{}.htm", temp_file("about"));
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert!(Path::new(&outfile).exists());
+        fs::remove_file(&outfile).unwrap();
+
+        // --etag compares with the file that got the extension
+        let options = DownloadOptions {
+            etag: true,
+            ..options
+        };
+        let outfile = temp_file("about-etag");
+        let html = format!("{outfile}.html");
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert!(FileMeta::load(&html, &url).is_some());
+        fs::write(&html, "local").unwrap();
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&html).unwrap(), "local");
+        assert!(!Path::new(&outfile).exists());
+        fs::remove_file(&html).unwrap();
+        fs::remove_file(FileMeta::path(&html)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trust_server_names() {
+        let server = MockServer::start().await;
+        Mock::given(path("/get"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/files/report.pdf"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/files/report.pdf"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("report"))
+            .mount(&server)
+            .await;
+        let url = format!("{}/get?id=3", server.uri());
+        let dir = temp_file("trust-server-names");
+        fs::create_dir_all(&dir).unwrap();
+        let outfile = format!("{dir}/get");
+
+        let options = DownloadOptions {
+            trust_server_names: true,
+            ..DownloadOptions::default()
+        };
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        let saved = format!("{dir}/report.pdf");
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "report");
+        assert!(!Path::new(&outfile).exists());
+
+        // without it the name of the requested url is kept
+        download(&test_client(), &url, &outfile, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "report");
+
+        // the names after the redirects are made unique before the downloads start
+        Mock::given(path("/other"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/files/report.pdf"))
+            .mount(&server)
+            .await;
+        let template = OutputTemplate::parse(&format!("{dir}/all/{{index}}")).unwrap();
+        let options = DownloadOptions {
+            trust_server_names: true,
+            output_template: Some(template),
+            ..DownloadOptions::default()
+        };
+        let targets = [
+            Target::new(&url),
+            Target::new(&format!("{}/other", server.uri())),
+        ];
+        download_all(&test_client(), &targets, 2, &options)
+            .await
+            .unwrap();
+        for name in ["report.pdf", "report.pdf.1"] {
+            let saved = format!("{dir}/all/{name}");
+            assert_eq!(fs::read_to_string(&saved).unwrap(), "report");
+        }
+
+        // --etag compares with the file named after the server
+        Mock::given(path("/etag"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/files/data.bin"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/files/data.bin"))
+            .and(header("If-None-Match", "\"v1\""))
+            .respond_with(ResponseTemplate::new(304))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/files/data.bin"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_string("data"),
+            )
+            .mount(&server)
+            .await;
+        let options = DownloadOptions {
+            trust_server_names: true,
+            etag: true,
+            ..DownloadOptions::default()
+        };
+        let url = format!("{}/etag", server.uri());
+        let outfile = format!("{dir}/etag");
+        for _ in 0..2 {
+            download(&test_client(), &url, &outfile, &options)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(format!("{dir}/data.bin")).unwrap(),
+            "data"
+        );
+        assert!(!Path::new(&format!("{dir}/data.bin.1")).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_mirrors() {
+        let server = MockServer::start().await;
+        Mock::given(path("/down/file.txt"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(path("/up/file.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("mirrored"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/missing/file.txt"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let url = |dir: &str| format!("{}/{dir}/file.txt", server.uri());
+        let stats = Arc::new(Stats::default());
+        let options = DownloadOptions {
+            stats: Some(stats.clone()),
+            ..DownloadOptions::default()
+        };
+
+        let outfile = temp_file("mirrors");
+        // the mirror after the first one that works is not asked
+        let urls = [url("down"), url("up"), url("missing")];
+        download_mirrors(&test_client(), &urls, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "mirrored");
+        fs::remove_file(&outfile).unwrap();
+
+        let result = download_mirrors(&test_client(), &[url("down")], &outfile, &options).await;
+        assert!(result.is_err());
+        assert!(!Path::new(&outfile).exists());
+        // every file counts once however many mirrors were tried
+        let report = stats.report();
+        assert_eq!((report.files_downloaded, report.failures), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_download_on_progress() {
+        let server = MockServer::start().await;
+        let body = vec![7u8; 100_000];
+        Mock::given(path("/file.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+            .mount(&server)
+            .await;
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = calls.clone();
+        let options = DownloadOptions {
+            on_progress: Some(OnProgress::new(move |downloaded, total| {
+                seen.lock().unwrap().push((downloaded, total));
+            })),
+            ..DownloadOptions::default()
+        };
+
+        let outfile = temp_file("on-progress");
+        let url = format!("{}/file.bin", server.uri());
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.first(), Some(&(0, Some(100_000))));
+        assert_eq!(calls.last(), Some(&(100_000, Some(100_000))));
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0), "{calls:?}");
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let text =
+            |body: &[u8], charset, convert| decode_text("u", body, charset, convert).unwrap();
+        assert_eq!(text(b"  a\r\nb\rc\n\n", None, false), "a\r\nb\rc\n");
+        assert_eq!(text(b"  a\r\nb\rc\n\n", None, true), "a\nb\nc\n");
+        assert_eq!(text(b"caf\xe9", Some("iso-8859-1"), false), "caf\u{e9}\n");
+        // the byte order mark wins over the charset
+        assert_eq!(
+            text(b"\xef\xbb\xbfcaf\xc3\xa9", Some("latin1"), false),
+            "caf\u{e9}\n"
+        );
+        assert_eq!(text(b" \n ", None, false), "");
+        assert!(matches!(
+            decode_text("u", b"a", Some("klingon"), false),
+            Err(RgetError::UnknownCharset { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_download_text() {
+        let server = MockServer::start().await;
+        Mock::given(path("/notes"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                b"\r\nl\xe4uft\r\nok\r\n\r\n".to_vec(),
+                "text/plain; charset=latin1",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(path("/image.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0x89, b'P'], "image/png"))
+            .mount(&server)
+            .await;
+        let options = DownloadOptions {
+            text: true,
+            convert_newlines: true,
+            ..DownloadOptions::default()
+        };
+
+        let outfile = temp_file("text");
+        let written = download(
+            &test_client(),
+            &format!("{}/notes", server.uri()),
+            &outfile,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "l\u{e4}uft\nok\n");
+        assert_eq!(written, 10);
+        fs::remove_file(&outfile).unwrap();
+
+        let result = download(
+            &test_client(),
+            &format!("{}/image.png", server.uri()),
+            &outfile,
+            &options,
+        )
+        .await;
+        assert!(
+            matches!(&result, Err(RgetError::NotText { content_type, .. }) if content_type == "image/png"),
+            "{result:?}"
+        );
+        assert!(!Path::new(&outfile).exists());
+
+        // a text that cannot be saved leaves no part file and gives back its room in the quota
+        fs::create_dir_all(format!("{outfile}/taken")).unwrap();
+        let quota = Arc::new(Quota::new(1_000));
+        let options = DownloadOptions {
+            clobber: ClobberPolicy::Force,
+            quota: Some(quota.clone()),
+            ..options
+        };
+        let result = download(
+            &test_client(),
+            &format!("{}/notes", server.uri()),
+            &outfile,
+            &options,
+        )
+        .await;
+        assert!(matches!(result, Err(RgetError::Io(_))), "{result:?}");
+        assert!(!Path::new(&part_file(&outfile)).exists());
+        assert_eq!(quota.used(), 0);
+        fs::remove_dir_all(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_missing_dir() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("content"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.txt", server.uri());
+        let dir = temp_file("missing-dir");
+        let outfile = format!("{dir}/sub/file.txt");
+
+        // nothing is requested for a file that cannot be saved
+        let result = download(&test_client(), &url, &outfile, &DownloadOptions::default()).await;
+        assert!(matches!(result, Err(RgetError::OutputDir { .. })));
+        let not_a_dir = temp_file("not-a-dir");
+        fs::write(&not_a_dir, "").unwrap();
+        let result = download(
+            &test_client(),
+            &url,
+            &format!("{not_a_dir}/file.txt"),
+            &DownloadOptions::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(RgetError::OutputDir { .. })));
+        fs::remove_file(&not_a_dir).unwrap();
+
+        let options = DownloadOptions {
+            create_dirs: true,
+            ..DownloadOptions::default()
+        };
+        download(&test_client(), &url, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "content");
+        // the directory is only probed once
+        let sub = Path::new(&dir).join("sub");
+        assert!(WRITABLE_DIRS.lock().unwrap().contains(&sub));
+
+        // a name of the input file only gets its directory with create_dirs
+        let targets = [Target {
+            url: url.clone(),
+            outfile: Some(format!("{dir}/other/file.txt")),
+        }];
+        let result = download_all(&test_client(), &targets, 1, &DownloadOptions::default()).await;
+        assert!(matches!(result, Err(RgetError::DownloadsFailed { .. })));
+        assert!(!Path::new(&dir).join("other").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_referer() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/image.png"))
+            .and(header("Referer", "http://localhost/page.html"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("image"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/image.png"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+        let url = format!("{}/image.png", server.uri());
+        let outfile = temp_file("referer");
+        let client = test_client();
+
+        let result = download(&client, &url, &outfile, &DownloadOptions::default()).await;
+        assert!(matches!(result, Err(RgetError::Http(_))));
+
+        let options = DownloadOptions {
+            referer: Some("http://localhost/page.html".to_string()),
+            ..DownloadOptions::default()
+        };
+        download(&client, &url, &outfile, &options).await.unwrap();
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "image");
+        fs::remove_file(&outfile).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_byte_range() {
+        let server = MockServer::start().await;
+        let body = test_body(10_000);
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .and(header("Range", "bytes=100-199"))
+            .respond_with(ResponseTemplate::new(206).set_body_bytes(&body[100..200]))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+            .mount(&server)
+            .await;
+        let url = format!("{}/file.bin", server.uri());
+        let outfile = temp_file("byte-range");
+        let client = test_client();
+
+        let range = |start, end| DownloadOptions {
+            byte_range: Some(ByteRange { start, end }),
+            ..DownloadOptions::default()
+        };
+        let written = download(&client, &url, &outfile, &range(100, Some(199)))
+            .await
+            .unwrap();
+        assert_eq!(written, 100);
+        assert_eq!(fs::read(&outfile).unwrap(), &body[100..200]);
+        fs::remove_file(&outfile).unwrap();
+
+        // the whole file is not what was asked for
+        let result = download(&client, &url, &outfile, &range(100, None)).await;
+        assert!(matches!(
+            result,
+            Err(RgetError::RangeNotSatisfied {
+                status: StatusCode::OK,
+                ..
+            })
+        ));
+        assert!(!Path::new(&outfile).exists());
+    }
+}
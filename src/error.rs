@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use http::StatusCode;
+use indicatif::HumanBytes;
+use rustyline::error::ReadlineError;
+use thiserror::Error;
+
+use crate::client::{proxy_auth_required, redirect_loop};
+
+/// everything that can go wrong in rget
+#[derive(Debug, Error)]
+pub enum RgetError {
+    #[error(transparent)]
+    Http(reqwest::Error),
+    /// the proxy answered 407
+    #[error(
+        "the proxy asked for a login, give it in the url of --proxy or with --proxy-user and --proxy-password"
+    )]
+    ProxyAuth,
+    /// the redirects of a request went in a circle
+    #[error("redirect loop {}", .chain.join(" -> "))]
+    RedirectLoop { chain: Vec<String> },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{url} is not a valid url: {reason}")]
+    InvalidUrl { url: String, reason: String },
+    /// the body ended before or after the announced Content-Length
+    #[error("expected {expected} bytes from {url} but got {actual}")]
+    SizeMismatch {
+        url: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("{url} is larger than the maximum file size of {}", HumanBytes(*.max))]
+    TooLarge { url: String, max: u64 },
+    /// the download would go beyond --quota
+    #[error("the quota of {} is used up, {url} was not saved", HumanBytes(*.quota))]
+    QuotaExceeded { url: String, quota: u64 },
+    #[error("not enough disk space for the {} of {path}", HumanBytes(*.size))]
+    NoSpace { path: String, size: u64 },
+    #[error("{url} is {content_type}, not text")]
+    NotText { url: String, content_type: String },
+    #[error("{url} is in the unknown charset {charset}")]
+    UnknownCharset { url: String, charset: String },
+    #[error("{url} answered a range request with {status}")]
+    RangeNotSatisfied { url: String, status: StatusCode },
+    /// the directory of an outfile is missing or cannot be written to, found before the request
+    #[error("cannot save {path}: {reason}")]
+    OutputDir { path: String, reason: String },
+    #[error("failed to read {}: {source}", .path.display())]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid crawl state: {0}")]
+    CrawlState(#[from] serde_json::Error),
+    #[error("{} holds the crawl of {root}, not of {url}", .path.display())]
+    CrawlRootMismatch {
+        path: PathBuf,
+        root: String,
+        url: String,
+    },
+    #[error("{failed} of {total} downloads failed")]
+    DownloadsFailed { failed: usize, total: usize },
+    /// the download was cancelled by --max-time
+    #[error("cancelled, the time limit was reached")]
+    TimeLimit,
+    #[error("{broken} of {total} urls are broken")]
+    BrokenLinks { broken: usize, total: usize },
+    /// flags or files that do not make sense
+    #[error("{0}")]
+    Config(String),
+    #[error("{url} answered {status}")]
+    Status { url: String, status: StatusCode },
+    #[error("{url} redirects to {location}, redirects are not followed over a unix socket")]
+    UnixRedirect { url: String, location: String },
+    #[error("failed to connect to {}: {source}", .path.display())]
+    UnixSocket {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Ftp(#[from] suppaftp::FtpError),
+    #[error(transparent)]
+    Readline(#[from] ReadlineError),
+}
+
+/// requests stopped by a redirect loop name its urls instead of only the first one, a proxy that
+/// wants a login says so instead of failing like any other request
+impl From<reqwest::Error> for RgetError {
+    fn from(error: reqwest::Error) -> Self {
+        if proxy_auth_required(&error) {
+            return RgetError::ProxyAuth;
+        }
+        match redirect_loop(&error) {
+            Some(redirect_loop) => RgetError::RedirectLoop {
+                chain: redirect_loop.chain.clone(),
+            },
+            None => RgetError::Http(error),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use regex::Regex;
+
+use crate::content_type::ContentType;
+use crate::urls::url_extension;
+
+/// decides by content type or file extension which downloads are saved
+///
+/// a pattern containing a `/` is a content type, `text` and `binary` are content type classes and
+/// everything else is a file extension matched against the url path
+#[derive(Debug, Default, Clone)]
+pub struct FileFilter {
+    /// patterns of which one has to match, everything is accepted when this is empty
+    pub accept: Vec<String>,
+    /// patterns of which none may match
+    pub reject: Vec<String>,
+}
+
+impl FileFilter {
+    /// `content_type` is the parsed type and the raw header value of the response, without it
+    /// content type patterns cannot be decided and `None` is returned when the result depends on
+    /// them
+    pub fn allows(&self, url: &str, content_type: Option<(&ContentType, &str)>) -> Option<bool> {
+        let matches = |pattern: &String| pattern_matches(pattern, url, content_type);
+        let rejected = any_match(self.reject.iter().map(matches));
+        let accepted = if self.accept.is_empty() {
+            Some(true)
+        } else {
+            any_match(self.accept.iter().map(matches))
+        };
+        match (accepted, rejected) {
+            (_, Some(true)) | (Some(false), _) => Some(false),
+            (Some(true), Some(false)) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// decides by regular expressions on the whole url which links of a crawl are followed, a url
+/// matching `reject` is never followed even if it matches `accept`
+#[derive(Debug, Default, Clone)]
+pub struct UrlFilter {
+    /// a url has to match this to be followed
+    pub accept: Option<Regex>,
+    /// urls matching this are not followed
+    pub reject: Option<Regex>,
+}
+
+impl UrlFilter {
+    pub fn allows(&self, url: &str) -> bool {
+        if self.reject.as_ref().is_some_and(|re| re.is_match(url)) {
+            return false;
+        }
+        self.accept.as_ref().is_none_or(|re| re.is_match(url))
+    }
+}
+
+/// parses the pattern of --accept-regex or --reject-regex
+pub fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("invalid regex {pattern:?}: {e}"))
+}
+
+/// `Some(true)` if any pattern matches and `Some(false)` if all are known not to match
+fn any_match(results: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut undecided = false;
+    for matched in results {
+        match matched {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => undecided = true,
+        }
+    }
+    if undecided { None } else { Some(false) }
+}
+
+fn pattern_matches(
+    pattern: &str,
+    url: &str,
+    content_type: Option<(&ContentType, &str)>,
+) -> Option<bool> {
+    match pattern {
+        "text" => content_type.map(|(ct, _)| ct.is_text()),
+        "binary" => content_type.map(|(ct, _)| ct.is_binary()),
+        pattern if pattern.contains('/') => {
+            content_type.map(|(_, mime)| mime_matches(pattern, mime))
+        }
+        extension => Some(
+            url_extension(url)
+                .is_some_and(|ext| ext.eq_ignore_ascii_case(extension.trim_start_matches('.'))),
+        ),
+    }
+}
+
+/// matches `type/*` or a full `type/subtype` against a content type header value
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim();
+    match pattern.strip_suffix("/*") {
+        Some(top) => essence
+            .split('/')
+            .next()
+            .is_some_and(|t| t.eq_ignore_ascii_case(top)),
+        None => essence.eq_ignore_ascii_case(pattern),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileFilter, UrlFilter, parse_regex};
+    use crate::content_type::ContentType;
+
+    #[test]
+    fn test_file_filter() {
+        let header = |mime: &'static str| {
+            ContentType::from_header_value(Some(&http::HeaderValue::from_static(mime)))
+        };
+        let html = header("text/html; charset=utf-8");
+        let html = Some((&html, "text/html; charset=utf-8"));
+        let png = header("image/png");
+        let png = Some((&png, "image/png"));
+        let filter = |accept: &[&str], reject: &[&str]| FileFilter {
+            accept: accept.iter().map(|s| s.to_string()).collect(),
+            reject: reject.iter().map(|s| s.to_string()).collect(),
+        };
+        let page = "http://localhost:3000/index.html";
+        let image = "http://localhost:3000/logo.png?size=2";
+
+        assert_eq!(FileFilter::default().allows(page, None), Some(true));
+        assert_eq!(filter(&["image/*"], &[]).allows(image, png), Some(true));
+        assert_eq!(filter(&["image/*"], &[]).allows(page, html), Some(false));
+        assert_eq!(filter(&["image/*"], &[]).allows(page, None), None);
+        assert_eq!(filter(&["text/html"], &[]).allows(page, html), Some(true));
+        assert_eq!(filter(&["text"], &[]).allows(page, html), Some(true));
+        assert_eq!(filter(&["binary"], &[]).allows(image, png), Some(true));
+        let unknown = Some((&ContentType::Unknown, ""));
+        assert_eq!(filter(&["binary"], &[]).allows(image, unknown), Some(false));
+        assert_eq!(
+            filter(&["image/*"], &["image/png"]).allows(image, png),
+            Some(false)
+        );
+        assert_eq!(filter(&[], &["text"]).allows(page, html), Some(false));
+        // a content type parsed from a mime string is filtered like one from a header
+        let parsed = ContentType::from_mime_str("text/html; charset=utf-8");
+        let parsed = Some((&parsed, "text/html; charset=utf-8"));
+        assert_eq!(filter(&["text/html"], &[]).allows(page, parsed), Some(true));
+        assert_eq!(filter(&[], &["text"]).allows(page, parsed), Some(false));
+
+        // extensions are decided from the url alone
+        assert_eq!(filter(&["png", "pdf"], &[]).allows(image, None), Some(true));
+        assert_eq!(filter(&["pdf"], &[]).allows(image, None), Some(false));
+        assert_eq!(filter(&["pdf", "image/*"], &[]).allows(image, None), None);
+        assert_eq!(
+            filter(&["pdf", "image/*"], &[]).allows(image, png),
+            Some(true)
+        );
+        assert_eq!(filter(&[], &[".PNG"]).allows(image, None), Some(false));
+        assert_eq!(
+            filter(&["html"], &[]).allows("http://localhost:3000/", None),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_url_filter() {
+        let filter = |accept: Option<&str>, reject: Option<&str>| UrlFilter {
+            accept: accept.map(|re| parse_regex(re).unwrap()),
+            reject: reject.map(|re| parse_regex(re).unwrap()),
+        };
+        let guide = "http://localhost:3000/docs/guide.html";
+        let logo = "http://localhost:3000/logo.png";
+
+        assert!(UrlFilter::default().allows(logo));
+        let docs = filter(Some(r"/docs/.*\.html"), None);
+        assert!(docs.allows(guide));
+        assert!(!docs.allows(logo));
+        assert!(filter(None, Some(r"\.png$")).allows(guide));
+        assert!(!filter(None, Some(r"\.png$")).allows(logo));
+        // reject wins over accept
+        assert!(!filter(Some("/docs/"), Some("guide")).allows(guide));
+    }
+
+    #[test]
+    fn test_parse_regex() {
+        assert!(parse_regex(r"^https://").is_ok());
+        let e = parse_regex("/docs/(.*").unwrap_err();
+        assert!(e.starts_with(r#"invalid regex "/docs/(.*": "#), "{e}");
+    }
+}
@@ -0,0 +1,117 @@
+use reqwest::header::LOCATION;
+use reqwest::{Response, Url};
+
+use crate::client::{HttpClient, MAX_REDIRECTS};
+use crate::error::RgetError;
+
+/// the status line and the headers of `response` like a server sends them
+fn format_response(response: &Response) -> String {
+    let mut text = format!("{:?} {}\n", response.version(), response.status());
+    for (name, value) in response.headers() {
+        text.push_str(&format!(
+            "{name}: {}\n",
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    text
+}
+
+/// where the redirect `response` points to, none if it is no redirect
+fn redirect_target(response: &Response) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    response.url().join(location).ok()
+}
+
+/// the responses to a HEAD request for `url` and every redirect after it, in the order they came
+pub async fn hops(client: &HttpClient, url: &str) -> Result<Vec<Response>, RgetError> {
+    let mut responses: Vec<Response> = Vec::new();
+    let mut next = Some(url.to_string());
+    while let Some(url) = next.take() {
+        let chain = || responses.iter().map(|r| r.url().to_string());
+        if chain().any(|seen| seen == url) {
+            let chain = chain().chain([url]).collect();
+            return Err(RgetError::RedirectLoop { chain });
+        }
+        if responses.len() > MAX_REDIRECTS {
+            return Err(RgetError::Config(format!(
+                "{url} is more than {MAX_REDIRECTS} redirects away"
+            )));
+        }
+        let response = client.send(client.head_hop(&url)?).await?;
+        next = redirect_target(&response).map(String::from);
+        responses.push(response);
+    }
+    Ok(responses)
+}
+
+/// prints the status and headers of every response on the way to `url`, like `curl -I -L`
+pub async fn head(client: &HttpClient, url: &str) -> Result<(), RgetError> {
+    let responses = hops(client, url).await?;
+    for (i, response) in responses.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("< {}", response.url());
+        print!("{}", format_response(response));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{format_response, hops};
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::error::RgetError;
+
+    #[tokio::test]
+    async fn test_head_hops() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/new"))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/new"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "text/plain")
+                    .insert_header("X-Served-By", "mock"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/loop"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/loop"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+
+        let responses = hops(&client, &format!("{}/old", server.uri()))
+            .await
+            .unwrap();
+        let urls: Vec<_> = responses.iter().map(|r| r.url().path()).collect();
+        assert_eq!(urls, ["/old", "/new"]);
+        let first = format_response(&responses[0]);
+        assert!(
+            first.starts_with("HTTP/1.1 301 Moved Permanently\n"),
+            "{first}"
+        );
+        assert!(first.contains("location: /new\n"), "{first}");
+        let last = format_response(&responses[1]);
+        assert!(last.starts_with("HTTP/1.1 200 OK\n"), "{last}");
+        assert!(last.contains("x-served-by: mock\n"), "{last}");
+
+        let looped = hops(&client, &format!("{}/loop", server.uri())).await;
+        assert!(
+            matches!(&looped, Err(RgetError::RedirectLoop { chain }) if chain.len() == 2),
+            "{looped:?}"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use indicatif::HumanBytes;
+use rget::client::HttpClient;
+use rget::download::{DownloadOptions, Transfer, download};
+use rget::error::RgetError;
+use rget::resume::resume;
+use tokio::task::JoinHandle;
+
+pub(crate) enum Status {
+    Running(JoinHandle<Result<u64, RgetError>>),
+    /// stopped by `pause`, the part file is kept to continue it
+    Paused,
+    Done,
+    Failed(String),
+}
+
+/// a download running in the background, its progress is reported to `transfer` instead of being
+/// drawn
+pub(crate) struct Job {
+    pub(crate) id: usize,
+    pub(crate) url: String,
+    pub(crate) outfile: String,
+    pub(crate) transfer: Arc<Transfer>,
+    pub(crate) status: Status,
+}
+
+impl Job {
+    /// how much of the download is done and the text shown next to its bar
+    pub(crate) fn progress(&self) -> (f64, String) {
+        let downloaded = self.transfer.downloaded();
+        let (ratio, size) = match self.transfer.total() {
+            Some(total) if total > 0 => (
+                (downloaded as f64 / total as f64).min(1.0),
+                format!("{}/{}", HumanBytes(downloaded), HumanBytes(total)),
+            ),
+            _ => (0.0, HumanBytes(downloaded).to_string()),
+        };
+        let head = format!("{} -> {}", self.url, self.outfile);
+        match &self.status {
+            Status::Running(_) => (ratio, format!("{head} {size}")),
+            Status::Paused => (ratio, format!("{head} {size} paused")),
+            Status::Done => (1.0, format!("{head} {size} done")),
+            Status::Failed(e) => (ratio, format!("{head} failed: {e}")),
+        }
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        matches!(self.status, Status::Running(_))
+    }
+}
+
+/// the background downloads of a session
+#[derive(Default)]
+pub(crate) struct Jobs {
+    pub(crate) jobs: Vec<Job>,
+}
+
+impl Jobs {
+    /// starts downloading `url` to `outfile` in the background and returns the id of the job
+    pub(crate) fn start(
+        &mut self,
+        url: String,
+        outfile: String,
+        client: &HttpClient,
+        options: &DownloadOptions,
+    ) -> usize {
+        let transfer = Arc::new(Transfer::default());
+        let task = spawn(&url, &outfile, &transfer, client, options);
+        let id = self.jobs.len() + 1;
+        self.jobs.push(Job {
+            id,
+            url,
+            outfile,
+            transfer,
+            status: Status::Running(task),
+        });
+        id
+    }
+
+    /// stops the running job `id`, its part file stays to resume it
+    pub(crate) fn pause(&mut self, id: usize) -> Result<(), String> {
+        let job = self.job(id)?;
+        match &job.status {
+            // a finished download is collected like the others
+            Status::Running(task) if !task.is_finished() => {
+                task.abort();
+                job.status = Status::Paused;
+                Ok(())
+            }
+            _ => Err(format!("[{id}] is not running")),
+        }
+    }
+
+    /// continues the paused job `id` with a range request from where it stopped, a download that
+    /// cannot be continued starts over
+    pub(crate) fn resume(
+        &mut self,
+        id: usize,
+        client: &HttpClient,
+        options: &DownloadOptions,
+    ) -> Result<(), String> {
+        let job = self.job(id)?;
+        if !matches!(job.status, Status::Paused) {
+            return Err(format!("[{id}] is not paused"));
+        }
+        let task = spawn(&job.url, &job.outfile, &job.transfer, client, options);
+        job.status = Status::Running(task);
+        Ok(())
+    }
+
+    fn job(&mut self, id: usize) -> Result<&mut Job, String> {
+        self.jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| format!("there is no job {id}"))
+    }
+
+    /// the running job writing to `outfile`
+    pub(crate) fn writing(&self, outfile: &str) -> Option<&Job> {
+        self.jobs
+            .iter()
+            .find(|job| job.is_running() && job.outfile == outfile)
+    }
+
+    /// moves finished downloads to done or failed and returns them
+    pub(crate) async fn collect_finished(&mut self) -> Vec<&Job> {
+        let mut finished = Vec::new();
+        for job in &mut self.jobs {
+            let Status::Running(task) = &mut job.status else {
+                continue;
+            };
+            if !task.is_finished() {
+                continue;
+            }
+            job.status = finished_status(task).await;
+            finished.push(&*job);
+        }
+        finished
+    }
+
+    /// waits until every running download is finished and returns them
+    pub(crate) async fn wait(&mut self) -> Vec<&Job> {
+        let mut finished = Vec::new();
+        for job in &mut self.jobs {
+            let Status::Running(task) = &mut job.status else {
+                continue;
+            };
+            job.status = finished_status(task).await;
+            finished.push(&*job);
+        }
+        finished
+    }
+
+    /// stops the running downloads, like an interrupt they are kept as .part files
+    pub(crate) fn abort(&self) {
+        for job in &self.jobs {
+            if let Status::Running(task) = &job.status {
+                task.abort();
+            }
+        }
+    }
+}
+
+/// downloads `url` in the background and reports to `transfer`, from the part file if an earlier
+/// attempt left one that can be continued
+fn spawn(
+    url: &str,
+    outfile: &str,
+    transfer: &Arc<Transfer>,
+    client: &HttpClient,
+    options: &DownloadOptions,
+) -> JoinHandle<Result<u64, RgetError>> {
+    let client = client.clone();
+    let options = DownloadOptions {
+        transfer: Some(transfer.clone()),
+        ..options.clone()
+    };
+    let (url, outfile) = (url.to_string(), outfile.to_string());
+    let sidecar = transfer.sidecar().filter(|sidecar| sidecar.exists());
+    tokio::spawn(async move {
+        match sidecar {
+            Some(sidecar) => resume(&client, &sidecar, &options).await,
+            None => download(&client, &url, &outfile, &options).await,
+        }
+    })
+}
+
+async fn finished_status(task: &mut JoinHandle<Result<u64, RgetError>>) -> Status {
+    match task.await {
+        Ok(Ok(_)) => Status::Done,
+        Ok(Err(e)) => Status::Failed(e.to_string()),
+        Err(e) => Status::Failed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rget::client::{ClientArgs, HttpClient};
+    use rget::download::{DownloadOptions, Transfer};
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{Job, Jobs, Status};
+
+    #[test]
+    fn test_job_progress() {
+        let job = |status| Job {
+            id: 1,
+            url: "http://localhost/a".to_string(),
+            outfile: "a".to_string(),
+            transfer: Arc::new(Transfer::default()),
+            status,
+        };
+
+        let done = job(Status::Done);
+        assert_eq!(
+            done.progress(),
+            (1.0, "http://localhost/a -> a 0 B done".to_string())
+        );
+
+        let failed = job(Status::Failed("404".to_string()));
+        assert_eq!(
+            failed.progress(),
+            (0.0, "http://localhost/a -> a failed: 404".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let server = MockServer::start().await;
+        // the first attempt hangs until it is paused
+        Mock::given(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("finally"))
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let options = DownloadOptions::default();
+        let outfile = std::env::temp_dir()
+            .join(format!("rget-job-pause-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let mut jobs = Jobs::default();
+        let id = jobs.start(
+            format!("{}/slow", server.uri()),
+            outfile.clone(),
+            &client,
+            &options,
+        );
+        assert!(jobs.resume(id, &client, &options).is_err());
+        while server.received_requests().await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        jobs.pause(id).unwrap();
+        assert!(matches!(jobs.jobs[0].status, Status::Paused));
+        assert!(jobs.pause(id).is_err());
+        assert!(jobs.pause(id + 1).is_err());
+        // a paused job is not waited for
+        assert!(jobs.wait().await.is_empty());
+
+        jobs.resume(id, &client, &options).unwrap();
+        jobs.wait().await;
+        assert!(matches!(jobs.jobs[0].status, Status::Done));
+        assert_eq!(std::fs::read_to_string(&outfile).unwrap(), "finally");
+        std::fs::remove_file(&outfile).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+//! rget downloads files and crawls sites like wget
+//!
+//! [`download`] saves a single url, [`crawl`] walks the links of a site into a [`Tree`] and
+//! [`find_links`] extracts the links of a html page. All requests go through a [`HttpClient`]
+//!
+//! the progress of downloads is drawn on the terminal, an [`OnProgress`] callback in the
+//! [`DownloadOptions`] gets it instead
+//!
+//! ```no_run
+//! use rget::{ClientArgs, CrawlOptions, DownloadOptions, HttpClient};
+//!
+//! # async fn run() -> Result<(), rget::RgetError> {
+//! let client = HttpClient::new(&ClientArgs::default(), &[])?;
+//! rget::download(&client, "https://example.com/", "index.html", &DownloadOptions::default())
+//!     .await?;
+//!
+//! let tree = rget::crawl(&client, "https://example.com/", 2, &CrawlOptions::default()).await?;
+//! tree.traverse(|url| println!("{url}"));
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod breaker;
+mod cache;
+pub mod client;
+pub mod content_type;
+pub mod crawl;
+mod data_url;
+pub mod download;
+pub mod error;
+pub mod filter;
+mod ftp;
+pub mod head;
+mod netrc;
+mod progress;
+pub mod resume;
+pub mod single_file;
+pub mod sitemap;
+pub mod spider;
+pub mod stats;
+pub mod structures;
+#[cfg(unix)]
+mod unix;
+pub mod urls;
+
+pub use client::{ClientArgs, HttpClient};
+pub use crawl::{CrawlOptions, crawl, download_depth, find_links};
+pub use download::{DownloadOptions, OnProgress, download};
+pub use error::RgetError;
+pub use structures::Tree;
@@ -1,424 +1,985 @@
-pub mod structures;
-use core::hash;
-use std::cell::RefCell;
-use std::collections::VecDeque;
-use std::fmt::format;
-use std::fs::File;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io::BufWriter;
-use std::ops::Sub;
-use std::rc::Rc;
-use std::sync::atomic::Ordering;
-use std::sync::mpsc::{self, Sender};
-use std::sync::{Arc, Mutex};
-use std::thread::{self};
-use std::time::{Duration, SystemTime};
-use std::{io::Write, sync::atomic::AtomicBool};
+mod config;
+mod jobs;
+mod tui;
+
+use std::ffi::OsString;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use clap::{Parser, Subcommand};
-use http::header::CONTENT_TYPE;
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
-use reqwest::Response;
-use scraper::{Html, Selector};
-use structures::{Queue, Tree, TreeNode, TreeNodeRef};
-use tokio::task;
+use http::Method;
+use indicatif::MultiProgress;
+use regex::Regex;
+use rget::breaker::HostBreakers;
+use rget::client::{ClientArgs, HttpClient};
+use rget::crawl::{CrawlOptions, Layout, download_depth};
+use rget::download::{
+    ByteRange, ClobberPolicy, DownloadOptions, ProgressFormat, Target, create_parent_dir,
+    download_all, download_mirrors, parse_compression_level, parse_input_file,
+    parse_progress_chars, parse_progress_template, parse_size,
+};
+use rget::error::RgetError;
+use rget::filter::{FileFilter, UrlFilter, parse_regex};
+use rget::stats::Stats;
+use rget::urls::{OutputTemplate, file_name_from_query, normalize_url};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::jobs::{Job, Jobs, Status};
 
 const OUT_FILE: &str = "rget.out";
 const DEFAULT_DEPTH: usize = 1;
-const MAX_THREADS: usize = 10;
 
 /// Simple program to download a URL
 #[derive(Parser, Debug)]
-#[command(name = "rget", about = "A Rust wget clone")]
+#[command(name = "rget", about = "A Rust wget clone", version)]
 struct Args {
     #[command(subcommand)]
     subs: SubCom,
+    #[command(flatten)]
+    client: ClientArgs,
+    /// write the urls fetched, the bytes downloaded, the average and peak speed, the retries, the
+    /// failures and the elapsed time of the run to this file as json once it ends
+    #[arg(long, global = true, value_name = "PATH")]
+    stats_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum SubCom {
     /// get an file from an url
     Get {
-        /// The URL to download
-        url: String,
-        #[arg(short, long, default_value = OUT_FILE)]
-        outfile: String,
+        /// The URLs to download, with more than one url every file is named after its url
+        #[arg(required_unless_present = "input_file")]
+        urls: Vec<String>,
+        /// read urls from this file, one `url` or `url<TAB>outfile` per line, empty lines and
+        /// lines starting with `#` are ignored
+        #[arg(short, long)]
+        input_file: Option<PathBuf>,
+        /// where a single url is saved [default: rget.out]
+        #[arg(short, long)]
+        outfile: Option<String>,
+        /// how many of the urls are downloaded at the same time
+        #[arg(short = 'P', long, default_value = "4")]
+        parallel: NonZeroUsize,
+        /// download the file over this many connections at once if the server supports range
+        /// requests
+        #[arg(short = 'j', long, default_value = "1")]
+        connections: NonZeroUsize,
+        /// the request method [default: POST with --data or --data-file, GET otherwise]
+        #[arg(short = 'X', long, value_name = "VERB", value_parser = parse_method)]
+        method: Option<Method>,
+        /// send this as the request body
+        #[arg(short, long, conflicts_with = "data_file")]
+        data: Option<String>,
+        /// send the content of this file as the request body
+        #[arg(long, value_name = "FILE")]
+        data_file: Option<PathBuf>,
+        /// only check that the urls exist with HEAD requests and print their status, nothing is
+        /// downloaded
+        #[arg(long, conflicts_with_all = ["outfile", "method", "data", "data_file"])]
+        spider: bool,
+        /// run this shell command for every downloaded file once it is complete, `{}` is replaced
+        /// by the path of the file, e.g. `gzip {}`
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+        /// only download the file from this byte offset on, the server has to support range
+        /// requests
+        #[arg(long, value_name = "N", conflicts_with_all = ["connections", "etag", "timestamping"])]
+        start_byte: Option<u64>,
+        /// only download the file up to and including this byte offset
+        #[arg(long, value_name = "N", conflicts_with_all = ["connections", "etag", "timestamping"])]
+        end_byte: Option<u64>,
+        /// send the requests over this unix domain socket, the host of the url is still sent as
+        /// the Host header, e.g. to talk to the docker daemon. Redirects are not followed
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["connections", "etag", "timestamping", "spider", "proxy", "http2"])]
+        unix_socket: Option<PathBuf>,
+        /// name the files without an --outfile or a name in the input file after the url their
+        /// redirects end at instead of the requested one, for sites that forward downloads
+        #[arg(long, conflicts_with = "output_template")]
+        trust_server_names: bool,
+        /// another url of the same file, the mirrors are tried in order when the url and the
+        /// mirrors before fail (can be given multiple times)
+        #[arg(long, value_name = "URL", conflicts_with_all = ["input_file", "spider"])]
+        mirror: Vec<String>,
+        /// save text responses decoded from their charset as utf-8 with the whitespace around
+        /// them trimmed, anything else than text is an error
+        #[arg(long, conflicts_with_all = ["connections", "start_byte", "end_byte", "spider", "unix_socket"])]
+        text: bool,
+        /// turn the `\r\n` and `\r` line endings of --text into `\n`
+        #[arg(long, requires = "text")]
+        convert_newlines: bool,
+        /// print the http version and the status of the response every file is downloaded with
+        #[arg(short, long)]
+        verbose: bool,
+        #[command(flatten)]
+        download: DownloadArgs,
     },
     /// start the program in interactive mode
     Interactive {
         #[arg(short, long, default_value = OUT_FILE)]
         outfile: String,
+        #[command(flatten)]
+        download: DownloadArgs,
+    },
+    /// show a dashboard of downloads, urls typed into it are downloaded in the background
+    Tui {
+        #[command(flatten)]
+        download: DownloadArgs,
     },
+    /// print version and build information
+    Version,
     GetDepth {
         /// The URL to download
         url: String,
         #[arg(short, long, default_value_t = DEFAULT_DEPTH)]
         depth: usize,
+        /// only save files matching one of these content types or file extensions, e.g.
+        /// `image/*,application/pdf` or `pdf,zip` (`text` and `binary` match whole classes)
+        #[arg(long, value_delimiter = ',')]
+        accept: Vec<String>,
+        /// never save files matching one of these content types or file extensions, takes
+        /// precedence over --accept
+        #[arg(long, value_delimiter = ',')]
+        reject: Vec<String>,
+        /// crawl and print the files that would be downloaded with their size, nothing is written
+        #[arg(long, conflicts_with_all = ["state_file", "resume"])]
+        dry_run: bool,
+        /// crawl the site and check every link with a HEAD request, broken links are printed with
+        /// the page they are on and nothing is downloaded
+        #[arg(long, conflicts_with = "dry_run")]
+        spider: bool,
+        /// run this shell command for every downloaded file once it is complete, `{}` is replaced
+        /// by the path of the file, e.g. `gzip {}`
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+        /// only download the N largest files of the crawl by the size a HEAD request reports,
+        /// the largest one without N. Files of unknown size are ranked last
+        #[arg(
+            long,
+            value_name = "N",
+            num_args = 0..=1,
+            default_missing_value = "1",
+            conflicts_with = "spider"
+        )]
+        largest_only: Option<NonZeroUsize>,
+        /// save the files gzip compressed with a `.gz` appended to their names, links are still
+        /// found in the uncompressed pages
+        #[arg(long, conflicts_with = "spider")]
+        compress: bool,
+        /// the gzip level of --compress from 0 to 9, `store` (0) only wraps the files in gzip
+        /// without compressing them
+        #[arg(
+            long,
+            value_name = "LEVEL",
+            default_value = "6",
+            requires = "compress",
+            value_parser = parse_compression_level
+        )]
+        compression_level: u32,
+        /// save only the page at the url to FILE with its images, stylesheets and scripts
+        /// embedded as `data:` urls, an archive of the page that opens without the site
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["depth", "dry_run", "spider", "largest_only", "compress", "state_file", "resume"]
+        )]
+        single_file: Option<String>,
+        #[command(flatten)]
+        crawl: CrawlArgs,
+        #[command(flatten)]
+        download: DownloadArgs,
+    },
+    /// download every page listed in the sitemap at the url, the sitemaps of a sitemap index are
+    /// read as well
+    Sitemap {
+        /// the url of the sitemap, e.g. `https://example.com/sitemap.xml`
+        url: String,
+        /// how many of the pages are downloaded at the same time
+        #[arg(short = 'P', long, default_value = "4")]
+        parallel: NonZeroUsize,
+        /// only print the urls of the sitemap, nothing is downloaded
+        #[arg(long)]
+        list: bool,
+        #[command(flatten)]
+        download: DownloadArgs,
+    },
+    /// print the status and the headers the url answers a HEAD request with, redirects are
+    /// followed and every response on the way is shown
+    Head {
+        /// the url to ask
+        url: String,
+    },
+    /// continue the interrupted downloads in a directory, every `.part` file with a `.part.meta`
+    /// next to it is finished with range requests
+    Resume {
+        /// the directory with the `.part` files, subdirectories are searched as well
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        /// how many of the files are resumed at the same time
+        #[arg(short = 'P', long, default_value = "4")]
+        parallel: NonZeroUsize,
+        #[command(flatten)]
+        download: DownloadArgs,
     },
 }
 
-#[derive(Debug)]
-pub enum TextType {
-    Plain,
-    Html,
-    Css,
-    Javascript,
-    Xml,
-    Markdown,
-    Csv,
-    Richtext,
-    TabSeparatedValues,
-}
-
-#[derive(Debug)]
-pub enum ContentType {
-    Text(TextType), // For specific text formats
-    Other(String),  // For any other content type, storing the string value
-    Unknown,        // For cases where the header is missing or invalid
+/// flags that change how get-depth crawls a site
+#[derive(clap::Args, Debug)]
+struct CrawlArgs {
+    /// save the crawl state to this file after every level so it can be resumed, the next crawl
+    /// with the same file only parses the pages that changed since
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+    /// continue the crawl saved in this state file, new checkpoints go to the same file
+    #[arg(long, conflicts_with = "state_file")]
+    resume: Option<PathBuf>,
+    /// name the files after the last segment of their url path instead of a hash, the query
+    /// string is dropped and repeated names get a `.<n>` suffix
+    #[arg(long, conflicts_with = "tree")]
+    flat: bool,
+    /// save the files to `host/path` like the site is laid out, a path ending in `/` is saved as
+    /// `index.html` and the query string stays part of the file name
+    #[arg(long)]
+    tree: bool,
+    /// wait this many seconds between the pages of the crawl
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    wait: Option<Duration>,
+    /// wait between 0.5 and 1.5 times --wait between the pages
+    #[arg(long, requires = "wait")]
+    random_wait: bool,
+    /// follow links into other hosts than the one of the url only this deep, the first page of a
+    /// host is depth 1
+    #[arg(long, value_name = "DEPTH")]
+    max_depth_per_host: Option<NonZeroUsize>,
+    /// look for links in every page as if it was html, for servers that send html with another
+    /// content type
+    #[arg(long)]
+    force_html: bool,
+    /// treat the page at the url as this content type instead of the one the server sends, e.g.
+    /// `text/html`
+    #[arg(long, value_name = "MIME", conflicts_with = "force_html")]
+    content_type_override: Option<String>,
+    /// stop the crawl and cancel the downloads still running after this many seconds, what was
+    /// completed is reported
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    max_time: Option<Duration>,
+    /// fetch at most this many pages or files of the same host at the same time
+    #[arg(long, value_name = "N")]
+    max_connections_per_host: Option<NonZeroUsize>,
+    /// only follow links whose whole url matches this regular expression, e.g.
+    /// `/docs/.*\.html`
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    accept_regex: Option<Regex>,
+    /// never follow links whose url matches this regular expression, even if they match
+    /// --accept-regex
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    reject_regex: Option<Regex>,
+    /// never ascend above the directory of the url, links to its parents, to sibling directories
+    /// and to other hosts are not followed
+    #[arg(long)]
+    no_parent: bool,
+    /// keep the crawled pages in this directory, the next crawl only asks the server whether they
+    /// changed and reuses them when they did not
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+    /// stop downloading once the files of the crawl add up to this size, e.g. `500M`. Files that
+    /// do not fit into what is left are not started
+    #[arg(short = 'Q', long, value_name = "SIZE", value_parser = parse_size)]
+    quota: Option<u64>,
 }
 
-impl ContentType {
-    pub fn from_header_value(ct_value: Option<&http::HeaderValue>) -> Self {
-        match ct_value {
-            Some(value) => {
-                match value.to_str() {
-                    Ok(ct_str) => match ct_str {
-                        ct_str if ct_str.starts_with("text/plain") => {
-                            ContentType::Text(TextType::Plain)
-                        }
-                        ct_str if ct_str.starts_with("text/html") => {
-                            ContentType::Text(TextType::Html)
-                        }
-                        ct_str if ct_str.starts_with("text/css") => {
-                            ContentType::Text(TextType::Css)
-                        }
-                        ct_str if ct_str.starts_with("text/javascript") => {
-                            ContentType::Text(TextType::Javascript)
-                        }
-                        ct_str if ct_str.starts_with("text/xml") => {
-                            ContentType::Text(TextType::Xml)
-                        }
-                        ct_str if ct_str.starts_with("text/markdown") => {
-                            ContentType::Text(TextType::Markdown)
-                        }
-                        ct_str if ct_str.starts_with("text/csv") => {
-                            ContentType::Text(TextType::Csv)
-                        }
-                        ct_str if ct_str.starts_with("text/richtext") => {
-                            ContentType::Text(TextType::Richtext)
-                        }
-                        ct_str if ct_str.starts_with("text/tab-separated-values") => {
-                            ContentType::Text(TextType::TabSeparatedValues)
-                        }
-                        other => ContentType::Other(other.to_string()), // Store the unknown type
-                    },
-                    Err(_) => ContentType::Unknown, // Header value not valid UTF-8
-                }
-            }
-            None => ContentType::Unknown, // Header is missing
+impl From<&CrawlArgs> for CrawlOptions {
+    fn from(args: &CrawlArgs) -> Self {
+        CrawlOptions {
+            state_file: args.resume.clone().or(args.state_file.clone()),
+            resume: args.resume.is_some(),
+            layout: if args.flat {
+                Layout::Flat
+            } else if args.tree {
+                Layout::Tree
+            } else {
+                Layout::Hashed
+            },
+            wait: args.wait,
+            random_wait: args.random_wait,
+            max_depth_per_host: args.max_depth_per_host.map(NonZeroUsize::get),
+            force_html: args.force_html,
+            content_type_override: args.content_type_override.clone(),
+            deadline: None,
+            largest_only: None,
+            max_connections_per_host: args.max_connections_per_host.map(NonZeroUsize::get),
+            url_filter: UrlFilter {
+                accept: args.accept_regex.clone(),
+                reject: args.reject_regex.clone(),
+            },
+            no_parent: args.no_parent,
+            cache_dir: args.cache_dir.clone(),
+            quota: args.quota,
         }
     }
 }
 
-struct Spinner {
-    chars: Vec<char>,
-    stop_tx: Option<Sender<bool>>,
+/// flags that change how a single file is downloaded, shared by all subcommands
+#[derive(clap::Args, Debug)]
+struct DownloadArgs {
+    /// never overwrite an existing file, save to `<outfile>.1`, `<outfile>.2`, ... instead
+    #[arg(long, conflicts_with = "force")]
+    no_clobber: bool,
+    /// overwrite existing files without a warning
+    #[arg(long)]
+    force: bool,
+    /// refuse files larger than this, accepts sizes like `500K`, `100M` or `2G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_filesize: Option<u64>,
+    /// only download files that changed since the last download, the ETag and Last-Modified of
+    /// every file are kept in `<outfile>.rget-meta`
+    #[arg(long, conflicts_with = "no_clobber")]
+    etag: bool,
+    /// skip files whose local copy is as new as the Last-Modified of the server, downloaded files
+    /// get the modification time of the server
+    #[arg(short = 'N', long, conflicts_with = "no_clobber")]
+    timestamping: bool,
+    /// send this url as the Referer header, get-depth sends the page a file was linked from
+    /// instead
+    #[arg(long, value_name = "URL")]
+    referer: Option<String>,
+    /// save files with the same content as an earlier download of the run as hard links to it,
+    /// they are still downloaded
+    #[arg(long)]
+    dedupe: bool,
+    /// how the progress of downloads is shown
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    progress: ProgressFormat,
+    /// an indicatif template for the progress bar, e.g. `{wide_bar} {percent}%`, `{msg}` is the
+    /// speed of the last seconds and the time left at that speed
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_progress_template)]
+    progress_template: Option<String>,
+    /// the characters of the filled, the current and the empty part of the progress bar, e.g.
+    /// `=> `
+    #[arg(long, value_name = "CHARS", value_parser = parse_progress_chars)]
+    progress_chars: Option<String>,
+    /// name the files of get and get-depth after this template instead of their url, e.g.
+    /// `{host}/{basename}-{index}.{ext}`. The fields are {host}, {path}, {basename}, {ext},
+    /// {hash} and {index}, the position of the url counted from 1
+    #[arg(long, value_name = "TEMPLATE", value_parser = OutputTemplate::parse)]
+    output_template: Option<OutputTemplate>,
+    /// name files after the `filename`, `file` or `name` parameter of their query string when
+    /// they have one, e.g. `download.php?file=report.pdf` is saved as `report.pdf`
+    #[arg(long)]
+    decode_query: bool,
+    /// append the extension of the content type to file names derived from the url that have
+    /// none, e.g. a page at `/about` is saved as `about.html`
+    #[arg(short = 'E', long)]
+    adjust_extension: bool,
+    /// create the directory of the outfile when it does not exist instead of failing, the
+    /// directories of --output-template and get-depth are always created
+    #[arg(long)]
+    create_dirs: bool,
+    /// delete every file right after it is downloaded and --exec ran on it, to warm a cache or
+    /// check that a site can be fetched without keeping it
+    #[arg(long, conflicts_with_all = ["dedupe", "etag", "timestamping"])]
+    delete_after: bool,
 }
 
-impl Spinner {
-    fn new(chars: Option<Vec<char>>) -> Self {
-        match chars {
-            Some(ch) => Spinner {
-                chars: ch,
-                stop_tx: None,
-            },
-            None => {
-                let chars = vec!['-', '\\', '|', '/'];
-                Spinner {
-                    chars,
-                    stop_tx: None,
-                }
-            }
+impl From<&DownloadArgs> for DownloadOptions {
+    fn from(args: &DownloadArgs) -> Self {
+        let clobber = if args.no_clobber {
+            ClobberPolicy::NoClobber
+        } else if args.force {
+            ClobberPolicy::Force
+        } else {
+            ClobberPolicy::Warn
+        };
+        DownloadOptions {
+            clobber,
+            max_filesize: args.max_filesize,
+            etag: args.etag,
+            timestamping: args.timestamping,
+            referer: args.referer.clone(),
+            progress_format: args.progress,
+            progress_template: args.progress_template.clone(),
+            progress_chars: args.progress_chars.clone(),
+            dedupe: args.dedupe.then(Arc::default),
+            output_template: args.output_template.clone(),
+            create_dirs: args.create_dirs,
+            decode_query: args.decode_query,
+            add_extension: args.adjust_extension,
+            delete_after: args.delete_after,
+            ..DownloadOptions::default()
         }
     }
-
-    fn start(&mut self) -> thread::JoinHandle<()> {
-        let (tx, rx) = mpsc::channel::<bool>();
-        let chars = self.chars.clone();
-        self.stop_tx = Some(tx);
-
-        thread::spawn(move || {
-            let mut i = 0;
-            loop {
-                if rx.try_recv().is_ok_and(|x| x) {
-                    break;
-                }
-
-                print!("\r{}", chars[i]);
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
-                thread::sleep(Duration::from_millis(100));
-                i = (i + 1) % chars.len();
-            }
-            println!("\rDone!        ");
-        })
-    }
-
-    fn stop(&mut self) {
-        // Dropping the sender stops the spinner
-        self.stop_tx.as_ref().unwrap().send(true).unwrap();
-        self.stop_tx = None;
-    }
 }
 
-struct Worker {
-    id: usize,
-    thread: thread::JoinHandle<()>,
+/// the slice of --start-byte and --end-byte, none for the whole file
+fn byte_range(start: Option<u64>, end: Option<u64>) -> Result<Option<ByteRange>, RgetError> {
+    let start = match (start, end) {
+        (None, None) => return Ok(None),
+        (Some(start), Some(end)) if start > end => {
+            return Err(RgetError::Config(format!(
+                "--start-byte {start} is after --end-byte {end}"
+            )));
+        }
+        (start, _) => start.unwrap_or(0),
+    };
+    Ok(Some(ByteRange { start, end }))
 }
 
-impl Worker {
-    fn new(id: usize) -> Worker {
-        let thread = thread::spawn(|| {});
-
-        Worker { id, thread }
-    }
+/// parses a duration in seconds like `2` or `0.5`
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|_| format!("invalid number of seconds {seconds:?}"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
 }
 
-struct ThreadPool {
-    workers: Vec<Worker>,
+/// parses a request method, methods are case sensitive but everyone means `POST` by `post`
+fn parse_method(method: &str) -> Result<Method, String> {
+    method
+        .to_ascii_uppercase()
+        .parse()
+        .map_err(|_| format!("invalid method {method:?}"))
 }
 
-impl ThreadPool {
-    fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(Worker::new(id));
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
         }
-
-        ThreadPool { workers }
-    }
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
+    };
+
+    // the running downloads are dropped when the select finishes, this way the progress bars are
+    // abandoned properly before we exit
+    let stats = Arc::new(Stats::default());
+    let res = tokio::select! {
+        res = run(&args, &stats) => Some(res),
+        Ok(()) = tokio::signal::ctrl_c() => None,
+    };
+    // the stats are written for failed and interrupted runs as well, that is when they matter
+    if let Some(path) = &args.stats_file
+        && let Err(e) = stats.write(path)
     {
+        eprintln!("failed to write the stats to {}: {e}", path.display());
     }
-}
 
-#[derive(Debug, Clone)]
-struct Node {
-    value: String,
-    children: Vec<Self>,
+    match res {
+        Some(Ok(())) => ExitCode::SUCCESS,
+        Some(Err(e)) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+        None => {
+            eprintln!(
+                "\ninterrupted, incomplete downloads are kept as .part files, `rget resume` continues them"
+            );
+            std::process::exit(130);
+        }
+    }
 }
 
-fn hash_file_name(s: String) -> String {
-    let mut hasher = DefaultHasher::new();
-    s.hash(&mut hasher);
-    format!("{:x}", hasher.finish())
+/// parses the command line, flags that are not given are taken from the config file
+fn parse_args() -> Result<Args, RgetError> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let argv = match config::config_path() {
+        Some(path) => config::with_config::<Args>(argv, &config::load(&path)?)?,
+        None => argv,
+    };
+    Ok(Args::parse_from(argv))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// the tls implementation reqwest is built with, the default features of reqwest use native-tls
+const TLS_BACKEND: &str = "native-tls";
+
+fn print_version() {
+    println!("rget {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "commit:  {}",
+        option_env!("RGET_GIT_COMMIT").unwrap_or("unknown")
+    );
+    println!(
+        "reqwest: {}",
+        option_env!("RGET_REQWEST_VERSION").unwrap_or("unknown")
+    );
+    println!("tls:     {TLS_BACKEND}");
+}
 
+async fn run(args: &Args, stats: &Arc<Stats>) -> Result<(), RgetError> {
+    // every client and download of the run counts into the same stats and draws on the same bars
+    let progress = MultiProgress::new();
+    let new_client = |urls: &[String]| {
+        HttpClient::new(&args.client, urls).map(|client| {
+            client
+                .with_stats(stats.clone())
+                .with_progress(progress.clone())
+        })
+    };
+    let download_options = |download: &DownloadArgs| DownloadOptions {
+        stats: Some(stats.clone()),
+        progress: progress.clone(),
+        ..download.into()
+    };
     match &args.subs {
-        SubCom::Interactive { outfile } => {
-            return loop_download(outfile).await;
+        SubCom::Version => {
+            print_version();
+            Ok(())
         }
-        SubCom::Get { url, outfile } => {
-            return download(url, outfile).await;
+        SubCom::Interactive { outfile, download } => {
+            let client = new_client(&[])?;
+            return loop_download(&client, outfile, &download_options(download)).await;
         }
-        SubCom::GetDepth { url, depth } => download_depth(url, *depth).await,
-    }
-}
+        SubCom::Tui { download } => {
+            let client = new_client(&[])?;
+            tui::run(&client, &download_options(download)).await
+        }
+        SubCom::Get {
+            urls,
+            input_file,
+            outfile,
+            parallel,
+            connections,
+            method,
+            data,
+            data_file,
+            spider,
+            exec,
+            start_byte,
+            end_byte,
+            unix_socket,
+            trust_server_names,
+            text,
+            convert_newlines,
+            verbose,
+            mirror,
+            download: dl,
+        } => {
+            // clap only sees the conflict when the global flags come after the subcommand
+            if unix_socket.is_some() && (args.client.proxy.is_some() || args.client.http2) {
+                return Err(RgetError::Config(
+                    "--unix-socket cannot be used with --proxy or --http2".to_string(),
+                ));
+            }
+            let mut targets: Vec<Target> = urls.iter().map(|url| Target::new(url)).collect();
+            if let Some(path) = input_file {
+                let content = fs::read_to_string(path).map_err(|source| RgetError::ReadFile {
+                    path: path.clone(),
+                    source,
+                })?;
+                targets.extend(parse_input_file(&content));
+            }
+            // invalid urls are kept as they are, their downloads fail with the reason
+            for target in &mut targets {
+                if let Ok(url) = normalize_url(&target.url) {
+                    target.url = url;
+                }
+            }
+
+            let mirrors: Vec<String> = mirror
+                .iter()
+                .map(|url| normalize_url(url).unwrap_or_else(|_| url.clone()))
+                .collect();
 
-async fn get_urls(root_url: String, max_depth: usize) -> Tree<String> {
-    let mut cur_width = 1;
-    let mut next_width = 1;
-    let mut cur_count = 1;
-    let mut q: Queue<TreeNodeRef<String>> = Queue::default();
-    let root = TreeNode::new(root_url);
-    let mut url_tree: Tree<String> = Tree::new(root);
-    q.push(url_tree.root.clone());
-    dbg!(format!(
-        "queue is empty: {} and max_depth < cur_depth {}",
-        q.is_empty(),
-        max_depth <= url_tree.depth
-    ));
-    while !q.is_empty() && max_depth > url_tree.depth {
-        dbg!("info", cur_count, next_width, url_tree.depth, cur_width);
-        if let Some(cur) = q.pop() {
-            cur_count += 1;
-            let cur_clone = cur.clone();
-            let current_url = {
-                let current = cur_clone.borrow();
-                current.value.clone()
+            let urls: Vec<String> = targets.iter().map(|t| t.url.clone()).collect();
+            let client = new_client(&[urls.as_slice(), &mirrors].concat())?;
+            if *spider {
+                return rget::spider::spider(&client, &urls).await;
+            }
+            let body = match (data, data_file) {
+                (Some(data), _) => Some(data.clone().into_bytes()),
+                (None, Some(path)) => {
+                    Some(fs::read(path).map_err(|source| RgetError::ReadFile {
+                        path: path.clone(),
+                        source,
+                    })?)
+                }
+                (None, None) => None,
             };
-            let res = reqwest::get(current_url)
-                .await
-                .unwrap()
-                .error_for_status()
-                .unwrap();
-            let content_type = ContentType::from_header_value(res.headers().get(CONTENT_TYPE));
-            match content_type {
-                ContentType::Text(_) => {
-                    let site = res.text().await.unwrap();
-                    let nodes = find_https_links_with_parser(&site);
-                    next_width += &nodes.len();
-
-                    for node in nodes {
-                        dbg!("adding node", &node);
-                        let tree_node = TreeNode::new(node);
-                        let tree_node_ref = Rc::new(RefCell::new(tree_node));
-                        let clone = tree_node_ref.clone();
-                        q.push(tree_node_ref);
-                        Tree::push_node(cur.clone(), clone);
-                    }
-                    if cur_width <= cur_count {
-                        cur_width = next_width;
-                        next_width = 0;
-                        cur_count = 0;
-                        url_tree.depth += 1;
-                    }
+            let method = match method {
+                Some(method) => method.clone(),
+                None if body.is_some() => Method::POST,
+                None => Method::GET,
+            };
+            let options = DownloadOptions {
+                connections: connections.get(),
+                method,
+                body,
+                exec: exec.clone(),
+                byte_range: byte_range(*start_byte, *end_byte)?,
+                unix_socket: unix_socket.clone(),
+                trust_server_names: *trust_server_names,
+                text: *text,
+                convert_newlines: *convert_newlines,
+                verbose: *verbose,
+                ..download_options(dl)
+            };
+            match (targets.as_slice(), outfile) {
+                ([target], outfile) if input_file.is_none() => {
+                    // a name given with --outfile is kept
+                    let options = DownloadOptions {
+                        trust_server_names: options.trust_server_names && outfile.is_none(),
+                        add_extension: options.add_extension && outfile.is_none(),
+                        ..options
+                    };
+                    let outfile = match (outfile, &options.output_template) {
+                        (Some(outfile), _) => outfile.clone(),
+                        (None, Some(template)) => {
+                            let outfile = template.file_name(&target.url, 1);
+                            create_parent_dir(&outfile)?;
+                            outfile
+                        }
+                        (None, None) => options
+                            .decode_query
+                            .then(|| file_name_from_query(&target.url))
+                            .flatten()
+                            .unwrap_or_else(|| OUT_FILE.to_string()),
+                    };
+                    let urls: Vec<String> =
+                        [target.url.clone()].into_iter().chain(mirrors).collect();
+                    download_mirrors(&client, &urls, &outfile, &options)
+                        .await
+                        .map(|_| ())
                 }
-                ContentType::Other(string) => {
-                    println!(
-                        "other content type: {string} stopping at depth {0}",
-                        url_tree.depth
-                    );
-                    continue;
+                (_, Some(_)) => Err(RgetError::Config(
+                    "--outfile can only be used with a single url".to_string(),
+                )),
+                _ if !mirrors.is_empty() => Err(RgetError::Config(
+                    "--mirror can only be used with a single url".to_string(),
+                )),
+                (targets, None) => download_all(&client, targets, parallel.get(), &options).await,
+            }
+        }
+        SubCom::GetDepth {
+            url,
+            depth,
+            accept,
+            reject,
+            dry_run,
+            spider,
+            exec,
+            largest_only,
+            compress,
+            compression_level,
+            single_file,
+            crawl,
+            download,
+        } => {
+            // the time limit covers everything from here on, including the crawl
+            let crawl_options = CrawlOptions {
+                deadline: crawl.max_time.map(|max_time| Instant::now() + max_time),
+                largest_only: largest_only.map(NonZeroUsize::get),
+                ..crawl.into()
+            };
+            let options = DownloadOptions {
+                filter: FileFilter {
+                    accept: accept.clone(),
+                    reject: reject.clone(),
+                },
+                exec: exec.clone(),
+                compress: *compress,
+                compression_level: *compression_level,
+                ..download_options(download)
+            };
+            let url = &normalize_url(url)?;
+            // a host that is overloaded by the crawl gets a break instead of more requests
+            let breakers = HostBreakers::new(options.progress.clone());
+            let client = new_client(std::slice::from_ref(url))?.with_breakers(Arc::new(breakers));
+            if let Some(outfile) = single_file {
+                return rget::single_file::single_file(&client, url, outfile, &options)
+                    .await
+                    .map(|_| ());
+            }
+            if *spider {
+                return rget::spider::spider_depth(&client, url, *depth, &crawl_options).await;
+            }
+            if *dry_run {
+                return rget::crawl::dry_run(&client, url, *depth, &crawl_options, &options).await;
+            }
+            download_depth(&client, url, *depth, &crawl_options, options).await
+        }
+        SubCom::Sitemap {
+            url,
+            parallel,
+            list,
+            download,
+        } => {
+            let url = &normalize_url(url)?;
+            let client = new_client(std::slice::from_ref(url))?;
+            let urls = rget::sitemap::sitemap_urls(&client, url).await?;
+            if *list {
+                for url in &urls {
+                    println!("{url}");
                 }
-                _ => unreachable!("the header should work"),
+                return Ok(());
             }
+            println!("found {} urls in {url}", urls.len());
+            let targets: Vec<Target> = urls.iter().map(|url| Target::new(url)).collect();
+            download_all(
+                &client,
+                &targets,
+                parallel.get(),
+                &download_options(download),
+            )
+            .await
+        }
+        SubCom::Head { url } => {
+            let url = &normalize_url(url)?;
+            let client = new_client(std::slice::from_ref(url))?;
+            rget::head::head(&client, url).await
+        }
+        SubCom::Resume {
+            dir,
+            parallel,
+            download,
+        } => {
+            let client = new_client(&[])?;
+            let options = download_options(download);
+            rget::resume::resume_all(&client, dir, parallel.get(), &options).await
         }
     }
-    url_tree
 }
 
-fn find_https_links_with_parser(html_content: &str) -> Vec<String> {
-    let document = Html::parse_document(html_content);
-
-    let href_selector =
-        Selector::parse("body a[href], body img[src]").expect("Failed to create selector");
-
-    let mut https_urls = Vec::new();
+const REPL_HELP: &str = "\
+<url> [outfile]        download url to outfile (defaults to the current outfile) in the background
+:set outfile <name>    change the default outfile
+:depth <url> <n>       download url and everything linked from it up to depth n
+:jobs | jobs           list the downloads and their progress
+:wait | wait           wait until all downloads are finished
+:pause | pause <id>    stop a download and keep what it has so far
+:resume | resume <id>  continue a paused download where it stopped
+:help                  show this help
+quit | q               leave interactive mode";
+
+/// a line typed into interactive mode
+#[derive(Debug, PartialEq, Eq)]
+enum ReplCommand {
+    Download {
+        url: String,
+        outfile: Option<String>,
+    },
+    SetOutfile(String),
+    Depth {
+        url: String,
+        depth: usize,
+    },
+    Jobs,
+    Wait,
+    /// stop the job with this id
+    Pause(usize),
+    /// continue the paused job with this id
+    Resume(usize),
+    Help,
+    Quit,
+    /// nothing but whitespace was typed
+    Empty,
+    Invalid(String),
+}
 
-    for element in document.select(&href_selector) {
-        // Check for the 'href' attribute first
-        if let Some(href) = element.attr("href") {
-            if href.starts_with("https://") || href.starts_with("http://") {
-                https_urls.push(href.to_string());
+impl ReplCommand {
+    fn parse(line: &str) -> Self {
+        let mut split = line.split_whitespace();
+        let Some(first) = split.next() else {
+            return ReplCommand::Empty;
+        };
+        let args: Vec<&str> = split.collect();
+
+        match (first, args.as_slice()) {
+            ("quit" | "q", _) => ReplCommand::Quit,
+            (":help", _) => ReplCommand::Help,
+            (":jobs" | "jobs", []) => ReplCommand::Jobs,
+            (":wait" | "wait", []) => ReplCommand::Wait,
+            (cmd @ (":pause" | "pause" | ":resume" | "resume"), args) => {
+                let cmd = cmd.trim_start_matches(':');
+                match args {
+                    [id] => match id.parse() {
+                        Ok(id) if cmd == "pause" => ReplCommand::Pause(id),
+                        Ok(id) => ReplCommand::Resume(id),
+                        Err(_) => ReplCommand::Invalid(format!("not a valid job id: {id}")),
+                    },
+                    _ => ReplCommand::Invalid(format!("usage: {cmd} <id>")),
+                }
             }
-        }
-        // If no 'href', check for the 'src' attribute (for img tags)
-        else if let Some(src) = element.attr("src") {
-            if src.starts_with("https://") {
-                https_urls.push(src.to_string());
+            (":set", ["outfile", name]) => ReplCommand::SetOutfile(name.to_string()),
+            (":set", _) => ReplCommand::Invalid("usage: :set outfile <name>".to_string()),
+            (":depth", [url, depth]) => match (normalize_url(url), depth.parse()) {
+                (Ok(url), Ok(depth)) => ReplCommand::Depth { url, depth },
+                (Err(e), _) => ReplCommand::Invalid(e.to_string()),
+                (_, Err(_)) => ReplCommand::Invalid(format!("not a valid depth: {depth}")),
+            },
+            (":depth", _) => ReplCommand::Invalid("usage: :depth <url> <n>".to_string()),
+            (cmd, _) if cmd.starts_with(':') => {
+                ReplCommand::Invalid(format!("unknown command {cmd}, try :help"))
             }
+            (url, [] | [_]) => match normalize_url(url) {
+                Ok(url) => ReplCommand::Download {
+                    url,
+                    outfile: args.first().map(|outfile| outfile.to_string()),
+                },
+                Err(e) => ReplCommand::Invalid(e.to_string()),
+            },
+            (_, _) => ReplCommand::Invalid("usage: <url> [outfile]".to_string()),
         }
-        // Add checks for other attributes/tags as needed
     }
+}
 
-    https_urls
+/// waits for the running downloads of the session and prints how they ended
+async fn wait_jobs(jobs: &mut Jobs) {
+    for job in jobs.wait().await {
+        println!("[{}] {}", job.id, job.progress().1);
+    }
 }
 
-async fn download_depth(url: &str, depth: usize) -> Result<(), Box<dyn std::error::Error>> {
-    let t: Tree<String> = get_urls(url.to_string(), depth).await;
-    // dbg!("tree", &t);
-    // this is a piece of very ugly code don't know how to fix it yet
-    t.traverse_async(async |url: String| {
-        let clone = url.clone();
-        let outfile = hash_file_name(url.to_string());
-        download(&clone, &outfile).await.unwrap();
-    })
-    .await;
-    Ok(())
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rget_history"))
 }
 
-async fn loop_download(outfile: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn loop_download(
+    client: &HttpClient,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let mut outfile = outfile.to_string();
+    let mut editor = DefaultEditor::new()?;
+    let history = history_file();
+    if let Some(history) = &history {
+        // there is no history yet on the first start
+        let _ = editor.load_history(history);
+    }
+
+    let mut jobs = Jobs::default();
     loop {
-        let mut buf = String::new();
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-        std::io::stdin()
-            .read_line(&mut buf)
-            .expect("You should always be able to read a line");
-
-        let mut split = buf.split_whitespace();
-        let url = split.next().unwrap_or("quit");
-
-        let of = match split.next() {
-            Some(outfile) => outfile,
-            None => outfile,
+        for job in jobs.collect_finished().await {
+            println!("[{}] {}", job.id, job.progress().1);
+        }
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
         };
-
-        if url == "quit" || url == "q" {
-            break;
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
         }
 
-        let res = download(url, of).await;
-        match res {
-            Ok(()) => {}
-            Err(e) => {
-                return Err(e);
+        match ReplCommand::parse(&line) {
+            ReplCommand::Quit => break,
+            ReplCommand::Empty => continue,
+            ReplCommand::Help => println!("{REPL_HELP}"),
+            ReplCommand::Invalid(msg) => println!("{msg}"),
+            ReplCommand::SetOutfile(name) => outfile = name,
+            ReplCommand::Depth { url, depth } => {
+                let crawl_options = CrawlOptions::default();
+                download_depth(client, &url, depth, &crawl_options, options.clone()).await?;
+            }
+            ReplCommand::Download { url, outfile: of } => {
+                let of = of.unwrap_or_else(|| outfile.clone());
+                match jobs.writing(&of) {
+                    Some(job) => println!("[{}] is still downloading to {of}", job.id),
+                    None => {
+                        let id = jobs.start(url, of, client, options);
+                        println!("[{id}] started");
+                    }
+                }
+            }
+            ReplCommand::Jobs => {
+                for job in &jobs.jobs {
+                    println!("[{}] {}", job.id, job.progress().1);
+                }
             }
+            ReplCommand::Wait => wait_jobs(&mut jobs).await,
+            ReplCommand::Pause(id) => match jobs.pause(id) {
+                Ok(()) => println!("[{id}] paused"),
+                Err(e) => println!("{e}"),
+            },
+            ReplCommand::Resume(id) => match jobs.resume(id, client, options) {
+                Ok(()) => println!("[{id}] resumed"),
+                Err(e) => println!("{e}"),
+            },
         }
     }
-    Ok(())
-}
-
-async fn download(url: &str, outfile: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut response = Client::new().get(url).send().await?.error_for_status()?;
-
-    let total_size = response.content_length();
-    match total_size {
-        Some(ts) => download_pb(outfile, ts, &mut response).await,
-        None => download_sp(outfile, response).await,
+    if jobs.jobs.iter().any(Job::is_running) {
+        println!("waiting for the running downloads, ctrl-c stops them");
     }
-}
-
-async fn download_pb(
-    outfile: &str,
-    total_size: u64,
-    response: &mut Response,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta})",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
-    );
-
-    let mut dest = BufWriter::new(File::create(outfile)?);
-
-    let mut downloaded: u64 = 0;
-
-    while let Some(chunk) = response.chunk().await? {
-        dest.write_all(chunk.as_ref())?;
-        downloaded += chunk.len() as u64;
-        pb.set_position(downloaded);
+    wait_jobs(&mut jobs).await;
+    if jobs
+        .jobs
+        .iter()
+        .any(|job| matches!(job.status, Status::Paused))
+    {
+        println!("paused downloads are kept as .part files, `rget resume` continues them");
     }
 
-    pb.finish_with_message("Download complete");
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
     Ok(())
 }
 
-async fn download_sp(outfile: &str, response: Response) -> Result<(), Box<dyn std::error::Error>> {
-    let ab = Arc::new(AtomicBool::new(false));
-    let clone = Arc::clone(&ab);
-    let mut sp = Spinner::new(None);
-
-    let handle = sp.start();
-    let mut outfile = File::create(outfile)?;
-
-    let content = response.bytes().await?;
-    outfile.write_all(&content)?;
-    clone.store(true, Ordering::Relaxed);
-    sp.stop();
-    handle.join().unwrap();
-
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::ReplCommand;
+
+    #[test]
+    fn test_parse_repl_command() {
+        assert_eq!(ReplCommand::parse("q"), ReplCommand::Quit);
+        assert_eq!(ReplCommand::parse("quit\n"), ReplCommand::Quit);
+        // a stray enter does not quit
+        assert_eq!(ReplCommand::parse(""), ReplCommand::Empty);
+        assert_eq!(ReplCommand::parse("  \n"), ReplCommand::Empty);
+        assert_eq!(ReplCommand::parse(":help"), ReplCommand::Help);
+        assert_eq!(ReplCommand::parse("jobs"), ReplCommand::Jobs);
+        assert_eq!(ReplCommand::parse(":wait"), ReplCommand::Wait);
+        assert_eq!(ReplCommand::parse("pause 2"), ReplCommand::Pause(2));
+        assert_eq!(ReplCommand::parse(":pause 2"), ReplCommand::Pause(2));
+        assert_eq!(ReplCommand::parse(":resume 2"), ReplCommand::Resume(2));
+        assert_eq!(ReplCommand::parse("resume 2"), ReplCommand::Resume(2));
+        assert!(matches!(
+            ReplCommand::parse("pause"),
+            ReplCommand::Invalid(_)
+        ));
+        assert!(matches!(
+            ReplCommand::parse("resume two"),
+            ReplCommand::Invalid(_)
+        ));
+        assert_eq!(
+            ReplCommand::parse(":set outfile page.html"),
+            ReplCommand::SetOutfile("page.html".to_string())
+        );
+        assert_eq!(
+            ReplCommand::parse(":depth http://localhost:3000 2"),
+            ReplCommand::Depth {
+                url: "http://localhost:3000/".to_string(),
+                depth: 2
+            }
+        );
+        assert_eq!(
+            ReplCommand::parse("http://localhost:3000 out"),
+            ReplCommand::Download {
+                url: "http://localhost:3000/".to_string(),
+                outfile: Some("out".to_string())
+            }
+        );
+        assert_eq!(
+            ReplCommand::parse("localhost:3000"),
+            ReplCommand::Download {
+                url: "https://localhost:3000/".to_string(),
+                outfile: None
+            }
+        );
+        assert!(matches!(
+            ReplCommand::parse(":depth http://localhost:3000 two"),
+            ReplCommand::Invalid(_)
+        ));
+        assert!(matches!(
+            ReplCommand::parse(":nope"),
+            ReplCommand::Invalid(_)
+        ));
+        assert!(matches!(
+            ReplCommand::parse("hello"),
+            ReplCommand::Invalid(_)
+        ));
+    }
 }
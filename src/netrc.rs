@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use http::HeaderValue;
+
+use crate::error::RgetError;
+
+/// the login of a host in a netrc file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Credentials {
+    pub(crate) login: String,
+    pub(crate) password: String,
+}
+
+impl Credentials {
+    fn empty() -> Self {
+        Credentials {
+            login: String::new(),
+            password: String::new(),
+        }
+    }
+
+    /// the Authorization header of basic auth with these credentials
+    pub(crate) fn basic_auth(&self) -> HeaderValue {
+        let encoded = STANDARD.encode(format!("{}:{}", self.login, self.password));
+        let mut value = HeaderValue::from_str(&format!("Basic {encoded}"))
+            .expect("base64 is a valid header value");
+        value.set_sensitive(true);
+        value
+    }
+}
+
+/// the credentials of a netrc file by host, the `default` entry is used for all other hosts
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Netrc {
+    machines: HashMap<String, Credentials>,
+    default: Option<Credentials>,
+}
+
+impl Netrc {
+    /// the credentials for `host`
+    pub(crate) fn credentials(&self, host: &str) -> Option<&Credentials> {
+        self.machines
+            .get(&host.to_ascii_lowercase())
+            .or(self.default.as_ref())
+    }
+}
+
+/// `~/.netrc`, where curl and wget look for it as well
+pub(crate) fn default_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc"))
+}
+
+pub(crate) fn read(path: &Path) -> Result<Netrc, RgetError> {
+    let content = fs::read_to_string(path).map_err(|source| RgetError::ReadFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(&content)
+}
+
+/// the words of a netrc file, `#` starts a comment and a value can be quoted to contain spaces
+///
+/// the lines of a `macdef` up to the next empty line are the macro and not part of the entries
+fn tokens(content: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            match chars.peek() {
+                None | Some('#') => break,
+                Some('"') => {
+                    chars.next();
+                    let mut token = String::new();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => token.extend(chars.next()),
+                            c => token.push(c),
+                        }
+                    }
+                    tokens.push(token);
+                }
+                Some(_) => {
+                    let token: String =
+                        std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect();
+                    tokens.push(token);
+                }
+            }
+        }
+        if tokens.len() >= 2 && tokens[tokens.len() - 2] == "macdef" {
+            tokens.truncate(tokens.len() - 2);
+            lines
+                .by_ref()
+                .take_while(|line| !line.trim().is_empty())
+                .for_each(drop);
+        }
+    }
+    tokens
+}
+
+/// parses the `machine`, `login` and `password` entries of a netrc file, the first entry of a
+/// host wins and unknown words are skipped like in curl
+pub(crate) fn parse(content: &str) -> Result<Netrc, RgetError> {
+    let invalid = |reason: String| RgetError::Config(format!("invalid netrc file: {reason}"));
+    let mut netrc = Netrc::default();
+    // every entry in the order of the file with its host, none for `default`
+    let mut entries: Vec<(Option<String>, Credentials)> = Vec::new();
+    let mut tokens = tokens(content).into_iter();
+    while let Some(token) = tokens.next() {
+        let mut value = |name: &str| {
+            tokens
+                .next()
+                .ok_or_else(|| invalid(format!("{name} without a value")))
+        };
+        let entry = entries.last_mut();
+        match (token.as_str(), entry) {
+            ("machine", _) => {
+                let host = value("machine")?.to_ascii_lowercase();
+                entries.push((Some(host), Credentials::empty()));
+            }
+            ("default", _) => entries.push((None, Credentials::empty())),
+            ("login", Some((_, credentials))) => credentials.login = value("login")?,
+            ("password", Some((_, credentials))) => credentials.password = value("password")?,
+            ("account", Some(_)) => {
+                value("account")?;
+            }
+            (token @ ("login" | "password" | "account"), None) => {
+                return Err(invalid(format!("{token} before the first machine")));
+            }
+            // like curl, words rget does not know are skipped
+            _ => {}
+        }
+    }
+    for (host, credentials) in entries {
+        match host {
+            Some(host) => {
+                netrc.machines.entry(host).or_insert(credentials);
+            }
+            None => {
+                netrc.default.get_or_insert(credentials);
+            }
+        }
+    }
+    Ok(netrc)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Credentials, parse};
+
+    #[test]
+    fn test_parse_netrc() {
+        let content = r#"
+# the intranet
+machine files.example.com login alice password "s3cret pass"
+machine Example.org
+    login bob
+    password hunter2
+    account ignored
+
+macdef init
+cd /pub
+machine inside.macro login x password y
+
+machine example.org login carol password other
+default login anonymous password me@example.com
+"#;
+        let netrc = parse(content).unwrap();
+        let credentials = |login: &str, password: &str| Credentials {
+            login: login.to_string(),
+            password: password.to_string(),
+        };
+        assert_eq!(
+            netrc.credentials("files.example.com"),
+            Some(&credentials("alice", "s3cret pass"))
+        );
+        // hosts are case insensitive and the first entry of a host wins
+        assert_eq!(
+            netrc.credentials("EXAMPLE.org"),
+            Some(&credentials("bob", "hunter2"))
+        );
+        assert_eq!(
+            netrc.credentials("inside.macro"),
+            Some(&credentials("anonymous", "me@example.com"))
+        );
+        assert_eq!(
+            credentials("bob", "hunter2").basic_auth(),
+            "Basic Ym9iOmh1bnRlcjI="
+        );
+
+        assert!(parse("machine").is_err());
+        assert!(parse("login alice").is_err());
+        // unknown words are skipped
+        assert_eq!(
+            parse("machine a port 21 login alice")
+                .unwrap()
+                .credentials("a"),
+            Some(&credentials("alice", ""))
+        );
+        assert_eq!(parse("").unwrap().credentials("a"), None);
+    }
+}
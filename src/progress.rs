@@ -0,0 +1,515 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressFinish, ProgressStyle};
+use serde::Serialize;
+
+use crate::download::{
+    DownloadOptions, OnProgress, ProgressFormat, Transfer, parse_progress_chars,
+};
+use crate::stats::Stats;
+
+/// how often a json progress event is printed at most
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// how often a progress bar is redrawn even when no bytes arrive, a resized terminal or a stalled
+/// download is drawn right away
+const STEADY_TICK: Duration = Duration::from_millis(200);
+/// how far back the speed shown next to a bar is averaged
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+/// the bytes of the speed window are summed up in slots of this length
+const SPEED_SLOT: Duration = Duration::from_millis(100);
+/// the message of a spinner whose download failed
+const ABORTED: &str = "aborted";
+/// how a download of unknown size is shown
+const SPINNER_TEMPLATE: &str = "{spinner} {bytes} ({bytes_per_sec}) {msg}";
+
+/// the indicatif template and bar characters of a progress bar preset
+fn preset(format: ProgressFormat) -> (&'static str, &'static str) {
+    match format {
+        ProgressFormat::Dots => ("{wide_bar} {bytes}/{total_bytes} {msg}", "•· "),
+        ProgressFormat::Minimal => ("{percent:>3}% {bytes}/{total_bytes}", "#>-"),
+        ProgressFormat::Bar | ProgressFormat::Json => (
+            "[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} {msg}",
+            "#>-",
+        ),
+    }
+}
+
+pub(crate) fn progress_bar(total_size: u64, options: &DownloadOptions) -> ProgressBar {
+    // when the download fails or is interrupted the bar stays where it stopped
+    let pb = options
+        .progress
+        .add(ProgressBar::new(total_size).with_finish(ProgressFinish::Abandon));
+    pb.set_style(bar_style(options));
+    pb.enable_steady_tick(STEADY_TICK);
+    pb
+}
+
+/// a spinner for a download of unknown size, drawn with the bars of the run. Unlike a bar it does
+/// not show how far a failed download got, so it says that it was aborted
+fn progress_spinner(options: &DownloadOptions) -> ProgressBar {
+    let aborted = ProgressFinish::AbandonWithMessage(ABORTED.into());
+    let sp = options
+        .progress
+        .add(ProgressBar::new_spinner().with_finish(aborted));
+    sp.set_style(ProgressStyle::with_template(SPINNER_TEMPLATE).expect("a valid template"));
+    sp.enable_steady_tick(STEADY_TICK);
+    sp
+}
+
+/// the style of the preset with the template and characters of the options, an invalid template
+/// or invalid characters fall back to the preset with a warning instead of failing the download
+fn bar_style(options: &DownloadOptions) -> ProgressStyle {
+    static WARN: Once = Once::new();
+
+    custom_style(options).unwrap_or_else(|e| {
+        WARN.call_once(|| {
+            options.suspend(|| eprintln!("warning: {e}, using the default progress bar"));
+        });
+        let (template, chars) = preset(options.progress_format);
+        ProgressStyle::with_template(template)
+            .expect("the presets are valid templates")
+            .progress_chars(chars)
+    })
+}
+
+/// the style of the preset with the template and characters of the options, the error says which
+/// of them is invalid
+fn custom_style(options: &DownloadOptions) -> Result<ProgressStyle, String> {
+    let (template, chars) = preset(options.progress_format);
+    let template = options.progress_template.as_deref().unwrap_or(template);
+    let chars = options.progress_chars.as_deref().unwrap_or(chars);
+    let style = ProgressStyle::with_template(template)
+        .map_err(|e| format!("invalid progress template: {e}"))?;
+    Ok(style.progress_chars(&parse_progress_chars(chars)?))
+}
+
+/// the speed of a download averaged over the last [`SPEED_WINDOW`], which stays steady on a
+/// bursty connection where the estimate of indicatif jumps around
+struct Speed {
+    start: Instant,
+    /// the bytes received in each slot since `start` that is still in the window, oldest first
+    slots: VecDeque<(u64, u64)>,
+    /// when the message of the bar was last changed
+    shown: Instant,
+}
+
+impl Speed {
+    fn new(now: Instant) -> Self {
+        Speed {
+            start: now,
+            slots: VecDeque::new(),
+            shown: now,
+        }
+    }
+
+    fn slot(&self, now: Instant) -> u64 {
+        (now.duration_since(self.start).as_millis() / SPEED_SLOT.as_millis()) as u64
+    }
+
+    fn record(&mut self, bytes: u64, now: Instant) {
+        let slot = self.slot(now);
+        match self.slots.back_mut() {
+            Some((last, received)) if *last == slot => *received += bytes,
+            _ => self.slots.push_back((slot, bytes)),
+        }
+    }
+
+    /// the bytes per second of the window that ends at `now`, a download younger than the window
+    /// is averaged over its whole time
+    fn bytes_per_sec(&mut self, now: Instant) -> u64 {
+        let window = (SPEED_WINDOW.as_millis() / SPEED_SLOT.as_millis()) as u64;
+        let slot = self.slot(now);
+        while self
+            .slots
+            .front()
+            .is_some_and(|(first, _)| first + window <= slot)
+        {
+            self.slots.pop_front();
+        }
+        let span = now.duration_since(self.start).min(SPEED_WINDOW);
+        if span.is_zero() {
+            return 0;
+        }
+        let received: u64 = self.slots.iter().map(|(_, bytes)| bytes).sum();
+        (received as f64 / span.as_secs_f64()) as u64
+    }
+
+    /// the speed and the time left for the `remaining` bytes at that speed
+    fn message(&mut self, remaining: u64, now: Instant) -> String {
+        match self.bytes_per_sec(now) {
+            0 => "stalled".to_string(),
+            speed => format!(
+                "{}/s, {} left",
+                HumanBytes(speed),
+                HumanDuration(Duration::from_secs(remaining.div_ceil(speed)))
+            ),
+        }
+    }
+}
+
+/// how the progress of a single download is shown, as json events, to a [`Transfer`] or through
+/// an [`OnProgress`] callback. The bars and spinners are callbacks as well, see [`bar_progress`]
+/// and [`spinner_progress`]
+enum Progress {
+    Json(JsonProgress),
+    /// the caller shows the progress
+    Transfer(Arc<Transfer>),
+    Callback {
+        callback: OnProgress,
+        total: Option<u64>,
+        downloaded: AtomicU64,
+        /// what the callback draws on, it is finished with the download
+        display: Display,
+    },
+}
+
+/// what the callback of a [`Progress`] draws on
+enum Display {
+    /// the callback of the caller
+    Caller,
+    Bar(ProgressBar),
+    Spinner(ProgressBar),
+}
+
+/// the progress of a single download, the bytes are counted in the [`Stats`] of the run as well
+pub(crate) struct DownloadProgress {
+    progress: Progress,
+    stats: Option<Arc<Stats>>,
+}
+
+impl DownloadProgress {
+    /// the progress of a download of `total` bytes, `None` if the size is not known
+    pub(crate) fn new(url: &str, total: Option<u64>, options: &DownloadOptions) -> Self {
+        DownloadProgress {
+            progress: Progress::new(url, total, options),
+            stats: options.stats.clone(),
+        }
+    }
+
+    /// the progress of a download that continues at `offset`, the bytes before it are shown as
+    /// done but are not counted as received
+    pub(crate) fn resumed(
+        url: &str,
+        total: Option<u64>,
+        offset: u64,
+        options: &DownloadOptions,
+    ) -> Self {
+        let pb = DownloadProgress::new(url, total, options);
+        pb.progress.inc(offset);
+        pb
+    }
+
+    pub(crate) fn inc(&self, bytes: u64) {
+        if let Some(stats) = &self.stats {
+            stats.received(bytes);
+        }
+        self.progress.inc(bytes);
+    }
+
+    /// marks the download as complete, it was saved to `path`
+    pub(crate) fn finish(self, path: &str) {
+        self.progress.finish(path);
+    }
+
+    /// stops the progress of a failed download, a bar stays where it stopped
+    pub(crate) fn abandon(self) {
+        self.progress.abandon();
+    }
+}
+
+impl Progress {
+    fn new(url: &str, total: Option<u64>, options: &DownloadOptions) -> Self {
+        if let Some(transfer) = &options.transfer {
+            transfer.start(total);
+            return Progress::Transfer(transfer.clone());
+        }
+        if let Some(callback) = &options.on_progress {
+            return Progress::callback(callback.clone(), total, Display::Caller);
+        }
+        match (options.progress_format, total) {
+            (ProgressFormat::Json, total) => Progress::Json(JsonProgress::start(url, total)),
+            (_, Some(total)) => {
+                let pb = progress_bar(total, options);
+                Progress::callback(bar_progress(pb.clone()), Some(total), Display::Bar(pb))
+            }
+            (_, None) => {
+                let sp = progress_spinner(options);
+                Progress::callback(spinner_progress(sp.clone()), None, Display::Spinner(sp))
+            }
+        }
+    }
+
+    /// the progress reported to `callback`, it is called with 0 right away
+    fn callback(callback: OnProgress, total: Option<u64>, display: Display) -> Self {
+        callback.call(0, total);
+        Progress::Callback {
+            callback,
+            total,
+            downloaded: AtomicU64::new(0),
+            display,
+        }
+    }
+
+    fn inc(&self, bytes: u64) {
+        match self {
+            Progress::Json(json) => json.inc(bytes),
+            Progress::Transfer(transfer) => transfer.inc(bytes),
+            Progress::Callback {
+                callback,
+                total,
+                downloaded,
+                ..
+            } => {
+                let downloaded = downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+                callback.call(downloaded, *total);
+            }
+        }
+    }
+
+    fn finish(self, path: &str) {
+        match self {
+            Progress::Callback {
+                display: Display::Bar(pb) | Display::Spinner(pb),
+                ..
+            } => pb.finish_with_message("Download complete"),
+            Progress::Json(json) => json.done(path),
+            progress => progress.abandon(),
+        }
+    }
+
+    fn abandon(self) {
+        if let Progress::Callback { display, .. } = self {
+            match display {
+                Display::Bar(pb) => pb.abandon(),
+                Display::Spinner(sp) => sp.abandon_with_message(ABORTED),
+                Display::Caller => {}
+            }
+        }
+    }
+}
+
+/// draws the progress on `pb` with the speed of the last seconds as its message
+fn bar_progress(pb: ProgressBar) -> OnProgress {
+    // the parts of a download over several connections may report out of order, the bar only
+    // moves forward
+    let state = Mutex::new((Speed::new(Instant::now()), 0));
+    OnProgress::new(move |downloaded, _| {
+        let mut state = state.lock().unwrap();
+        let (speed, position) = &mut *state;
+        if downloaded <= *position {
+            return;
+        }
+        let now = Instant::now();
+        speed.record(downloaded - *position, now);
+        *position = downloaded;
+        pb.set_position(downloaded);
+        // the message is redrawn with the bar, changing it more often is wasted work
+        if now.duration_since(speed.shown) >= STEADY_TICK {
+            speed.shown = now;
+            let remaining = pb.length().unwrap_or(0).saturating_sub(downloaded);
+            pb.set_message(speed.message(remaining, now));
+        }
+    })
+}
+
+/// counts the bytes received on the spinner `sp`, it shows them with their speed
+fn spinner_progress(sp: ProgressBar) -> OnProgress {
+    OnProgress::new(move |downloaded, _| sp.set_position(downloaded))
+}
+
+/// prints the progress of a download as newline delimited json events to stderr, a `start` event,
+/// a `progress` event at most every [`JSON_PROGRESS_INTERVAL`] and a `done` event
+pub(crate) struct JsonProgress {
+    url: String,
+    downloaded: AtomicU64,
+    last_event: Mutex<Instant>,
+}
+
+impl JsonProgress {
+    fn start(url: &str, total: Option<u64>) -> Self {
+        JsonEvent::Start { url, total }.print();
+        JsonProgress {
+            url: url.to_string(),
+            downloaded: AtomicU64::new(0),
+            last_event: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn inc(&self, bytes: u64) {
+        let downloaded = self.downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let mut last_event = self.last_event.lock().unwrap();
+        if last_event.elapsed() >= JSON_PROGRESS_INTERVAL {
+            *last_event = Instant::now();
+            JsonEvent::Progress {
+                url: &self.url,
+                downloaded,
+            }
+            .print();
+        }
+    }
+
+    fn done(&self, path: &str) {
+        let downloaded = self.downloaded.load(Ordering::Relaxed);
+        JsonEvent::Done {
+            url: &self.url,
+            path,
+            downloaded,
+        }
+        .print();
+    }
+}
+
+/// an event of the json progress, serialized with its kind in the `event` field
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum JsonEvent<'a> {
+    Start {
+        url: &'a str,
+        total: Option<u64>,
+    },
+    Progress {
+        url: &'a str,
+        downloaded: u64,
+    },
+    Done {
+        url: &'a str,
+        path: &'a str,
+        downloaded: u64,
+    },
+}
+
+impl JsonEvent<'_> {
+    fn print(&self) {
+        eprintln!("{}", serde_json::to_string(self).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+
+    use super::{
+        Display, JsonEvent, Progress, Speed, bar_progress, bar_style, custom_style,
+        progress_spinner, spinner_progress,
+    };
+    use crate::download::DownloadOptions;
+
+    #[test]
+    fn test_speed_window() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut speed = Speed::new(start);
+        assert_eq!(speed.bytes_per_sec(start), 0);
+        // a burst at the start is spread over the time since then
+        speed.record(1000, at(100));
+        assert_eq!(speed.bytes_per_sec(at(1000)), 1000);
+        // 10 bytes every 100ms from 1s to 10s
+        for millis in (1000..10_000).step_by(100) {
+            speed.record(10, at(millis));
+        }
+        // only the last 5 seconds count, the burst is out of the window
+        assert_eq!(speed.bytes_per_sec(at(9_950)), 100);
+        assert_eq!(speed.message(1000, at(9_950)), "100 B/s, 10 seconds left");
+        assert_eq!(speed.message(1000, at(20_000)), "stalled");
+    }
+
+    #[test]
+    fn test_bar_progress() {
+        let pb = ProgressBar::hidden();
+        pb.set_length(1000);
+        let callback = bar_progress(pb.clone());
+        callback.call(500, Some(1000));
+        // a connection that reports late does not move the bar back
+        callback.call(300, Some(1000));
+        assert_eq!(pb.position(), 500);
+        callback.call(1000, Some(1000));
+        assert_eq!(pb.position(), 1000);
+    }
+
+    #[test]
+    fn test_spinner_progress() {
+        let options = DownloadOptions {
+            progress: MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            ..DownloadOptions::default()
+        };
+        let sp = progress_spinner(&options);
+        spinner_progress(sp.clone()).call(500, None);
+        assert_eq!(sp.position(), 500);
+        assert_eq!(sp.length(), None);
+
+        // only a finished download is complete, a failed one is aborted
+        Progress::callback(
+            spinner_progress(sp.clone()),
+            None,
+            Display::Spinner(sp.clone()),
+        )
+        .abandon();
+        assert!(sp.is_finished());
+        assert_eq!(sp.message(), "aborted");
+        let sp = progress_spinner(&options);
+        Progress::callback(
+            spinner_progress(sp.clone()),
+            None,
+            Display::Spinner(sp.clone()),
+        )
+        .finish("a");
+        assert_eq!(sp.message(), "Download complete");
+    }
+
+    #[test]
+    fn test_invalid_bar_style() {
+        // not checked by the command line parser, e.g. when rget is used as a library
+        let options = DownloadOptions {
+            progress_template: Some("{}".to_string()),
+            progress_chars: Some("#".to_string()),
+            ..DownloadOptions::default()
+        };
+        let error = custom_style(&options).err().unwrap();
+        assert!(error.starts_with("invalid progress template"), "{error}");
+        let options = DownloadOptions {
+            progress_template: None,
+            ..options
+        };
+        assert_eq!(
+            custom_style(&options).err().unwrap(),
+            "at least 2 progress characters are needed"
+        );
+        assert!(custom_style(&DownloadOptions::default()).is_ok());
+        // the download goes on with the default bar
+        bar_style(&options);
+    }
+
+    #[test]
+    fn test_json_events() {
+        let url = "http://localhost/a";
+        let json = |event: JsonEvent| serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json(JsonEvent::Start {
+                url,
+                total: Some(10)
+            }),
+            r#"{"event":"start","url":"http://localhost/a","total":10}"#
+        );
+        assert_eq!(
+            json(JsonEvent::Start { url, total: None }),
+            r#"{"event":"start","url":"http://localhost/a","total":null}"#
+        );
+        assert_eq!(
+            json(JsonEvent::Progress { url, downloaded: 5 }),
+            r#"{"event":"progress","url":"http://localhost/a","downloaded":5}"#
+        );
+        assert_eq!(
+            json(JsonEvent::Done {
+                url,
+                path: "a",
+                downloaded: 10
+            }),
+            r#"{"event":"done","url":"http://localhost/a","path":"a","downloaded":10}"#
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, stream};
+use http::header::{ACCEPT_ENCODING, RANGE};
+use http::{HeaderMap, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::client::HttpClient;
+use crate::download::{DownloadOptions, finish_file, report_downloads};
+use crate::error::RgetError;
+use crate::progress::DownloadProgress;
+
+/// how often the progress of a download is written to its sidecar at most
+pub(crate) const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// what `rget resume` needs to continue a `.part` file, saved next to it as `<part>.meta`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PartMeta {
+    pub(crate) url: String,
+    /// the size of the complete file if the server sent it
+    pub(crate) size: Option<u64>,
+    /// how many bytes at the start of the part file are written, the rest of a preallocated part
+    /// is still empty
+    pub(crate) downloaded: u64,
+    /// the parts of the file written after the ones at the start, as `(start, end)` with the end
+    /// exclusive. A download over several connections writes one per connection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) ranges: Vec<(u64, u64)>,
+}
+
+/// the sidecar of the `part` file
+pub(crate) fn meta_path(part: &str) -> String {
+    format!("{part}.meta")
+}
+
+impl PartMeta {
+    fn load(path: &Path) -> Result<Self, RgetError> {
+        let content = fs::read_to_string(path).map_err(|source| RgetError::ReadFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_json::from_str(&content).map_err(|e| {
+            RgetError::Config(format!("{} is not a valid part file: {e}", path.display()))
+        })
+    }
+
+    /// the parts of the file that are still missing, as `(start, end)` with the end exclusive.
+    /// The last one has no end when the size of the file is not known
+    fn missing(&self) -> Vec<(u64, Option<u64>)> {
+        let mut written = self.ranges.clone();
+        written.push((0, self.downloaded));
+        written.sort_unstable();
+        let mut missing = Vec::new();
+        let mut at = 0;
+        for (start, end) in written {
+            if start > at {
+                missing.push((at, Some(start)));
+            }
+            at = at.max(end);
+        }
+        match self.size {
+            Some(size) if at < size => missing.push((at, Some(size))),
+            Some(_) => {}
+            None => missing.push((at, None)),
+        }
+        missing
+    }
+
+    /// writes the sidecar of `part`, it is replaced in one go so an interrupted write leaves the
+    /// one before
+    fn save(&self, part: &str) -> Result<(), RgetError> {
+        let path = meta_path(part);
+        let tmp = format!("{path}.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// keeps the sidecar of a part file up to date while it is downloaded, downloads that cannot be
+/// resumed get none
+pub(crate) struct Checkpoint {
+    part: String,
+    meta: PartMeta,
+    saved: Instant,
+    enabled: bool,
+}
+
+impl Checkpoint {
+    /// writes the sidecar of the `part` file `url` is downloaded to
+    ///
+    /// only whole files fetched with GET can be continued with a range request, and neither a
+    /// gzip stream of --compress nor a body saved with its Content-Encoding can be continued
+    /// halfway
+    pub(crate) fn start(
+        part: &str,
+        url: &str,
+        size: Option<u64>,
+        encoded: bool,
+        options: &DownloadOptions,
+    ) -> Result<Self, RgetError> {
+        let checkpoint = Checkpoint {
+            part: part.to_string(),
+            meta: PartMeta {
+                url: url.to_string(),
+                size,
+                downloaded: 0,
+                ranges: Vec::new(),
+            },
+            saved: Instant::now(),
+            enabled: options.method == Method::GET
+                && options.byte_range.is_none()
+                && !options.compress
+                && !encoded,
+        };
+        if checkpoint.enabled {
+            checkpoint.meta.save(part)?;
+            if let Some(transfer) = &options.transfer {
+                transfer.set_sidecar(PathBuf::from(meta_path(part)));
+            }
+        }
+        Ok(checkpoint)
+    }
+
+    /// records that the first `downloaded` bytes are in `dest`, the sidecar is only written every
+    /// [`CHECKPOINT_INTERVAL`]
+    pub(crate) fn update(
+        &mut self,
+        dest: &mut impl Write,
+        downloaded: u64,
+    ) -> Result<(), RgetError> {
+        if !self.enabled || self.saved.elapsed() < CHECKPOINT_INTERVAL {
+            return Ok(());
+        }
+        // the sidecar must never claim more than is in the file
+        dest.flush()?;
+        self.meta.downloaded = downloaded;
+        self.meta.save(&self.part)?;
+        self.saved = Instant::now();
+        Ok(())
+    }
+
+    /// records that the part of the file from `start` is written up to `end` and writes the
+    /// sidecar, the data has to be flushed before
+    pub(crate) fn range_written(&mut self, start: u64, end: u64) -> Result<(), RgetError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        match self.meta.ranges.iter_mut().find(|range| range.0 == start) {
+            Some(range) => range.1 = end,
+            None => self.meta.ranges.push((start, end)),
+        }
+        self.meta.save(&self.part)?;
+        self.saved = Instant::now();
+        Ok(())
+    }
+
+    /// the part file is complete or removed, its sidecar goes as well
+    pub(crate) fn finish(self) -> Result<(), RgetError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        match fs::remove_file(meta_path(&self.part)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// every sidecar in `dir` and its subdirectories
+fn find_sidecars(dir: &Path, sidecars: &mut Vec<PathBuf>) -> Result<(), RgetError> {
+    let entries = fs::read_dir(dir).map_err(|source| RgetError::ReadFile {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_sidecars(&path, sidecars)?;
+        } else if path.to_string_lossy().ends_with(".part.meta") {
+            sidecars.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// continues every `.part` file in `dir` and its subdirectories that has a sidecar, at most
+/// `parallel` at a time, and prints a summary like [`download_all`](crate::download::download_all)
+pub async fn resume_all(
+    client: &HttpClient,
+    dir: &Path,
+    parallel: usize,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let start = Instant::now();
+    let mut sidecars = Vec::new();
+    find_sidecars(dir, &mut sidecars)?;
+    sidecars.sort();
+    if sidecars.is_empty() {
+        println!("no downloads to resume in {}", dir.display());
+        return Ok(());
+    }
+    println!("resuming {} downloads", sidecars.len());
+
+    let results: Vec<_> = stream::iter(&sidecars)
+        .map(|sidecar| async move { (sidecar, resume(client, sidecar, options).await) })
+        .buffer_unordered(parallel)
+        .collect()
+        .await;
+    let mut written = 0;
+    let mut failures = Vec::new();
+    for (sidecar, result) in results {
+        match result {
+            Ok(bytes) => written += bytes,
+            Err(e) => failures.push((sidecar.display().to_string(), e)),
+        }
+    }
+    report_downloads(sidecars.len(), &failures, written, start.elapsed())
+}
+
+/// continues the part file of the `sidecar` with range requests for the parts that are missing
+/// and renames it to its outfile once it is complete. The file is finished like any other
+/// download, see [`finish_file`]. Returns the number of bytes written
+///
+/// a server that does not support ranges sends the whole file again
+pub async fn resume(
+    client: &HttpClient,
+    sidecar: &Path,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    let meta = PartMeta::load(sidecar)?;
+    let sidecar = sidecar.to_string_lossy();
+    let part = sidecar.strip_suffix(".meta").unwrap_or(&sidecar);
+    let outfile = part.strip_suffix(".part").unwrap_or(part);
+    let len = fs::metadata(part)
+        .map_err(|source| RgetError::ReadFile {
+            path: PathBuf::from(part),
+            source,
+        })?
+        .len();
+    let url = &meta.url.clone();
+
+    let meta = PartMeta {
+        downloaded: meta.downloaded.min(len),
+        ..meta
+    };
+    let missing = meta.missing();
+    if missing.is_empty() {
+        // the file was complete, only the rename and the steps after it were missing
+        fs::rename(part, outfile)?;
+        fs::remove_file(&*sidecar)?;
+        options.suspend(|| println!("{outfile} was already complete"));
+        finish_file(options, url, outfile, None, None).await?;
+        return Ok(0);
+    }
+    let left: u64 = missing
+        .iter()
+        .filter_map(|&(start, end)| Some(end? - start))
+        .sum();
+    let already = match meta.size {
+        Some(size) => size - left,
+        None => meta.downloaded,
+    };
+    let size = meta.size;
+    let mut checkpoint = Checkpoint {
+        part: part.to_string(),
+        meta,
+        saved: Instant::now(),
+        enabled: true,
+    };
+
+    let mut file = OpenOptions::new().write(true).open(part)?;
+    let mut pb = None;
+    let mut headers = HeaderMap::new();
+    let mut written = 0;
+    let mut end_of_file = size;
+    // every error of the download abandons its progress
+    let fetched: Result<(), RgetError> = async {
+        for (start, end) in missing {
+            // the last part goes to the end of the file
+            let range = match end.filter(|&end| Some(end) != size) {
+                Some(end) => format!("bytes={start}-{}", end - 1),
+                None => format!("bytes={start}-"),
+            };
+            let request = client
+                .get(url)
+                .header(RANGE, range)
+                .header(ACCEPT_ENCODING, "identity");
+            let mut response = client.send(request).await?.error_for_status()?;
+            let whole = response.status() != StatusCode::PARTIAL_CONTENT;
+            let (start, end) = match whole {
+                true => {
+                    options.suspend(|| {
+                        println!("{url} does not support range requests, starting over")
+                    });
+                    checkpoint.meta.downloaded = 0;
+                    checkpoint.meta.ranges.clear();
+                    (0, size)
+                }
+                false => (start, end),
+            };
+            let bar = pb.get_or_insert_with(|| {
+                let already = if whole { 0 } else { already };
+                DownloadProgress::resumed(url, size, already, options)
+            });
+
+            file.seek(SeekFrom::Start(start))?;
+            let mut dest = BufWriter::new(&mut file);
+            let mut at = start;
+            while let Some(chunk) = response.chunk().await? {
+                dest.write_all(&chunk)?;
+                at += chunk.len() as u64;
+                written += chunk.len() as u64;
+                bar.inc(chunk.len() as u64);
+                if checkpoint.saved.elapsed() >= CHECKPOINT_INTERVAL {
+                    dest.flush()?;
+                    checkpoint.range_written(start, at)?;
+                }
+            }
+            dest.flush()?;
+            checkpoint.range_written(start, at)?;
+            headers = response.headers().clone();
+            match end {
+                Some(expected) if at != expected => {
+                    return Err(RgetError::SizeMismatch {
+                        url: url.clone(),
+                        expected: size.unwrap_or(expected),
+                        actual: already + written,
+                    });
+                }
+                Some(_) => {}
+                None => end_of_file = Some(at),
+            }
+            if whole {
+                break;
+            }
+        }
+        // what is left of an earlier attempt behind the end is not part of the file
+        if let Some(end_of_file) = end_of_file {
+            file.set_len(end_of_file)?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = fetched {
+        if let Some(pb) = pb {
+            pb.abandon();
+        }
+        return Err(e);
+    }
+    drop(file);
+    fs::rename(part, outfile)?;
+    checkpoint.finish()?;
+    if let Some(pb) = pb {
+        pb.finish(outfile);
+    }
+    finish_file(options, url, outfile, None, Some(&headers)).await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use wiremock::matchers::{header, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{PartMeta, resume, resume_all};
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::download::DownloadOptions;
+
+    #[tokio::test]
+    async fn test_resume_all() {
+        let server = MockServer::start().await;
+        let body: Vec<u8> = (0..1_000u32).map(|i| (i % 251) as u8).collect();
+        Mock::given(path("/a.bin"))
+            .and(header("Range", "bytes=600-"))
+            .respond_with(ResponseTemplate::new(206).set_body_bytes(&body[600..]))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // without range support the whole file comes again
+        Mock::given(path("/b.bin"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dir = std::env::temp_dir().join(format!("rget-resume-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        let part = |name: &str, content: &[u8], downloaded: u64| {
+            let part = dir.join(format!("{name}.part"));
+            fs::write(&part, content).unwrap();
+            let meta = PartMeta {
+                url: format!("{}/{}", server.uri(), name.trim_start_matches("nested/")),
+                size: Some(1_000),
+                downloaded,
+                ranges: Vec::new(),
+            };
+            meta.save(&part.to_string_lossy()).unwrap();
+        };
+        // a preallocated part of which only the first 600 bytes arrived
+        let mut preallocated = body[..600].to_vec();
+        preallocated.resize(1_000, 0);
+        part("a.bin", &preallocated, 600);
+        part("nested/b.bin", &body[..300], 300);
+        // a part without a sidecar is left alone
+        fs::write(dir.join("c.bin.part"), b"?").unwrap();
+
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        resume_all(&client, &dir, 2, &DownloadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(fs::read(dir.join("a.bin")).unwrap(), body);
+        assert_eq!(fs::read(dir.join("nested/b.bin")).unwrap(), body);
+        let mut left: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["a.bin", "c.bin.part", "nested"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_ranges() {
+        let server = MockServer::start().await;
+        let body: Vec<u8> = (0..1_000u32).map(|i| (i % 251) as u8).collect();
+        Mock::given(path("/c.bin"))
+            .and(header("Range", "bytes=300-499"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_bytes(&body[300..500]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/c.bin"))
+            .and(header("Range", "bytes=800-"))
+            .respond_with(
+                ResponseTemplate::new(206)
+                    .insert_header("ETag", "\"v1\"")
+                    .set_body_bytes(&body[800..]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dir = std::env::temp_dir().join(format!("rget-resume-ranges-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // two connections got part of their ranges before the download stopped
+        let mut content = body.clone();
+        content[300..500].fill(0);
+        content[800..].fill(0);
+        let part = dir.join("c.bin.part");
+        fs::write(&part, &content).unwrap();
+        let meta = PartMeta {
+            url: format!("{}/c.bin", server.uri()),
+            size: Some(1_000),
+            downloaded: 300,
+            ranges: vec![(500, 800)],
+        };
+        assert_eq!(meta.missing(), [(300, Some(500)), (800, Some(1_000))]);
+        meta.save(&part.to_string_lossy()).unwrap();
+
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let options = DownloadOptions {
+            etag: true,
+            ..Default::default()
+        };
+        let sidecar = dir.join("c.bin.part.meta");
+        assert_eq!(resume(&client, &sidecar, &options).await.unwrap(), 400);
+        assert_eq!(fs::read(dir.join("c.bin")).unwrap(), body);
+        // the resumed file is finished like any other download
+        assert!(dir.join("c.bin.rget-meta").exists());
+        assert!(!sidecar.exists());
+
+        // so is a part that was complete before only its rename was missing
+        fs::write(&part, &body).unwrap();
+        let meta = PartMeta {
+            downloaded: 1_000,
+            ranges: Vec::new(),
+            ..meta
+        };
+        meta.save(&part.to_string_lossy()).unwrap();
+        let options = DownloadOptions {
+            delete_after: true,
+            ..Default::default()
+        };
+        assert_eq!(resume(&client, &sidecar, &options).await.unwrap(), 0);
+        assert!(!dir.join("c.bin").exists());
+        assert!(!sidecar.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
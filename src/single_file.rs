@@ -0,0 +1,403 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::LazyLock;
+
+use ego_tree::NodeId;
+use futures::{StreamExt, stream};
+use http::header::CONTENT_TYPE;
+use regex::{Captures, Regex};
+use scraper::{Html, Node, Selector};
+use url::Url;
+
+use crate::client::HttpClient;
+use crate::content_type::ContentType;
+use crate::crawl::{CRAWL_CONCURRENCY, decode_page, document_base};
+use crate::data_url;
+use crate::download::{DownloadOptions, check_filesize, part_file, resolve_outfile};
+use crate::error::RgetError;
+
+/// the elements that load a requisite of the page and the attribute with its url
+const REQUISITES: [(&str, &str); 7] = [
+    ("img[src]", "src"),
+    ("script[src]", "src"),
+    ("link[href]", "href"),
+    ("source[src]", "src"),
+    ("audio[src]", "src"),
+    ("video[src]", "src"),
+    ("video[poster]", "poster"),
+];
+
+/// the `rel`s of a `<link>` that the page needs, other links like `next` or `canonical` point
+/// to pages and are kept
+const LINK_RELS: [&str; 4] = ["stylesheet", "icon", "shortcut", "apple-touch-icon"];
+
+/// a requisite of a page, the `attr` of the element `node` loads `url`
+struct Requisite {
+    node: NodeId,
+    attr: &'static str,
+    url: Url,
+}
+
+/// the requisites of the page in `document`, relative urls are resolved against `base`
+fn requisites(base: &Url, document: &Html) -> Vec<Requisite> {
+    let mut requisites = Vec::new();
+    for (selector, attr) in REQUISITES {
+        let selector = Selector::parse(selector).expect("the selector is valid");
+        for element in document.select(&selector) {
+            if element.value().name() == "link" {
+                let rel = element.attr("rel").unwrap_or_default().to_ascii_lowercase();
+                if !rel.split_whitespace().any(|rel| LINK_RELS.contains(&rel)) {
+                    continue;
+                }
+            }
+            let Some(url) = element
+                .attr(attr)
+                .and_then(|link| base.join(link.trim()).ok())
+            else {
+                continue;
+            };
+            if matches!(url.scheme(), "http" | "https") {
+                requisites.push(Requisite {
+                    node: element.id(),
+                    attr,
+                    url,
+                });
+            }
+        }
+    }
+    requisites
+}
+
+/// the css written into the page, the text of every `<style>` element and every `style`
+/// attribute with the node it belongs to
+fn inline_css(document: &Html) -> Vec<(NodeId, String)> {
+    let mut css = Vec::new();
+    let styles = Selector::parse("style").expect("the selector is valid");
+    for style in document.select(&styles) {
+        for text in style.children() {
+            if let Node::Text(content) = text.value() {
+                css.push((text.id(), content.to_string()));
+            }
+        }
+    }
+    let attributes = Selector::parse("[style]").expect("the selector is valid");
+    for element in document.select(&attributes) {
+        if let Some(style) = element.attr("style") {
+            css.push((element.id(), style.to_string()));
+        }
+    }
+    css
+}
+
+/// the `url(...)` references of a stylesheet
+static CSS_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)'"\s]*))\s*\)"#).expect("the regex is valid")
+});
+
+/// the absolute urls referenced by `url(...)` in `css`
+fn css_urls(base: &Url, css: &str) -> Vec<Url> {
+    CSS_URL
+        .captures_iter(css)
+        .filter_map(|captures| css_reference(base, &captures))
+        .collect()
+}
+
+fn css_reference(base: &Url, captures: &Captures) -> Option<Url> {
+    let link = captures.iter().skip(1).flatten().next()?.as_str();
+    let url = base.join(link.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+/// replaces the `url(...)` references of `css` by the `data:` urls in `embedded`, references that
+/// could not be fetched point to their absolute url so they still load
+fn rewrite_css(base: &Url, css: &str, embedded: &HashMap<Url, String>) -> String {
+    CSS_URL
+        .replace_all(css, |captures: &Captures| {
+            match css_reference(base, captures) {
+                Some(url) => match embedded.get(&url) {
+                    Some(data) => format!("url(\"{data}\")"),
+                    None => format!("url(\"{url}\")"),
+                },
+                None => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// fetches `url` and returns its media type and content
+async fn fetch(
+    client: &HttpClient,
+    url: &Url,
+    options: &DownloadOptions,
+) -> Result<(String, Vec<u8>), RgetError> {
+    let response = client
+        .send(client.get(url.as_str()))
+        .await?
+        .error_for_status()?;
+    let mime = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let content = response.bytes().await?;
+    check_filesize(url.as_str(), content.len() as u64, options.max_filesize)?;
+    Ok((mime, content.to_vec()))
+}
+
+/// fetches all `urls` and returns the media type and content of each one, failures are reported
+/// and left out
+async fn embed_all(
+    client: &HttpClient,
+    urls: impl IntoIterator<Item = Url>,
+    options: &DownloadOptions,
+) -> HashMap<Url, (String, Vec<u8>)> {
+    let fetches = urls.into_iter().map(|url| async move {
+        let fetched = fetch(client, &url, options).await;
+        (url, fetched)
+    });
+    let fetched: Vec<_> = stream::iter(fetches)
+        .buffer_unordered(CRAWL_CONCURRENCY)
+        .collect()
+        .await;
+    let mut embedded = HashMap::new();
+    for (url, result) in fetched {
+        match result {
+            Ok(content) => {
+                embedded.insert(url, content);
+            }
+            Err(e) => options.suspend(|| eprintln!("failed to embed {url}: {e}")),
+        }
+    }
+    embedded
+}
+
+/// downloads the page at `url` with its images, stylesheets and scripts and saves it as one
+/// html file, every requisite is embedded as a `data:` url
+///
+/// the `url(...)` references of stylesheets, `<style>` elements and `style` attributes are
+/// embedded as well, requisites that cannot be fetched keep their absolute url. Returns the number
+/// of bytes written
+pub async fn single_file(
+    client: &HttpClient,
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    let response = client.send(client.get(url)).await?.error_for_status()?;
+    // relative links are relative to where redirects ended
+    let base = response.url().clone();
+    let charset = ContentType::charset(response.headers().get(CONTENT_TYPE));
+    let body = response.bytes().await?;
+    let page = decode_page(url, &body, charset.as_deref())
+        .ok_or_else(|| RgetError::Config(format!("{url} cannot be decoded, it was not saved")))?;
+    let mut document = Html::parse_document(&page);
+
+    let base = document_base(&base, &document);
+    let requisites = requisites(&base, &document);
+    let inline = inline_css(&document);
+    let urls: HashSet<Url> = requisites.iter().map(|r| r.url.clone()).collect();
+    let total = urls.len();
+    let mut fetched = embed_all(client, urls, options).await;
+
+    // the references of stylesheets are fetched in a second round, they are not nested further.
+    // The ones of the inline css are relative to the page
+    let stylesheets: Vec<(Url, String)> = fetched
+        .iter()
+        .filter(|(_, (mime, _))| mime.starts_with("text/css"))
+        .map(|(url, (_, css))| (url.clone(), String::from_utf8_lossy(css).into_owned()))
+        .collect();
+    let references: HashSet<Url> = stylesheets
+        .iter()
+        .flat_map(|(url, css)| css_urls(url, css))
+        .chain(inline.iter().flat_map(|(_, css)| css_urls(&base, css)))
+        .collect();
+    let references: HashMap<Url, String> = embed_all(client, references, options)
+        .await
+        .into_iter()
+        .map(|(url, (mime, content))| (url, data_url::encode(&mime, &content)))
+        .collect();
+    for (url, css) in stylesheets {
+        let css = rewrite_css(&url, &css, &references);
+        if let Some((_, content)) = fetched.get_mut(&url) {
+            *content = css.into_bytes();
+        }
+    }
+
+    for (node, css) in &inline {
+        let css = rewrite_css(&base, css, &references);
+        let Some(mut node) = document.tree.get_mut(*node) else {
+            continue;
+        };
+        match node.value() {
+            Node::Text(text) => text.text = css.into(),
+            Node::Element(element) => {
+                for (name, attr) in element.attrs.iter_mut() {
+                    if &*name.local == "style" {
+                        *attr = css.as_str().into();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let embedded: HashMap<Url, String> = fetched
+        .into_iter()
+        .map(|(url, (mime, content))| (url, data_url::encode(&mime, &content)))
+        .collect();
+    for requisite in &requisites {
+        let Some(mut node) = document.tree.get_mut(requisite.node) else {
+            continue;
+        };
+        let Node::Element(element) = node.value() else {
+            continue;
+        };
+        let value = match embedded.get(&requisite.url) {
+            Some(data) => data.clone(),
+            None => requisite.url.to_string(),
+        };
+        for (name, attr) in element.attrs.iter_mut() {
+            if &*name.local == requisite.attr {
+                *attr = value.as_str().into();
+            }
+        }
+    }
+
+    let html = document.html();
+    let outfile = &resolve_outfile(outfile, options);
+    let part = part_file(outfile);
+    fs::write(&part, &html)?;
+    fs::rename(&part, outfile)?;
+    options.suspend(|| {
+        println!(
+            "saved {url} with {} of {} requisites embedded to {outfile}",
+            embedded.len(),
+            total
+        )
+    });
+    Ok(html.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use scraper::Html;
+    use url::Url;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{css_urls, requisites, rewrite_css, single_file};
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::data_url;
+    use crate::download::DownloadOptions;
+
+    #[test]
+    fn test_requisites() {
+        let base = Url::parse("http://localhost/docs/").unwrap();
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="style.css"><link rel="next" href="page2.html">
+            <link rel="shortcut icon" href="/favicon.ico"><script src="app.js"></script>
+            </head><body><img src=" logo.png "><a href="other.html">other</a>
+            <img src="data:,inline"><video src="clip.mp4" poster="clip.jpg"></video></body></html>"#;
+        let document = Html::parse_document(html);
+        let mut urls: Vec<String> = requisites(&base, &document)
+            .into_iter()
+            .map(|r| r.url.path().to_string())
+            .collect();
+        urls.sort();
+        assert_eq!(
+            urls,
+            [
+                "/docs/app.js",
+                "/docs/clip.jpg",
+                "/docs/clip.mp4",
+                "/docs/logo.png",
+                "/docs/style.css",
+                "/favicon.ico"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_css() {
+        let base = Url::parse("http://localhost/css/style.css").unwrap();
+        let css = r#"a { background: url( "../bg.png" ) } b { background: url(gone.png) }
+            @font-face { src: url('data:font/woff2;base64,AA==') }"#;
+        let urls: Vec<String> = css_urls(&base, css).iter().map(Url::to_string).collect();
+        assert_eq!(
+            urls,
+            ["http://localhost/bg.png", "http://localhost/css/gone.png"]
+        );
+
+        let embedded = HashMap::from([(
+            Url::parse("http://localhost/bg.png").unwrap(),
+            "data:image/png;base64,AA==".to_string(),
+        )]);
+        assert_eq!(
+            rewrite_css(&base, css, &embedded),
+            r#"a { background: url("data:image/png;base64,AA==") } b { background: url("http://localhost/css/gone.png") }
+            @font-face { src: url('data:font/woff2;base64,AA==') }"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_file() {
+        let server = MockServer::start().await;
+        let page = r#"<html><head><link rel="stylesheet" href="/style.css">
+            <style>h1 { background: url('/logo.png') }</style></head>
+            <body><img src="/logo.png"><img src="/gone.png"><a href="/a">a</a>
+            <div style="background: url(bg.png)"></div></body></html>"#;
+        Mock::given(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(page, "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/style.css"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("body { background: url(bg.png) }", "text/css"),
+            )
+            .mount(&server)
+            .await;
+        for image in ["/logo.png", "/bg.png"] {
+            Mock::given(path(image))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(vec![1, 2, 3], "image/png"))
+                .mount(&server)
+                .await;
+        }
+        let dir = std::env::temp_dir().join(format!("rget-single-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let outfile = dir.join("page.html").to_string_lossy().to_string();
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+
+        let url = format!("{}/", server.uri());
+        single_file(&client, &url, &outfile, &DownloadOptions::default())
+            .await
+            .unwrap();
+        let html = fs::read_to_string(&outfile).unwrap();
+        let image = data_url::encode("image/png", &[1, 2, 3]);
+        assert!(html.contains(&format!(r#"<img src="{image}">"#)), "{html}");
+        assert!(html.contains(&format!(r#"<img src="{}/gone.png">"#, server.uri())));
+        // links to other pages are kept
+        assert!(html.contains(r#"<a href="/a">"#));
+        let css = data_url::encode(
+            "text/css",
+            format!("body {{ background: url(\"{image}\") }}").as_bytes(),
+        );
+        assert!(html.contains(&css), "{html}");
+        // so are the references of the inline css
+        assert!(
+            html.contains(&format!(r#"h1 {{ background: url("{image}") }}"#)),
+            "{html}"
+        );
+        assert!(
+            html.contains(&format!(
+                r#"<div style="background: url(&quot;{image}&quot;)">"#
+            )),
+            "{html}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use scraper::{Html, Selector};
+
+use crate::client::HttpClient;
+use crate::error::RgetError;
+use crate::structures::VecQueue;
+
+/// how many sitemaps a sitemap index may lead to, indexes that point to each other end here
+const MAX_SITEMAPS: usize = 1000;
+
+/// the `<loc>` entries of a sitemap
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Sitemap {
+    /// the pages of a `<urlset>`
+    urls: Vec<String>,
+    /// the further sitemaps of a `<sitemapindex>`
+    sitemaps: Vec<String>,
+}
+
+/// parses a `<urlset>` or a `<sitemapindex>`
+///
+/// the html parser keeps elements it does not know like `<loc>` as they are, which is all of the
+/// structure a sitemap has
+fn parse_sitemap(xml: &str) -> Sitemap {
+    let document = Html::parse_document(xml);
+    let locs = |selector: &str| {
+        let selector = Selector::parse(selector).expect("the selector is valid");
+        document
+            .select(&selector)
+            .map(|loc| loc.text().collect::<String>().trim().to_string())
+            .filter(|loc| !loc.is_empty())
+            .collect()
+    };
+    Sitemap {
+        urls: locs("urlset > url > loc"),
+        sitemaps: locs("sitemapindex > sitemap > loc"),
+    }
+}
+
+/// the text of a sitemap, `sitemap.xml.gz` files are gzip compressed themselves
+fn sitemap_text(url: &str, body: &[u8]) -> Result<String, RgetError> {
+    if !body.starts_with(&[0x1f, 0x8b]) {
+        return Ok(String::from_utf8_lossy(body).into_owned());
+    }
+    let mut xml = String::new();
+    GzDecoder::new(body)
+        .read_to_string(&mut xml)
+        .map_err(|e| RgetError::InvalidUrl {
+            url: url.to_string(),
+            reason: format!("not a valid compressed sitemap: {e}"),
+        })?;
+    Ok(xml)
+}
+
+async fn fetch_sitemap(client: &HttpClient, url: &str) -> Result<Sitemap, RgetError> {
+    let response = client.send(client.get(url)).await?.error_for_status()?;
+    let body = response.bytes().await?;
+    Ok(parse_sitemap(&sitemap_text(url, &body)?))
+}
+
+/// the urls listed in the sitemap at `url` in the order they are listed, the sitemaps of a
+/// sitemap index are fetched one after the other
+///
+/// only a failing `url` is an error, sitemaps of an index that fail are reported and skipped
+pub async fn sitemap_urls(client: &HttpClient, url: &str) -> Result<Vec<String>, RgetError> {
+    let mut urls = Vec::new();
+    let mut seen_urls = HashSet::new();
+    let mut seen_sitemaps = HashSet::from([url.to_string()]);
+    let mut q = VecQueue::default();
+    q.push(url.to_string());
+    while let Some(sitemap_url) = q.pop() {
+        let sitemap = match fetch_sitemap(client, &sitemap_url).await {
+            Ok(sitemap) => sitemap,
+            Err(e) if sitemap_url == url => return Err(e),
+            Err(e) => {
+                eprintln!("failed to read the sitemap {sitemap_url}: {e}");
+                continue;
+            }
+        };
+        for nested in sitemap.sitemaps {
+            if seen_sitemaps.len() >= MAX_SITEMAPS {
+                eprintln!("more than {MAX_SITEMAPS} sitemaps, skipping {nested}");
+                continue;
+            }
+            if seen_sitemaps.insert(nested.clone()) {
+                q.push(nested);
+            }
+        }
+        urls.extend(
+            sitemap
+                .urls
+                .into_iter()
+                .filter(|url| seen_urls.insert(url.clone())),
+        );
+    }
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{Sitemap, parse_sitemap, sitemap_urls};
+    use crate::client::{ClientArgs, HttpClient};
+
+    #[test]
+    fn test_parse_sitemap() {
+        let urlset = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc> https://example.com/ </loc><lastmod>2024-01-01</lastmod></url>
+              <url><loc>https://example.com/a?x=1&amp;y=2</loc></url>
+              <url><loc></loc></url>
+            </urlset>"#;
+        assert_eq!(
+            parse_sitemap(urlset),
+            Sitemap {
+                urls: vec![
+                    "https://example.com/".to_string(),
+                    "https://example.com/a?x=1&y=2".to_string()
+                ],
+                sitemaps: vec![],
+            }
+        );
+
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>https://example.com/pages.xml</loc></sitemap>
+            </sitemapindex>"#;
+        assert_eq!(
+            parse_sitemap(index),
+            Sitemap {
+                urls: vec![],
+                sitemaps: vec!["https://example.com/pages.xml".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sitemap_urls() {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        let index = format!(
+            "<sitemapindex><sitemap><loc>{uri}/pages.xml</loc></sitemap>\
+             <sitemap><loc>{uri}/posts.xml.gz</loc></sitemap>\
+             <sitemap><loc>{uri}/gone.xml</loc></sitemap>\
+             <sitemap><loc>{uri}/sitemap.xml</loc></sitemap></sitemapindex>"
+        );
+        Mock::given(path("/sitemap.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(index, "application/xml"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let pages =
+            format!("<urlset><url><loc>{uri}/a</loc></url><url><loc>{uri}/b</loc></url></urlset>");
+        Mock::given(path("/pages.xml"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(pages, "application/xml"))
+            .mount(&server)
+            .await;
+        let posts =
+            format!("<urlset><url><loc>{uri}/b</loc></url><url><loc>{uri}/c</loc></url></urlset>");
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(posts.as_bytes()).unwrap();
+        Mock::given(path("/posts.xml.gz"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(gz.finish().unwrap(), "application/gzip"),
+            )
+            .mount(&server)
+            .await;
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+
+        let urls = sitemap_urls(&client, &format!("{uri}/sitemap.xml"))
+            .await
+            .unwrap();
+        assert_eq!(
+            urls,
+            [format!("{uri}/a"), format!("{uri}/b"), format!("{uri}/c")]
+        );
+        assert!(
+            sitemap_urls(&client, &format!("{uri}/gone.xml"))
+                .await
+                .is_err()
+        );
+    }
+}
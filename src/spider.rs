@@ -0,0 +1,217 @@
+use futures::{StreamExt, stream};
+use http::StatusCode;
+
+use crate::client::HttpClient;
+use crate::crawl::{CRAWL_CONCURRENCY, CrawlOptions, crawl};
+use crate::data_url::{self, is_data_url};
+use crate::error::RgetError;
+
+/// what the server answered for a url
+#[derive(Debug)]
+pub struct LinkCheck {
+    pub url: String,
+    /// where the redirects ended, none when the url answered itself
+    pub location: Option<String>,
+    /// the status of the final response or why there was none
+    pub status: Result<StatusCode, String>,
+}
+
+impl LinkCheck {
+    /// the url answered with an error status or not at all
+    pub fn is_broken(&self) -> bool {
+        match self.status {
+            Ok(status) => status.is_client_error() || status.is_server_error(),
+            Err(_) => true,
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut line = match &self.status {
+            Ok(status) => format!("{status} {}", self.url),
+            Err(e) => format!("failed {}: {e}", self.url),
+        };
+        if let Some(location) = &self.location {
+            line.push_str(&format!(" -> {location}"));
+        }
+        line
+    }
+}
+
+/// checks that `url` exists with a HEAD request, redirects are followed
+///
+/// servers that do not allow HEAD are asked with a GET whose body is never read, `data:` urls
+/// are fine when their content can be decoded
+pub async fn check(client: &HttpClient, url: &str) -> LinkCheck {
+    if is_data_url(url) {
+        return LinkCheck {
+            url: url.to_string(),
+            location: None,
+            status: data_url::decode(url)
+                .map(|_| StatusCode::OK)
+                .map_err(|e| e.to_string()),
+        };
+    }
+    let mut response = client.send(client.head(url)).await;
+    if let Ok(head) = &response
+        && matches!(
+            head.status(),
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+        )
+    {
+        response = client.send(client.get(url)).await;
+    }
+    match response {
+        Ok(response) => LinkCheck {
+            url: url.to_string(),
+            location: (response.url().as_str() != url).then(|| response.url().to_string()),
+            status: Ok(response.status()),
+        },
+        Err(e) => LinkCheck {
+            url: url.to_string(),
+            location: None,
+            status: Err(e.to_string()),
+        },
+    }
+}
+
+/// checks all `urls` and prints their status, an error if any of them is broken
+pub async fn spider(client: &HttpClient, urls: &[String]) -> Result<(), RgetError> {
+    let checks: Vec<LinkCheck> = stream::iter(urls.iter().map(|url| check(client, url)))
+        .buffered(CRAWL_CONCURRENCY)
+        .collect()
+        .await;
+    for link in &checks {
+        println!("{}", link.report());
+    }
+    let broken = checks.iter().filter(|link| link.is_broken()).count();
+    report_broken(broken, checks.len())
+}
+
+/// crawls `url` like [`crawl`] and checks every link of the site, broken links are printed with
+/// the page that links to them
+pub async fn spider_depth(
+    client: &HttpClient,
+    url: &str,
+    depth: usize,
+    options: &CrawlOptions,
+) -> Result<(), RgetError> {
+    // every url with the page it was found on
+    let links = crawl(client, url, depth, options).await?.with_parents();
+
+    let checks = links
+        .into_iter()
+        .map(|(url, page)| async move { (check(client, &url).await, page) });
+    let checks: Vec<_> = stream::iter(checks)
+        .buffered(CRAWL_CONCURRENCY)
+        .collect()
+        .await;
+    let mut broken = 0;
+    for (link, page) in &checks {
+        if !link.is_broken() {
+            continue;
+        }
+        broken += 1;
+        match page {
+            Some(page) => println!("broken link on {page}: {}", link.report()),
+            None => println!("broken link: {}", link.report()),
+        }
+    }
+    report_broken(broken, checks.len())
+}
+
+fn report_broken(broken: usize, total: usize) -> Result<(), RgetError> {
+    println!("{total} urls checked, {broken} broken");
+    if broken > 0 {
+        return Err(RgetError::BrokenLinks { broken, total });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use http::StatusCode;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::{check, spider_depth};
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::crawl::CrawlOptions;
+    use crate::error::RgetError;
+
+    fn test_client() -> HttpClient {
+        HttpClient::new(&ClientArgs::default(), &[]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/ok"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/old"))
+            .respond_with(ResponseTemplate::new(301).insert_header("Location", "/ok"))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/no-head"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/no-head"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = test_client();
+        let check = |p: &str| {
+            let url = format!("{}{p}", server.uri());
+            let client = &client;
+            async move { check(client, &url).await }
+        };
+
+        let ok = check("/ok").await;
+        assert_eq!(ok.status, Ok(StatusCode::OK));
+        assert_eq!(ok.location, None);
+        assert!(!ok.is_broken());
+
+        let moved = check("/old").await;
+        assert_eq!(moved.status, Ok(StatusCode::OK));
+        assert_eq!(moved.location, Some(format!("{}/ok", server.uri())));
+
+        assert_eq!(check("/no-head").await.status, Ok(StatusCode::OK));
+
+        let missing = check("/missing").await;
+        assert_eq!(missing.status, Ok(StatusCode::NOT_FOUND));
+        assert!(missing.is_broken());
+    }
+
+    #[tokio::test]
+    async fn test_spider_depth() {
+        let server = MockServer::start().await;
+        Mock::given(path("/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(r#"<a href="/a">a</a><a href="/gone">gone</a>"#, "text/html"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/a"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("", "text/plain"))
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/", server.uri());
+        let result = spider_depth(&test_client(), &url, 2, &CrawlOptions::default()).await;
+        assert!(matches!(
+            result,
+            Err(RgetError::BrokenLinks {
+                broken: 1,
+                total: 3
+            })
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::error::RgetError;
+
+/// how long the bytes are summed up for the peak speed
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+
+/// counters of a whole run, shared by its client and its downloads and written to --stats-file
+/// at the end
+#[derive(Debug)]
+pub struct Stats {
+    start: Instant,
+    urls_fetched: AtomicU64,
+    files_downloaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    retries: AtomicU64,
+    failures: AtomicU64,
+    /// the start of the current speed window and the bytes received in it
+    window: Mutex<(Instant, u64)>,
+    /// the most bytes per second of any finished window
+    peak_speed: AtomicU64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        let start = Instant::now();
+        Stats {
+            start,
+            urls_fetched: AtomicU64::new(0),
+            files_downloaded: AtomicU64::new(0),
+            bytes_downloaded: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            window: Mutex::new((start, 0)),
+            peak_speed: AtomicU64::new(0),
+        }
+    }
+}
+
+/// the json written to --stats-file, speeds are in bytes per second
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatsReport {
+    /// the responses of every page and file, HEAD requests are not counted
+    pub urls_fetched: u64,
+    /// downloads that succeeded, files that were skipped as up to date or filtered out included
+    pub files_downloaded: u64,
+    pub bytes_downloaded: u64,
+    pub average_speed: u64,
+    /// the fastest second of the run
+    pub peak_speed: u64,
+    /// requests sent again because the server asked to wait
+    pub retries: u64,
+    /// failed downloads and pages of a crawl that could not be fetched
+    pub failures: u64,
+    pub elapsed_secs: f64,
+}
+
+impl Stats {
+    /// a response was received, retries are counted by [`Stats::retried`] instead
+    pub(crate) fn fetched(&self) {
+        self.urls_fetched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn downloaded(&self) {
+        self.files_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// a download or a page of a crawl failed
+    pub(crate) fn failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `bytes` of any download arrived, the downloads running at the same time add up to the
+    /// speed of the run
+    pub(crate) fn received(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.received_at(bytes, Instant::now());
+    }
+
+    fn received_at(&self, bytes: u64, now: Instant) {
+        let mut window = self.window.lock().unwrap();
+        let (start, received) = &mut *window;
+        let elapsed = now.duration_since(*start);
+        if elapsed >= SPEED_WINDOW {
+            let speed = (*received as f64 / elapsed.as_secs_f64()) as u64;
+            self.peak_speed.fetch_max(speed, Ordering::Relaxed);
+            *window = (now, bytes);
+        } else {
+            *received += bytes;
+        }
+    }
+
+    /// the counters as they are now
+    pub fn report(&self) -> StatsReport {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let bytes_downloaded = self.bytes_downloaded.load(Ordering::Relaxed);
+        let average_speed = if elapsed > 0.0 {
+            (bytes_downloaded as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        StatsReport {
+            urls_fetched: self.urls_fetched.load(Ordering::Relaxed),
+            files_downloaded: self.files_downloaded.load(Ordering::Relaxed),
+            bytes_downloaded,
+            average_speed,
+            // a run shorter than a window never finishes one, its average is its peak
+            peak_speed: self.peak_speed.load(Ordering::Relaxed).max(average_speed),
+            retries: self.retries.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            elapsed_secs: elapsed,
+        }
+    }
+
+    /// writes the [`StatsReport`] of the run to `path` as json
+    pub fn write(&self, path: &Path) -> Result<(), RgetError> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, &self.report())?;
+        writeln!(file)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Stats;
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::default();
+        stats.fetched();
+        stats.fetched();
+        stats.downloaded();
+        stats.retried();
+        stats.failed();
+        let start = stats.start;
+        stats.received_at(100, start);
+        stats.received_at(300, start + Duration::from_millis(500));
+        // the window of the first two chunks closes with the third one
+        stats.received_at(50, start + Duration::from_secs(2));
+        stats.received_at(10, start + Duration::from_millis(2500));
+
+        let report = stats.report();
+        assert_eq!(report.urls_fetched, 2);
+        assert_eq!(report.files_downloaded, 1);
+        assert_eq!(report.retries, 1);
+        assert_eq!(report.failures, 1);
+        assert_eq!(report.peak_speed, 200);
+
+        let json = serde_json::to_value(&report).unwrap();
+        for field in ["bytes_downloaded", "average_speed", "elapsed_secs"] {
+            assert!(json.get(field).is_some(), "{field} is missing");
+        }
+    }
+}
@@ -1,18 +1,52 @@
-use std::{cell::RefCell, future::Future, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    convert::Infallible,
+    future::Future,
+    rc::Rc,
+    sync::{Arc, RwLock},
+};
 
-use tokio::task;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, ser::SerializeSeq};
+use tokio::task::{self, JoinHandle};
 
 type QueueNodeRef<T> = Rc<RefCell<QueueNode<T>>>;
 type OptQueueNodeRef<T> = Option<QueueNodeRef<T>>;
 
 pub type TreeNodeRef<T> = Rc<RefCell<TreeNode<T>>>;
-type OptTreeNodeRef<T> = Option<TreeNodeRef<T>>;
-type TraverseFunction<T> = fn(&T);
+pub type SyncTreeNodeRef<T> = Arc<RwLock<SyncTreeNode<T>>>;
+
+/// what the callback of a traversal returns, `()` for callbacks that cannot fail and a `Result`
+/// for the ones that can
+pub trait TraverseResult: Send + 'static {
+    type Error: Send + 'static;
+
+    fn into_result(self) -> Result<(), Self::Error>;
+}
+
+impl TraverseResult for () {
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<E: Send + 'static> TraverseResult for Result<(), E> {
+    type Error = E;
+
+    fn into_result(self) -> Result<(), E> {
+        self
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct TreeNode<T: Default + Clone> {
     pub value: T,
     pub children: Vec<TreeNodeRef<T>>,
+    /// where the value resolved to when it differs from it, for a crawl the url the redirects of
+    /// the page ended at
+    pub resolved: Option<T>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -21,12 +55,47 @@ pub struct Tree<T: Default + Clone> {
     pub depth: usize,
 }
 
+/// the values of a [`Tree`] with their depth in breadth first order, see [`Tree::iter`]
+pub struct TreeIter<T: Default + Clone> {
+    q: VecQueue<(TreeNodeRef<T>, usize)>,
+}
+
+impl<T: Default + Clone> Iterator for TreeIter<T> {
+    type Item = (T, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (current, depth) = self.q.pop()?;
+        let node = current.borrow();
+        for child in &node.children {
+            self.q.push((child.clone(), depth + 1));
+        }
+        Some((node.value.clone(), depth))
+    }
+}
+
+/// thread safe counterpart of [`TreeNode`] that can be shared between tokio tasks
+#[derive(Debug, Default)]
+pub struct SyncTreeNode<T: Default + Clone> {
+    pub value: T,
+    pub children: Vec<SyncTreeNodeRef<T>>,
+}
+
+/// thread safe counterpart of [`Tree`], nodes can be added from any task holding a node ref
+#[derive(Debug, Default, Clone)]
+pub struct SyncTree<T: Default + Clone> {
+    pub root: SyncTreeNodeRef<T>,
+    pub depth: usize,
+}
+
 #[derive(Debug, Default)]
 struct QueueNode<T: Default> {
     pub value: T,
     pub next: OptQueueNodeRef<T>,
 }
 
+/// a fifo queue as a singly linked list, every value gets its own node
+///
+/// [`VecQueue`] has the same api on a ring buffer and is a lot faster for large queues
 #[derive(Debug, Default, Clone)]
 pub struct Queue<T: Default + Clone> {
     head: OptQueueNodeRef<T>,
@@ -34,18 +103,46 @@ pub struct Queue<T: Default + Clone> {
     pub length: usize,
 }
 
+/// a fifo queue on a [`VecDeque`], values are stored next to each other instead of in a node each
+///
+/// this is what crawls and traversals use, it is serialized like a [`Queue`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VecQueue<T> {
+    values: VecDeque<T>,
+}
+
 impl<T: Default + Clone> Tree<T> {
     pub fn push_node(parent: TreeNodeRef<T>, child: TreeNodeRef<T>) {
         parent.borrow_mut().children.push(child);
     }
 
-    pub async fn traverse_async<F, Fut>(&self, mut f: F)
+    /// calls `f` for every value in breadth first order, the futures run concurrently as tokio
+    /// tasks
+    pub async fn traverse_async<F, Fut>(&self, f: F)
     where
         T: Send + 'static,
         F: FnMut(T) -> Fut + Send + 'static,
-        Fut: Future<Output = ()> + Send + 'static
+        Fut: Future<Output = ()> + Send + 'static,
     {
-        let mut q = Queue::default();
+        for result in self.traverse_with(f).await {
+            let Ok(()) = result;
+        }
+    }
+
+    /// like [`Tree::traverse_async`] for callbacks that can fail, a failing callback does not stop
+    /// the others and the results are returned in traversal order
+    pub async fn traverse_with<F, Fut>(
+        &self,
+        mut f: F,
+    ) -> Vec<Result<(), <Fut::Output as TraverseResult>::Error>>
+    where
+        T: Send + 'static,
+        F: FnMut(T) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: TraverseResult,
+    {
+        let mut q = VecQueue::default();
         q.push(self.root.clone());
         // handles
         let mut h = vec![];
@@ -62,9 +159,7 @@ impl<T: Default + Clone> Tree<T> {
                 h.push(task::spawn(f(value)));
             }
         }
-        for handle in h {
-            handle.await.unwrap();
-        }
+        join_all(h).await
     }
 
     pub fn traverse<F>(&self, mut f: F)
@@ -72,7 +167,7 @@ impl<T: Default + Clone> Tree<T> {
         F: FnMut(&T),
         T: Default,
     {
-        let mut q = Queue::default();
+        let mut q = VecQueue::default();
         q.push(self.root.clone());
         while !q.is_empty() {
             if let Some(current) = q.pop() {
@@ -87,6 +182,92 @@ impl<T: Default + Clone> Tree<T> {
         }
     }
 
+    /// like [`Tree::traverse`] but `f` also gets the depth of the node, the root is at depth 1
+    /// like in a crawl
+    pub fn traverse_with_depth<F>(&self, mut f: F)
+    where
+        F: FnMut(&T, usize),
+    {
+        let mut q = VecQueue::default();
+        q.push((self.root.clone(), 1));
+        while let Some((current, depth)) = q.pop() {
+            let node = current.borrow();
+            for child in &node.children {
+                q.push((child.clone(), depth + 1));
+            }
+            f(&node.value, depth);
+        }
+    }
+
+    /// every value with its depth in breadth first order, the root is at depth 1 like in a crawl
+    pub fn iter(&self) -> TreeIter<T> {
+        let mut q = VecQueue::default();
+        q.push((self.root.clone(), 1));
+        TreeIter { q }
+    }
+
+    /// the values of all nodes without children in breadth first order, for a crawl these are
+    /// the non html resources and the pages at the frontier
+    pub fn leaves(&self) -> Vec<T> {
+        let mut leaves = Vec::new();
+        let mut q = VecQueue::default();
+        q.push(self.root.clone());
+        while let Some(current) = q.pop() {
+            let node = current.borrow();
+            if node.children.is_empty() {
+                leaves.push(node.value.clone());
+            }
+            for child in &node.children {
+                q.push(child.clone());
+            }
+        }
+        leaves
+    }
+
+    /// every value together with the value of its parent in breadth first order, the root has no
+    /// parent. For a crawl this is every url with the page it was found on
+    pub fn with_parents(&self) -> Vec<(T, Option<T>)> {
+        let mut values = vec![(self.root.borrow().value.clone(), None)];
+        let mut q = VecQueue::default();
+        q.push(self.root.clone());
+        while let Some(current) = q.pop() {
+            let node = current.borrow();
+            for child in &node.children {
+                values.push((child.borrow().value.clone(), Some(node.value.clone())));
+                q.push(child.clone());
+            }
+        }
+        values
+    }
+
+    /// reverses the children of every node, a breadth first traversal then visits the links of a
+    /// page from the last to the first
+    pub fn reverse_children(&mut self) {
+        let mut q = VecQueue::default();
+        q.push(self.root.clone());
+        while let Some(current) = q.pop() {
+            let mut node = current.borrow_mut();
+            node.children.reverse();
+            for child in &node.children {
+                q.push(child.clone());
+            }
+        }
+    }
+
+    /// removes every node for which `keep` is false together with its whole subtree, the root is
+    /// always kept. `depth` stays the depth of the crawl
+    pub fn prune<P: Fn(&T) -> bool>(&mut self, keep: P) {
+        let mut q = VecQueue::default();
+        q.push(self.root.clone());
+        while let Some(current) = q.pop() {
+            let mut node = current.borrow_mut();
+            node.children.retain(|child| keep(&child.borrow().value));
+            for child in &node.children {
+                q.push(child.clone());
+            }
+        }
+    }
+
     pub fn new(root: TreeNode<T>) -> Self
     where
         T: Default,
@@ -98,6 +279,27 @@ impl<T: Default + Clone> Tree<T> {
     }
 }
 
+impl<T: Default + Clone> IntoIterator for &Tree<T> {
+    type Item = (T, usize);
+    type IntoIter = TreeIter<T>;
+
+    fn into_iter(self) -> TreeIter<T> {
+        self.iter()
+    }
+}
+
+/// waits for the tasks of a traversal in order, a panic in a callback is passed on to the caller
+async fn join_all<R: TraverseResult>(handles: Vec<JoinHandle<R>>) -> Vec<Result<(), R::Error>> {
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        match handle.await {
+            Ok(result) => results.push(result.into_result()),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    results
+}
+
 impl<T: Default + Clone> TreeNode<T> {
     pub fn new(value: T) -> Self {
         Self {
@@ -107,6 +309,74 @@ impl<T: Default + Clone> TreeNode<T> {
     }
 }
 
+impl<T: Default + Clone> SyncTree<T> {
+    pub fn push_node(parent: SyncTreeNodeRef<T>, child: SyncTreeNodeRef<T>) {
+        parent.write().unwrap().children.push(child);
+    }
+
+    pub async fn traverse_async<F, Fut>(&self, f: F)
+    where
+        T: Send + 'static,
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        for result in self.traverse_with(f).await {
+            let Ok(()) = result;
+        }
+    }
+
+    /// like [`SyncTree::traverse_async`] for callbacks that can fail, a failing callback does not
+    /// stop the others and the results are returned in traversal order
+    pub async fn traverse_with<F, Fut>(
+        &self,
+        mut f: F,
+    ) -> Vec<Result<(), <Fut::Output as TraverseResult>::Error>>
+    where
+        T: Send + 'static,
+        F: FnMut(T) -> Fut,
+        Fut: Future + Send + 'static,
+        Fut::Output: TraverseResult,
+    {
+        // collect first so no lock and no queue is held across an await
+        let mut values = Vec::new();
+        self.traverse(|value| values.push(value.clone()));
+
+        let handles = values.into_iter().map(|v| task::spawn(f(v))).collect();
+        join_all(handles).await
+    }
+
+    pub fn traverse<F>(&self, mut f: F)
+    where
+        F: FnMut(&T),
+    {
+        let mut q = VecQueue::default();
+        q.push(self.root.clone());
+        while let Some(current) = q.pop() {
+            let node = current.read().unwrap();
+            for child in &node.children {
+                q.push(child.clone());
+            }
+            f(&node.value);
+        }
+    }
+
+    pub fn new(root: SyncTreeNode<T>) -> Self {
+        Self {
+            root: Arc::new(RwLock::new(root)),
+            depth: 1,
+        }
+    }
+}
+
+impl<T: Default + Clone> SyncTreeNode<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            ..Self::default()
+        }
+    }
+}
+
 impl<T: Default + Clone> Queue<T> {
     pub fn push(&mut self, value: T)
     where
@@ -163,6 +433,101 @@ impl<T: Default + Clone> Queue<T> {
     pub fn is_empty(&self) -> bool {
         self.head.is_none() && self.tail.is_none()
     }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// reverses the order of the queue in place by relinking its nodes, the values are not moved
+    pub fn reverse(&mut self) {
+        let mut previous: OptQueueNodeRef<T> = None;
+        let mut current = self.head.take();
+        self.tail = current.clone();
+        while let Some(node) = current {
+            current = node.borrow_mut().next.take();
+            node.borrow_mut().next = previous;
+            previous = Some(node);
+        }
+        self.head = previous;
+    }
+
+    /// pops all values from front to back
+    pub fn drain_to_vec(mut self) -> Vec<T> {
+        let mut values = Vec::with_capacity(self.length);
+        while let Some(value) = self.pop() {
+            values.push(value);
+        }
+        values
+    }
+}
+
+/// a queue is serialized as the sequence of its values from front to back
+impl<T: Default + Clone + Serialize> Serialize for Queue<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.length))?;
+        let mut current = self.head.clone();
+        while let Some(node) = current {
+            current = {
+                let node = node.borrow();
+                seq.serialize_element(&node.value)?;
+                node.next.clone()
+            };
+        }
+        seq.end()
+    }
+}
+
+impl<'de, T: Default + Clone + Deserialize<'de>> Deserialize<'de> for Queue<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut q = Queue::default();
+        for value in Vec::<T>::deserialize(deserializer)? {
+            q.push(value);
+        }
+        Ok(q)
+    }
+}
+
+impl<T> Default for VecQueue<T> {
+    fn default() -> Self {
+        VecQueue {
+            values: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> VecQueue<T> {
+    /// a queue with room for `capacity` values before it has to grow
+    pub fn with_capacity(capacity: usize) -> Self {
+        VecQueue {
+            values: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.values.push_back(value);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.values.pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// reverses the order of the queue in place
+    pub fn reverse(&mut self) {
+        self.values.make_contiguous().reverse();
+    }
+
+    /// pops all values from front to back
+    pub fn drain_to_vec(self) -> Vec<T> {
+        self.values.into()
+    }
 }
 
 impl<T: Default> QueueNode<T> {
@@ -179,9 +544,14 @@ impl<T: Default> QueueNode<T> {
 
 #[cfg(test)]
 mod test {
-    use std::{cell::RefCell, rc::Rc};
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{Arc, RwLock},
+        thread,
+    };
 
-    use super::{Queue, QueueNode, Tree, TreeNode};
+    use super::{Queue, QueueNode, SyncTree, SyncTreeNode, Tree, TreeNode, VecQueue};
 
     #[test]
     fn test_default() {
@@ -224,6 +594,23 @@ mod test {
         assert!(none.is_none());
     }
 
+    #[test]
+    fn test_queue_serde() {
+        let mut q: Queue<usize> = Queue::default();
+        q.push(1);
+        q.push(2);
+        q.push(3);
+        let json = serde_json::to_string(&q).unwrap();
+        assert_eq!(json, "[1,2,3]");
+
+        let mut q: Queue<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(q.length, 3);
+        assert_eq!(q.pop(), Some(1));
+        assert_eq!(q.pop(), Some(2));
+        assert_eq!(q.pop(), Some(3));
+        assert!(q.is_empty());
+    }
+
     #[test]
     fn test_default_tree() {
         let root = TreeNode::new(10);
@@ -231,8 +618,7 @@ mod test {
         assert!(t.root.borrow().value == 10);
     }
 
-    #[test]
-    fn test_queue_traverse() {
+    fn sample_tree() -> Tree<usize> {
         let root = TreeNode::new(10);
         let t: Tree<usize> = Tree::new(root);
 
@@ -255,11 +641,230 @@ mod test {
         Tree::push_node(refc4, refc7);
         Tree::push_node(refc5, refc8.clone());
         Tree::push_node(refc8, refc9);
+        t
+    }
 
+    #[test]
+    fn test_queue_traverse() {
+        let t = sample_tree();
         let nodes = Rc::new(RefCell::new(Vec::new()));
         let clone = nodes.clone();
 
         t.traverse(move |n| clone.borrow_mut().push(*n));
         assert_eq!(nodes.take(), vec![10, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
     }
+
+    #[test]
+    fn test_queue_reverse() {
+        let mut q: Queue<usize> = Queue::default();
+        q.reverse();
+        assert!(q.is_empty());
+
+        for n in [10, 1, 2, 3] {
+            q.push(n);
+        }
+        q.reverse();
+        assert_eq!(q.length, 4);
+        assert_eq!(q.tail.as_ref().unwrap().borrow().value, 10);
+        // the relinked queue still works at both ends
+        q.push(42);
+        assert_eq!(q.drain_to_vec(), [3, 2, 1, 10, 42]);
+    }
+
+    #[test]
+    fn test_vec_queue() {
+        let mut q: VecQueue<usize> = VecQueue::with_capacity(2);
+        assert!(q.is_empty());
+        assert_eq!(q.pop(), None);
+        for n in [10, 1, 2, 3] {
+            q.push(n);
+        }
+        assert_eq!(q.len(), 4);
+        assert_eq!(q.pop(), Some(10));
+        q.push(42);
+        q.reverse();
+        assert_eq!(q.clone().drain_to_vec(), [42, 3, 2, 1]);
+
+        // both queues are saved the same way, crawl states of either can be loaded
+        let json = serde_json::to_string(&q).unwrap();
+        assert_eq!(json, "[42,3,2,1]");
+        let mut linked: Queue<usize> = serde_json::from_str(&json).unwrap();
+        assert_eq!(linked.pop(), Some(42));
+        let q: VecQueue<usize> =
+            serde_json::from_str(&serde_json::to_string(&linked).unwrap()).unwrap();
+        assert_eq!(q.drain_to_vec(), [3, 2, 1]);
+    }
+
+    #[test]
+    fn test_reverse_children() {
+        let mut t = sample_tree();
+        t.reverse_children();
+        let mut nodes = Vec::new();
+        t.traverse(|n| nodes.push(*n));
+        assert_eq!(nodes, [10, 1, 4, 3, 2, 7, 6, 5, 8, 9]);
+    }
+
+    #[test]
+    fn test_traverse_with_depth() {
+        let mut nodes = Vec::new();
+        sample_tree().traverse_with_depth(|n, depth| nodes.push((*n, depth)));
+        assert_eq!(
+            nodes,
+            [
+                (10, 1),
+                (1, 2),
+                (2, 3),
+                (3, 3),
+                (4, 3),
+                (5, 4),
+                (6, 4),
+                (7, 4),
+                (8, 5),
+                (9, 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tree_iter() {
+        let t = sample_tree();
+        let mut depths = Vec::new();
+        t.traverse_with_depth(|n, depth| depths.push((*n, depth)));
+        assert_eq!(t.iter().collect::<Vec<_>>(), depths);
+
+        // the nodes deeper than 3 and the indentation of a listing
+        let deep: Vec<_> = t
+            .iter()
+            .filter(|(_, depth)| *depth > 3)
+            .map(|(n, _)| n)
+            .collect();
+        assert_eq!(deep, [5, 6, 7, 8, 9]);
+        let listing: Vec<_> = (&t)
+            .into_iter()
+            .take(3)
+            .map(|(n, depth)| format!("{}{n}", "  ".repeat(depth - 1)))
+            .collect();
+        assert_eq!(listing, ["10", "  1", "    2"]);
+        assert_eq!(
+            Tree::new(TreeNode::new(1)).iter().collect::<Vec<_>>(),
+            [(1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_leaves() {
+        assert_eq!(sample_tree().leaves(), [3, 6, 7, 9]);
+        assert_eq!(Tree::new(TreeNode::new(1)).leaves(), [1]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let values = |t: &Tree<usize>| {
+            let mut values = Vec::new();
+            t.traverse(|n| values.push(*n));
+            values
+        };
+
+        // 1 is odd, so everything below it goes as well
+        let mut t = sample_tree();
+        t.prune(|n| n % 2 == 0);
+        assert_eq!(values(&t), [10]);
+        assert!(t.root.borrow().children.is_empty());
+
+        let mut t = sample_tree();
+        t.prune(|n| *n != 4 && *n != 8);
+        assert_eq!(values(&t), [10, 1, 2, 3, 5]);
+        assert_eq!(t.leaves(), [3, 5]);
+
+        let mut t = sample_tree();
+        t.prune(|_| true);
+        assert_eq!(values(&t), [10, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_with_parents() {
+        let values = sample_tree().with_parents();
+        assert_eq!(values[..3], [(10, None), (1, Some(10)), (2, Some(1))]);
+        assert_eq!(values.last(), Some(&(9, Some(8))));
+        assert_eq!(values.len(), 10);
+    }
+
+    #[test]
+    fn test_drain_to_vec() {
+        let mut q: Queue<usize> = Queue::default();
+        let t = sample_tree();
+        t.traverse(|n| q.push(*n));
+        assert_eq!(q.drain_to_vec(), [10, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert!(Queue::<usize>::default().drain_to_vec().is_empty());
+    }
+
+    #[test]
+    fn test_sync_tree_shared_between_threads() {
+        let t: SyncTree<usize> = SyncTree::new(SyncTreeNode::new(0));
+
+        thread::scope(|scope| {
+            for i in 1..=4 {
+                let root = t.root.clone();
+                scope.spawn(move || {
+                    let child = Arc::new(RwLock::new(SyncTreeNode::new(i)));
+                    SyncTree::push_node(root, child.clone());
+                    SyncTree::push_node(child, Arc::new(RwLock::new(SyncTreeNode::new(i * 10))));
+                });
+            }
+        });
+
+        let mut nodes = Vec::new();
+        t.traverse(|n| nodes.push(*n));
+        assert_eq!(nodes.len(), 9);
+        assert_eq!(nodes[0], 0);
+        let (children, grandchildren) = nodes[1..].split_at(4);
+        assert!(children.iter().all(|n| *n < 10));
+        assert!(grandchildren.iter().all(|n| *n >= 10));
+    }
+
+    #[tokio::test]
+    async fn test_sync_tree_traverse_async() {
+        let t: SyncTree<usize> = SyncTree::new(SyncTreeNode::new(1));
+        SyncTree::push_node(t.root.clone(), Arc::new(RwLock::new(SyncTreeNode::new(2))));
+        SyncTree::push_node(t.root.clone(), Arc::new(RwLock::new(SyncTreeNode::new(3))));
+
+        let sum = Arc::new(RwLock::new(0));
+        let clone = sum.clone();
+        // the traversal itself has to be Send to be spawned onto the runtime
+        tokio::spawn(async move {
+            t.traverse_async(move |n| {
+                let sum = clone.clone();
+                async move { *sum.write().unwrap() += n }
+            })
+            .await
+        })
+        .await
+        .unwrap();
+        assert_eq!(*sum.read().unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_traverse_with() {
+        let t: Tree<usize> = Tree::new(TreeNode::new(1));
+        for i in 2..=4 {
+            Tree::push_node(t.root.clone(), Rc::new(RefCell::new(TreeNode::new(i))));
+        }
+
+        let visited = Arc::new(RwLock::new(Vec::new()));
+        let clone = visited.clone();
+        let result = t
+            .traverse_with(move |n| {
+                let visited = clone.clone();
+                async move {
+                    visited.write().unwrap().push(n);
+                    if n % 2 == 0 { Err(n) } else { Ok(()) }
+                }
+            })
+            .await;
+        // a failing callback does not stop the others
+        assert_eq!(result, [Ok(()), Err(2), Ok(()), Err(4)]);
+        let mut visited = visited.read().unwrap().clone();
+        visited.sort();
+        assert_eq!(visited, [1, 2, 3, 4]);
+    }
 }
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, LineGauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use rget::client::HttpClient;
+use rget::download::{DownloadOptions, unique_name};
+use rget::error::RgetError;
+use rget::urls::derived_file_name;
+
+use crate::ReplCommand;
+use crate::jobs::{Job, Jobs, Status};
+
+/// how often the dashboard is redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+const TUI_HELP: &str = "type <url> [outfile] and press enter to download, :pause | pause <id> or :resume | resume <id>, q or esc quits";
+
+/// the color of the bar of a job
+fn color(job: &Job) -> Color {
+    match job.status {
+        Status::Running(_) => Color::Yellow,
+        Status::Paused => Color::Blue,
+        Status::Done => Color::Green,
+        Status::Failed(_) => Color::Red,
+    }
+}
+
+#[derive(Default)]
+struct App {
+    jobs: Jobs,
+    input: String,
+    message: Option<String>,
+    /// the outfiles of the session, a new download never writes the file of another one
+    outfiles: HashSet<String>,
+}
+
+impl App {
+    /// handles an entered line, false if it asks to quit
+    fn submit(&mut self, line: &str, client: &HttpClient, options: &DownloadOptions) -> bool {
+        self.message = None;
+        match ReplCommand::parse(line) {
+            ReplCommand::Quit => return false,
+            ReplCommand::Empty => {}
+            ReplCommand::Download { url, outfile } => {
+                let outfile =
+                    outfile.unwrap_or_else(|| derived_file_name(&url, options.decode_query));
+                let outfile = unique_name(outfile, &mut self.outfiles);
+                self.jobs.start(url, outfile, client, options);
+            }
+            ReplCommand::Pause(id) => self.message = self.jobs.pause(id).err(),
+            ReplCommand::Resume(id) => self.message = self.jobs.resume(id, client, options).err(),
+            ReplCommand::Invalid(msg) => self.message = Some(msg),
+            ReplCommand::Help
+            | ReplCommand::SetOutfile(_)
+            | ReplCommand::Depth { .. }
+            | ReplCommand::Jobs
+            | ReplCommand::Wait => {
+                self.message = Some(format!("only downloads are supported here, {TUI_HELP}"));
+            }
+        }
+        true
+    }
+
+    /// true if any download finished since the last call
+    async fn collect_finished(&mut self) -> bool {
+        !self.jobs.collect_finished().await.is_empty()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [jobs_area, input_area, help_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let block = Block::bordered().title(" downloads ");
+        let inner = block.inner(jobs_area);
+        frame.render_widget(block, jobs_area);
+        // the newest downloads that fit
+        let jobs = &self.jobs.jobs;
+        let visible = &jobs[jobs.len().saturating_sub(inner.height as usize)..];
+        for (i, job) in visible.iter().enumerate() {
+            let (ratio, label) = job.progress();
+            let gauge = LineGauge::default()
+                .ratio(ratio)
+                .label(format!("[{}] {label}", job.id))
+                .filled_style(Style::default().fg(color(job)));
+            let row = Rect {
+                y: inner.y + i as u16,
+                height: 1,
+                ..inner
+            };
+            frame.render_widget(gauge, row);
+        }
+
+        let input = Paragraph::new(self.input.as_str()).block(Block::bordered().title(" url "));
+        frame.render_widget(input, input_area);
+        frame.set_cursor_position((
+            input_area.x + 1 + self.input.chars().count() as u16,
+            input_area.y + 1,
+        ));
+        let help = self.message.as_deref().unwrap_or(TUI_HELP);
+        frame.render_widget(Paragraph::new(help), help_area);
+    }
+}
+
+/// a dashboard of the downloads of the session, urls typed at the bottom are downloaded in the
+/// background while their progress is shown above
+pub async fn run(client: &HttpClient, options: &DownloadOptions) -> Result<(), RgetError> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, client, options).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    client: &HttpClient,
+    options: &DownloadOptions,
+) -> Result<(), RgetError> {
+    let mut app = App::default();
+    loop {
+        // the messages download prints end up somewhere on the screen, a full redraw removes them
+        if app.collect_finished().await {
+            terminal.clear()?;
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+
+        // waiting for a key blocks, the downloads keep running on the other threads
+        if !tokio::task::block_in_place(|| event::poll(TICK))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyCode::Char('q') if app.input.is_empty() => break,
+            KeyCode::Esc => break,
+            KeyCode::Char(c) => app.input.push(c),
+            KeyCode::Backspace => {
+                app.input.pop();
+            }
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut app.input);
+                if !app.submit(&line, client, options) {
+                    break;
+                }
+                terminal.clear()?;
+            }
+            _ => {}
+        }
+    }
+
+    // like an interrupt, unfinished downloads are kept as .part files
+    app.jobs.abort();
+    Ok(())
+}
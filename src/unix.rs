@@ -0,0 +1,310 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use bytes::Bytes;
+use http::header::{
+    AUTHORIZATION, CONTENT_TYPE, COOKIE, HOST, LOCATION, RANGE, REFERER, USER_AGENT,
+};
+use http::{HeaderValue, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use sha2::{Digest as _, Sha256};
+use tokio::net::UnixStream;
+use url::{Position, Url};
+
+use crate::client::{HttpClient, RATE_LIMIT_RETRIES, rate_limit_wait};
+use crate::content_type::ContentType;
+use crate::download::{
+    DownloadOptions, FileWriter, check_filesize, finish_file, header_content_length, part_file,
+    print_response, resolve_outfile, with_extension,
+};
+use crate::error::RgetError;
+use crate::progress::DownloadProgress;
+
+/// downloads `url` over the unix socket at `socket` instead of connecting to its host, the host of
+/// the url is still sent as the Host header
+///
+/// the cookies, the netrc login and the rate limit retries of the client apply like to any other
+/// request. Redirects are not followed, they fail the download, and the body is saved the way the
+/// server sends it. Returns the number of bytes written
+pub(crate) async fn download(
+    client: &HttpClient,
+    socket: &Path,
+    url: &str,
+    outfile: &str,
+    options: &DownloadOptions,
+) -> Result<u64, RgetError> {
+    let parsed = Url::parse(url).map_err(|e| RgetError::InvalidUrl {
+        url: url.to_string(),
+        reason: e.to_string(),
+    })?;
+
+    let mut authorization = None;
+    let mut retries = 0;
+    let response = loop {
+        let response = send(client, socket, &parsed, authorization.as_ref(), options).await?;
+        client.keep_cookies(&parsed, response.headers());
+        let status = response.status();
+        // a login is only sent once, the second 401 means it is wrong
+        if status == StatusCode::UNAUTHORIZED
+            && authorization.is_none()
+            && let Some(login) = parsed.host_str().and_then(|host| client.netrc_login(host))
+        {
+            authorization = Some(login);
+            continue;
+        }
+        match rate_limit_wait(status, response.headers()) {
+            Some(wait) if retries < RATE_LIMIT_RETRIES => {
+                options.suspend(|| {
+                    eprintln!("{url} answered {status}, retrying in {}s", wait.as_secs())
+                });
+                tokio::time::sleep(wait).await;
+                retries += 1;
+            }
+            _ => break response,
+        }
+    };
+    let status = response.status();
+    print_response(options, url, response.version(), status);
+    if status.is_redirection() {
+        let location = response.headers().get(LOCATION);
+        return Err(RgetError::UnixRedirect {
+            url: url.to_string(),
+            location: location
+                .and_then(|location| location.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        });
+    }
+    if !status.is_success() {
+        return Err(RgetError::Status {
+            url: url.to_string(),
+            status,
+        });
+    }
+    if options.byte_range.is_some() && status != StatusCode::PARTIAL_CONTENT {
+        return Err(RgetError::RangeNotSatisfied {
+            url: url.to_string(),
+            status,
+        });
+    }
+    let total_size = header_content_length(response.headers());
+    if let Some(ts) = total_size {
+        check_filesize(url, ts, options.max_filesize)?;
+    }
+    let content_type = ContentType::from_header_value(response.headers().get(CONTENT_TYPE));
+    let outfile = &with_extension(outfile, &content_type, options);
+    let outfile = &resolve_outfile(outfile, options);
+
+    let pb = DownloadProgress::new(url, total_size, options);
+    let part = part_file(outfile);
+    let mut dest = FileWriter::new(File::create(&part)?, options.compression());
+    let mut body = response.into_body();
+    let mut downloaded: u64 = 0;
+    let mut hasher = Sha256::new();
+    while let Some(frame) = body.frame().await {
+        // trailers are not part of the file
+        let Ok(chunk) = frame?.into_data() else {
+            continue;
+        };
+        dest.write_all(&chunk)?;
+        hasher.update(&chunk);
+        downloaded += chunk.len() as u64;
+        if let Err(e) = check_filesize(url, downloaded, options.max_filesize) {
+            drop(dest);
+            std::fs::remove_file(&part)?;
+            pb.abandon();
+            return Err(e);
+        }
+        pb.inc(chunk.len() as u64);
+    }
+    dest.finish()?;
+    if let Some(expected) = total_size.filter(|&ts| ts != downloaded) {
+        pb.abandon();
+        return Err(RgetError::SizeMismatch {
+            url: url.to_string(),
+            expected,
+            actual: downloaded,
+        });
+    }
+    std::fs::rename(&part, outfile)?;
+    pb.finish(outfile);
+
+    let digest = hasher.finalize().into();
+    finish_file(options, url, outfile, Some(digest), None).await?;
+    Ok(downloaded)
+}
+
+/// sends the request for `url` over a new connection to `socket`, with `authorization` when the
+/// server asked for a login
+async fn send(
+    client: &HttpClient,
+    socket: &Path,
+    url: &Url,
+    authorization: Option<&HeaderValue>,
+    options: &DownloadOptions,
+) -> Result<Response<Incoming>, RgetError> {
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => "localhost".to_string(),
+    };
+
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|source| RgetError::UnixSocket {
+            path: socket.to_path_buf(),
+            source,
+        })?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    // the connection is driven on its own task, its errors show up in the response
+    tokio::spawn(connection);
+
+    let mut request = Request::builder()
+        .method(options.method.clone())
+        .uri(&url[Position::BeforePath..Position::AfterQuery])
+        .header(HOST, host)
+        .header(USER_AGENT, client.user_agent());
+    if let Some(cookie) = client.cookie_header(url) {
+        request = request.header(COOKIE, cookie);
+    }
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    if let Some(referer) = &options.referer {
+        request = request.header(REFERER, referer);
+    }
+    if let Some(range) = &options.byte_range {
+        request = request.header(RANGE, range.header());
+    }
+    let body = Full::new(Bytes::from(options.body.clone().unwrap_or_default()));
+    let request = request
+        .body(body)
+        .map_err(|e| RgetError::Config(e.to_string()))?;
+    Ok(sender.send_request(request).await?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+    use tokio::task::JoinHandle;
+
+    use super::download;
+    use crate::client::{ClientArgs, HttpClient};
+    use crate::download::DownloadOptions;
+    use crate::error::RgetError;
+
+    /// answers one request after the other on the socket `name` in a new directory with
+    /// `responses` and returns the requests it got
+    fn serve(name: &str, responses: Vec<String>) -> (PathBuf, JoinHandle<Vec<String>>) {
+        let dir = std::env::temp_dir().join(format!("rget-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let listener = UnixListener::bind(dir.join("api.sock")).unwrap();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                }
+                requests.push(String::from_utf8(request).unwrap().to_lowercase());
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (dir, server)
+    }
+
+    fn ok(headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n{headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_download_unix_socket() {
+        let not_found = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n".to_string();
+        let (dir, server) = serve("unix", vec![ok("", "hello"), not_found]);
+        let socket = dir.join("api.sock");
+        let client = HttpClient::new(&ClientArgs::default(), &[]).unwrap();
+        let options = DownloadOptions::default();
+        let outfile = dir.join("out").to_string_lossy().to_string();
+
+        let url = "http://api.local/v1/file?x=1";
+        let written = download(&client, &socket, url, &outfile, &options)
+            .await
+            .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(fs::read(&outfile).unwrap(), b"hello");
+        let missing = download(&client, &socket, url, &outfile, &options).await;
+        assert!(matches!(missing, Err(RgetError::Status { .. })));
+
+        let requests = server.await.unwrap();
+        assert!(requests[0].starts_with("get /v1/file?x=1 http/1.1\r\n"));
+        assert!(requests[0].contains("host: api.local\r\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_client() {
+        let unauthorized = "HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n";
+        let moved =
+            "HTTP/1.1 301 Moved Permanently\r\nlocation: /new\r\ncontent-length: 4\r\n\r\ngone";
+        let (dir, server) = serve(
+            "unix-client",
+            vec![
+                unauthorized.to_string(),
+                ok("set-cookie: session=1\r\n", "secret"),
+                moved.to_string(),
+            ],
+        );
+        let socket = dir.join("api.sock");
+        let netrc = dir.join("netrc");
+        fs::write(&netrc, "machine api.local login user password pass\n").unwrap();
+        let args = ClientArgs {
+            netrc_file: Some(netrc),
+            ..ClientArgs::default()
+        };
+        let client = HttpClient::new(&args, &[]).unwrap();
+        let options = DownloadOptions::default();
+        let outfile = dir.join("out").to_string_lossy().to_string();
+
+        // the login of the netrc file is sent once the server asks for it
+        download(
+            &client,
+            &socket,
+            "http://api.local/private",
+            &outfile,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&outfile).unwrap(), b"secret");
+
+        // a redirect is an error instead of a saved body
+        let moved = download(&client, &socket, "http://api.local/old", &outfile, &options).await;
+        assert!(
+            matches!(&moved, Err(RgetError::UnixRedirect { location, .. }) if location == "/new"),
+            "{moved:?}"
+        );
+        assert_eq!(fs::read(&outfile).unwrap(), b"secret");
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("authorization"));
+        assert!(requests[1].contains("authorization: basic dxnlcjpwyxnz\r\n"));
+        // the cookie the server set is sent back
+        assert!(requests[2].contains("cookie: session=1\r\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
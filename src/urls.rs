@@ -0,0 +1,414 @@
+use sha2::{Digest, Sha256};
+use url::{Host, Url};
+
+use crate::error::RgetError;
+
+/// checks that `input` is a http(s) or ftp url, `https://` is prepended when it has no scheme
+///
+/// without a scheme the host has to look like one, so a stray word is not taken for a url
+pub fn normalize_url(input: &str) -> Result<String, RgetError> {
+    let input = input.trim();
+    let invalid = |reason: &str| RgetError::InvalidUrl {
+        url: input.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let has_scheme = input.contains("://");
+    let url = if has_scheme {
+        Url::parse(input)
+    } else {
+        Url::parse(&format!("https://{input}"))
+    }
+    .map_err(|e| invalid(&e.to_string()))?;
+
+    if !matches!(url.scheme(), "http" | "https" | "ftp") {
+        return Err(invalid(&format!("unsupported scheme {}", url.scheme())));
+    }
+    match url.host() {
+        None => Err(invalid("missing host")),
+        Some(Host::Domain(domain))
+            if !has_scheme && !domain.contains('.') && domain != "localhost" =>
+        {
+            Err(invalid("not a host name"))
+        }
+        Some(_) => Ok(url.to_string()),
+    }
+}
+
+/// the extension of the last segment of the url path
+pub(crate) fn url_extension(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    let (_, ext) = name.rsplit_once('.')?;
+    (!ext.is_empty()).then(|| ext.to_string())
+}
+
+/// whether `url` is in the directory of `root` or below it, urls of other sites are never below
+///
+/// the directory of `https://site/docs/guide/` is the url itself and the one of
+/// `https://site/docs/guide/index.html` is `https://site/docs/guide/`
+pub(crate) fn is_below(root: &str, url: &str) -> bool {
+    let (Ok(root), Ok(url)) = (Url::parse(root), Url::parse(url)) else {
+        return false;
+    };
+    let dir = match root.path().rfind('/') {
+        Some(end) => &root.path()[..=end],
+        None => "/",
+    };
+    root.origin() == url.origin() && url.path().starts_with(dir)
+}
+
+/// a hash of the url that stays the same across runs and rust versions
+pub(crate) fn url_hash(url: &str) -> String {
+    let digest = Sha256::digest(url.as_bytes());
+    digest[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// a file name that stays the same for the same url across runs and rust versions, the extension
+/// of the url is kept so the file can still be opened
+pub fn hash_file_name(url: &str) -> String {
+    let hash = url_hash(url);
+    match url_extension(url) {
+        Some(ext) if ext.len() <= 10 && ext.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            format!("{hash}.{ext}")
+        }
+        _ => hash,
+    }
+}
+
+/// a file name for the download of `url` taken from the last segment of its path
+pub fn file_name_from_url(url: &str) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return hash_file_name(url);
+    };
+    // e.g. `data:` urls have no path to take a name from
+    if parsed.cannot_be_a_base() {
+        return hash_file_name(url);
+    }
+    match parsed.path_segments().and_then(|mut s| s.next_back()) {
+        Some(name) if !name.is_empty() && name != "." && name != ".." => name.to_string(),
+        _ => "index.html".to_string(),
+    }
+}
+
+/// the query parameters that name the file of a dynamic download endpoint, the most specific first
+const NAME_PARAMETERS: [&str; 3] = ["filename", "file", "name"];
+
+/// a file name for the download of `url` taken from the `filename`, `file` or `name` parameter of
+/// its query, e.g. `report.pdf` for `download.php?file=report.pdf`. Only the last segment of a
+/// path in the value is kept
+pub fn file_name_from_query(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    NAME_PARAMETERS.iter().find_map(|key| {
+        let (_, value) = parsed
+            .query_pairs()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))?;
+        let name = value.rsplit(['/', '\\']).next()?.trim();
+        let valid = !matches!(name, "" | "." | "..") && !name.chars().any(char::is_control);
+        valid.then(|| name.to_string())
+    })
+}
+
+/// the file name of `url` from its query with `decode_query` if it has one there, from its path
+/// otherwise
+pub fn derived_file_name(url: &str, decode_query: bool) -> String {
+    decode_query
+        .then(|| file_name_from_query(url))
+        .flatten()
+        .unwrap_or_else(|| file_name_from_url(url))
+}
+
+/// a relative path for the download of `url` that mirrors the site as `host[:port]/path`
+///
+/// a path ending in `/` is saved as `index.html` in that directory, a query string stays part of
+/// the file name as `name?query` with `/` escaped as `%2F`
+pub fn tree_path_from_url(url: &str) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return hash_file_name(url);
+    };
+    let Some(host) = parsed.host_str() else {
+        return hash_file_name(url);
+    };
+    let mut path = match parsed.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    };
+    path.push('/');
+    path.push_str(&relative_path(&parsed, |segment| segment.to_string()));
+    if let Some(query) = parsed.query() {
+        path.push('?');
+        path.push_str(&query.replace('/', "%2F"));
+    }
+    path
+}
+
+/// the path of the url without its leading `/` and with every segment passed through `segment`,
+/// empty segments, `.` and `..` are dropped and a path ending in `/` gets an `index.html`
+fn relative_path(url: &Url, segment: impl Fn(&str) -> String) -> String {
+    let segments: Vec<&str> = url.path_segments().into_iter().flatten().collect();
+    let (name, dirs) = segments.split_last().unwrap_or((&"", &[]));
+    let mut path = String::new();
+    for dir in dirs {
+        if !dir.is_empty() && *dir != "." && *dir != ".." {
+            path.push_str(&segment(dir));
+            path.push('/');
+        }
+    }
+    match *name {
+        "" | "." | ".." => path.push_str("index.html"),
+        name => path.push_str(&segment(name)),
+    }
+    path
+}
+
+/// the fields an [`OutputTemplate`] can contain
+const TEMPLATE_FIELDS: [&str; 6] = ["host", "path", "basename", "ext", "hash", "index"];
+
+/// a file name built from the parts of a url, e.g. `{host}/{basename}-{index}.{ext}`
+///
+/// `{host}` is the host of the url, `{path}` its path with the directories below the host and
+/// `index.html` for a path ending in `/`, `{basename}` and `{ext}` the last segment of that path
+/// without and only its extension, `{hash}` a hash of the url and `{index}` the position of the
+/// url in the run counted from 1. Characters that are not allowed in file names are replaced by
+/// `_`, only `/` in the template itself and in `{path}` create directories
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTemplate(String);
+
+impl OutputTemplate {
+    /// checks that every `{field}` of the template is known and every brace is closed
+    pub fn parse(template: &str) -> Result<Self, String> {
+        if template.trim().is_empty() {
+            return Err("the output template is empty".to_string());
+        }
+        render(template, |field| match TEMPLATE_FIELDS.contains(&field) {
+            true => Ok(String::new()),
+            false => Err(format!(
+                "unknown field {{{field}}}, expected one of {}",
+                TEMPLATE_FIELDS
+                    .map(|field| format!("{{{field}}}"))
+                    .join(", ")
+            )),
+        })?;
+        Ok(OutputTemplate(template.to_string()))
+    }
+
+    /// the file name of `url`, the `index`-th url of the run
+    pub fn file_name(&self, url: &str, index: usize) -> String {
+        let parsed = Url::parse(url).ok().filter(|url| !url.cannot_be_a_base());
+        let path = match &parsed {
+            Some(parsed) => relative_path(parsed, safe_file_name),
+            // e.g. a `data:` url has no path
+            None => hash_file_name(url),
+        };
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let (basename, ext) = match name.rsplit_once('.') {
+            Some((basename, ext)) if !basename.is_empty() => (basename, ext),
+            _ => (name, ""),
+        };
+        let host = parsed.as_ref().and_then(Url::host_str).unwrap_or_default();
+
+        let name = render(&self.0, |field| {
+            Ok(match field {
+                "host" => safe_file_name(host),
+                "path" => path.clone(),
+                "basename" => basename.to_string(),
+                "ext" => ext.to_string(),
+                "hash" => url_hash(url),
+                "index" => index.to_string(),
+                _ => unreachable!("the fields were checked when parsing the template"),
+            })
+        })
+        .expect("the template was checked when parsing it");
+        if name.trim_matches('/').is_empty() {
+            return hash_file_name(url);
+        }
+        name
+    }
+}
+
+/// the template with every `{field}` replaced by what `field` returns for it
+fn render(
+    template: &str,
+    mut field: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        let (text, tail) = rest.split_at(start);
+        name.push_str(text);
+        let end = tail
+            .find('}')
+            .filter(|_| tail.starts_with('{'))
+            .ok_or_else(|| format!("unmatched brace in the output template {template:?}"))?;
+        name.push_str(&field(&tail[1..end])?);
+        rest = &tail[end + 1..];
+    }
+    name.push_str(rest);
+    Ok(name)
+}
+
+/// `name` with the characters that are not allowed in file names on some system replaced by `_`
+fn safe_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        OutputTemplate, derived_file_name, file_name_from_query, hash_file_name, is_below,
+        normalize_url, tree_path_from_url,
+    };
+
+    #[test]
+    fn test_hash_file_name() {
+        assert_eq!(
+            hash_file_name("http://localhost:3000/logo.png"),
+            "d6d61ecb0b88bac8.png"
+        );
+        assert_eq!(hash_file_name("http://localhost:3000/"), "f960fc983a2bc719");
+        assert!(!hash_file_name("http://localhost:3000/a.b%2Fc").contains('%'));
+    }
+
+    #[test]
+    fn test_file_name_from_query() {
+        let name = |url: &str| file_name_from_query(url);
+        assert_eq!(
+            name("http://a/download.php?file=report.pdf").as_deref(),
+            Some("report.pdf")
+        );
+        // the most specific parameter wins and values are decoded
+        assert_eq!(
+            name("http://a/get?name=x&FileName=my%20notes.txt").as_deref(),
+            Some("my notes.txt")
+        );
+        assert_eq!(
+            name("http://a/get?file=..%2F..%2Fetc%2Fpasswd").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(
+            name("http://a/get?file=dir/&name=b.zip").as_deref(),
+            Some("b.zip")
+        );
+        assert_eq!(name("http://a/get?file=..").as_deref(), None);
+        assert_eq!(name("http://a/get?id=3").as_deref(), None);
+        assert_eq!(name("not a url"), None);
+
+        assert_eq!(
+            derived_file_name("http://a/get.php?file=r.pdf", true),
+            "r.pdf"
+        );
+        assert_eq!(
+            derived_file_name("http://a/get.php?file=r.pdf", false),
+            "get.php"
+        );
+        assert_eq!(derived_file_name("http://a/get.php?id=3", true), "get.php");
+    }
+
+    #[test]
+    fn test_tree_path_from_url() {
+        assert_eq!(
+            tree_path_from_url("http://example.com/a/b.html"),
+            "example.com/a/b.html"
+        );
+        assert_eq!(
+            tree_path_from_url("http://localhost:3000/docs/"),
+            "localhost:3000/docs/index.html"
+        );
+        assert_eq!(
+            tree_path_from_url("http://example.com"),
+            "example.com/index.html"
+        );
+        assert_eq!(
+            tree_path_from_url("http://example.com//a/page?next=/b&c=d"),
+            "example.com/a/page?next=%2Fb&c=d"
+        );
+    }
+
+    #[test]
+    fn test_output_template() {
+        let name = |template, url, index| {
+            OutputTemplate::parse(template)
+                .unwrap()
+                .file_name(url, index)
+        };
+        let url = "http://example.com:8080/docs/a/report.tar.gz?x=1";
+        assert_eq!(
+            name("{host}/{path}", url, 1),
+            "example.com/docs/a/report.tar.gz"
+        );
+        assert_eq!(name("{index}-{basename}.{ext}", url, 3), "3-report.tar.gz");
+        assert_eq!(name("{basename}", "http://example.com/", 1), "index");
+        assert_eq!(
+            name("{ext}", "http://example.com/.hidden", 1),
+            hash_file_name("http://example.com/.hidden")
+        );
+        assert_eq!(
+            name("{hash}.{ext}", "http://localhost:3000/logo.png", 1),
+            "d6d61ecb0b88bac8.png"
+        );
+        assert_eq!(
+            name("{basename}", "http://example.com/a%3Ab/c:d|e", 1),
+            "c_d_e"
+        );
+        assert_eq!(name("{path}", "http://example.com/../x/./y", 1), "x/y");
+
+        assert!(OutputTemplate::parse("{name}").is_err());
+        assert!(OutputTemplate::parse("{host").is_err());
+        assert!(OutputTemplate::parse("host}").is_err());
+        assert!(OutputTemplate::parse(" ").is_err());
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let normalized = |url| normalize_url(url).ok();
+        assert_eq!(
+            normalized("example.com"),
+            Some("https://example.com/".to_string())
+        );
+        assert_eq!(
+            normalized(" http://Example.com/a?b=c "),
+            Some("http://example.com/a?b=c".to_string())
+        );
+        assert_eq!(
+            normalized("localhost:3000/page"),
+            Some("https://localhost:3000/page".to_string())
+        );
+        assert_eq!(
+            normalized("127.0.0.1/x"),
+            Some("https://127.0.0.1/x".to_string())
+        );
+        assert_eq!(normalized("hello"), None);
+        assert_eq!(
+            normalized("ftp://example.com/file"),
+            Some("ftp://example.com/file".to_string())
+        );
+        assert_eq!(normalized("file:///etc/passwd"), None);
+        assert_eq!(normalized("http://"), None);
+        assert_eq!(normalized(""), None);
+    }
+
+    #[test]
+    fn test_is_below() {
+        let root = "https://site/docs/guide/";
+        assert!(is_below(root, "https://site/docs/guide/"));
+        assert!(is_below(
+            root,
+            "https://site/docs/guide/install/linux.html?v=2"
+        ));
+        assert!(!is_below(root, "https://site/docs/"));
+        assert!(!is_below(root, "https://site/docs/api/"));
+        assert!(!is_below(root, "https://site/docs/guidebook"));
+        assert!(!is_below(root, "http://site/docs/guide/a"));
+        assert!(!is_below(root, "https://other/docs/guide/a"));
+        // the page of the root is in its directory
+        let root = "https://site/docs/guide/index.html";
+        assert!(is_below(root, "https://site/docs/guide/setup.html"));
+        assert!(!is_below(root, "https://site/docs/other.html"));
+    }
+}